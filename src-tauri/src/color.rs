@@ -0,0 +1,336 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+/// An sRGB color with alpha, parsed from CSS syntax.
+///
+/// Always rendered as hex (`#rrggbb` or `#rrggbbaa`) when passed to GEGL: some
+/// CSS names (pink, orange, cyan, magenta, brown…) come back wrong from
+/// `Gegl.Color.new`, and hex can't smuggle quotes into the generated Python.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+/// The CSS Color Module Level 4 named colors.
+const NAMED_COLORS: &[(&str, u32)] = &[
+    ("aliceblue", 0xF0F8FF), ("antiquewhite", 0xFAEBD7), ("aqua", 0x00FFFF),
+    ("aquamarine", 0x7FFFD4), ("azure", 0xF0FFFF), ("beige", 0xF5F5DC),
+    ("bisque", 0xFFE4C4), ("black", 0x000000), ("blanchedalmond", 0xFFEBCD),
+    ("blue", 0x0000FF), ("blueviolet", 0x8A2BE2), ("brown", 0xA52A2A),
+    ("burlywood", 0xDEB887), ("cadetblue", 0x5F9EA0), ("chartreuse", 0x7FFF00),
+    ("chocolate", 0xD2691E), ("coral", 0xFF7F50), ("cornflowerblue", 0x6495ED),
+    ("cornsilk", 0xFFF8DC), ("crimson", 0xDC143C), ("cyan", 0x00FFFF),
+    ("darkblue", 0x00008B), ("darkcyan", 0x008B8B), ("darkgoldenrod", 0xB8860B),
+    ("darkgray", 0xA9A9A9), ("darkgreen", 0x006400), ("darkgrey", 0xA9A9A9),
+    ("darkkhaki", 0xBDB76B), ("darkmagenta", 0x8B008B), ("darkolivegreen", 0x556B2F),
+    ("darkorange", 0xFF8C00), ("darkorchid", 0x9932CC), ("darkred", 0x8B0000),
+    ("darksalmon", 0xE9967A), ("darkseagreen", 0x8FBC8F), ("darkslateblue", 0x483D8B),
+    ("darkslategray", 0x2F4F4F), ("darkslategrey", 0x2F4F4F), ("darkturquoise", 0x00CED1),
+    ("darkviolet", 0x9400D3), ("deeppink", 0xFF1493), ("deepskyblue", 0x00BFFF),
+    ("dimgray", 0x696969), ("dimgrey", 0x696969), ("dodgerblue", 0x1E90FF),
+    ("firebrick", 0xB22222), ("floralwhite", 0xFFFAF0), ("forestgreen", 0x228B22),
+    ("fuchsia", 0xFF00FF), ("gainsboro", 0xDCDCDC), ("ghostwhite", 0xF8F8FF),
+    ("gold", 0xFFD700), ("goldenrod", 0xDAA520), ("gray", 0x808080),
+    ("green", 0x008000), ("greenyellow", 0xADFF2F), ("grey", 0x808080),
+    ("honeydew", 0xF0FFF0), ("hotpink", 0xFF69B4), ("indianred", 0xCD5C5C),
+    ("indigo", 0x4B0082), ("ivory", 0xFFFFF0), ("khaki", 0xF0E68C),
+    ("lavender", 0xE6E6FA), ("lavenderblush", 0xFFF0F5), ("lawngreen", 0x7CFC00),
+    ("lemonchiffon", 0xFFFACD), ("lightblue", 0xADD8E6), ("lightcoral", 0xF08080),
+    ("lightcyan", 0xE0FFFF), ("lightgoldenrodyellow", 0xFAFAD2), ("lightgray", 0xD3D3D3),
+    ("lightgreen", 0x90EE90), ("lightgrey", 0xD3D3D3), ("lightpink", 0xFFB6C1),
+    ("lightsalmon", 0xFFA07A), ("lightseagreen", 0x20B2AA), ("lightskyblue", 0x87CEFA),
+    ("lightslategray", 0x778899), ("lightslategrey", 0x778899), ("lightsteelblue", 0xB0C4DE),
+    ("lightyellow", 0xFFFFE0), ("lime", 0x00FF00), ("limegreen", 0x32CD32),
+    ("linen", 0xFAF0E6), ("magenta", 0xFF00FF), ("maroon", 0x800000),
+    ("mediumaquamarine", 0x66CDAA), ("mediumblue", 0x0000CD), ("mediumorchid", 0xBA55D3),
+    ("mediumpurple", 0x9370DB), ("mediumseagreen", 0x3CB371), ("mediumslateblue", 0x7B68EE),
+    ("mediumspringgreen", 0x00FA9A), ("mediumturquoise", 0x48D1CC), ("mediumvioletred", 0xC71585),
+    ("midnightblue", 0x191970), ("mintcream", 0xF5FFFA), ("mistyrose", 0xFFE4E1),
+    ("moccasin", 0xFFE4B5), ("navajowhite", 0xFFDEAD), ("navy", 0x000080),
+    ("oldlace", 0xFDF5E6), ("olive", 0x808000), ("olivedrab", 0x6B8E23),
+    ("orange", 0xFFA500), ("orangered", 0xFF4500), ("orchid", 0xDA70D6),
+    ("palegoldenrod", 0xEEE8AA), ("palegreen", 0x98FB98), ("paleturquoise", 0xAFEEEE),
+    ("palevioletred", 0xDB7093), ("papayawhip", 0xFFEFD5), ("peachpuff", 0xFFDAB9),
+    ("peru", 0xCD853F), ("pink", 0xFFC0CB), ("plum", 0xDDA0DD),
+    ("powderblue", 0xB0E0E6), ("purple", 0x800080), ("rebeccapurple", 0x663399),
+    ("red", 0xFF0000), ("rosybrown", 0xBC8F8F), ("royalblue", 0x4169E1),
+    ("saddlebrown", 0x8B4513), ("salmon", 0xFA8072), ("sandybrown", 0xF4A460),
+    ("seagreen", 0x2E8B57), ("seashell", 0xFFF5EE), ("sienna", 0xA0522D),
+    ("silver", 0xC0C0C0), ("skyblue", 0x87CEEB), ("slateblue", 0x6A5ACD),
+    ("slategray", 0x708090), ("slategrey", 0x708090), ("snow", 0xFFFAFA),
+    ("springgreen", 0x00FF7F), ("steelblue", 0x4682B4), ("tan", 0xD2B48C),
+    ("teal", 0x008080), ("thistle", 0xD8BFD8), ("tomato", 0xFF6347),
+    ("turquoise", 0x40E0D0), ("violet", 0xEE82EE), ("wheat", 0xF5DEB3),
+    ("white", 0xFFFFFF), ("whitesmoke", 0xF5F5F5), ("yellow", 0xFFFF00),
+    ("yellowgreen", 0x9ACD32),
+];
+
+/// "-ish" forms people use in prompts ("reddish-brown", "bluish grey").
+const ISH_FORMS: &[(&str, &str)] = &[
+    ("reddish", "red"), ("bluish", "blue"), ("blueish", "blue"), ("greenish", "green"),
+    ("yellowish", "yellow"), ("purplish", "purple"), ("pinkish", "pink"),
+    ("orangish", "orange"), ("orangey", "orange"), ("greyish", "grey"), ("grayish", "gray"),
+    ("brownish", "brown"), ("blackish", "black"), ("whitish", "white"),
+];
+
+impl Color {
+    pub const BLACK: Color = Color { r: 0, g: 0, b: 0, a: 255 };
+    pub const WHITE: Color = Color { r: 255, g: 255, b: 255, a: 255 };
+    /// What lines, shapes and text are drawn in when a request names no
+    /// colour, in plans and in the quick drawing commands alike.
+    pub const DEFAULT_INK: Color = Color::BLACK;
+
+    pub fn rgb(r: u8, g: u8, b: u8) -> Color {
+        Color { r, g, b, a: 255 }
+    }
+
+    /// A CSS named color ("transparent" included), case-insensitive.
+    pub fn named(name: &str) -> Option<Color> {
+        let name = name.trim().to_ascii_lowercase();
+        if name == "transparent" {
+            return Some(Color { r: 0, g: 0, b: 0, a: 0 });
+        }
+        NAMED_COLORS
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, hex)| Color::rgb((hex >> 16) as u8, (hex >> 8) as u8, *hex as u8))
+    }
+
+    /// Parse a CSS color: a named color, `#rgb`, `#rgba`, `#rrggbb`, `#rrggbbaa`,
+    /// `rgb()`/`rgba()` or `hsl()`/`hsla()`.
+    pub fn parse(text: &str) -> Result<Color, String> {
+        let s = text.trim().to_ascii_lowercase();
+        if let Some(hex) = s.strip_prefix('#') {
+            return parse_hex(hex).ok_or_else(|| format!("invalid hex color {text:?}"));
+        }
+        if let Some((func, args)) = s.strip_suffix(')').and_then(|s| s.split_once('(')) {
+            let args = split_args(args);
+            return match func.trim() {
+                "rgb" | "rgba" => parse_rgb(&args),
+                "hsl" | "hsla" => parse_hsl(&args),
+                other => Err(format!("unsupported color function {other:?}")),
+            }
+            .map_err(|e| format!("invalid color {text:?}: {e}"));
+        }
+        // "light blue" and "light-blue" are how people write lightblue
+        let compact: String = s.chars().filter(|c| !c.is_whitespace() && *c != '-').collect();
+        Color::named(&compact).ok_or_else(|| format!("unknown color {text:?}"))
+    }
+
+    /// Find the color a free-text prompt asks for, e.g. "draw a reddish-brown circle".
+    /// Returns the words as written along with the color.
+    pub fn find_in_text(text: &str) -> Option<(String, Color)> {
+        let lower = text.to_ascii_lowercase();
+
+        // Explicit syntax wins: "#ff8800", "rgb(10, 20, 30)"
+        for (start, _) in lower.match_indices(['#', 'r', 'h']) {
+            let rest = &lower[start..];
+            let candidate = if let Some(digits) = rest.strip_prefix('#') {
+                let len = digits.chars().take_while(|c| c.is_ascii_hexdigit()).count();
+                &rest[..1 + len]
+            } else if rest.starts_with("rgb") || rest.starts_with("hsl") {
+                match rest.find(')') {
+                    Some(end) => &rest[..=end],
+                    None => continue,
+                }
+            } else {
+                continue;
+            };
+            if let Ok(color) = Color::parse(candidate) {
+                return Some((candidate.to_string(), color));
+            }
+        }
+
+        let words: Vec<&str> = lower
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|w| !w.is_empty())
+            .collect();
+
+        // Prefer the longest run of words that names a color ("dark slate gray" over "gray")
+        for len in (1..=3).rev() {
+            for (i, window) in words.windows(len).enumerate() {
+                let Some(color) = Color::named(&window.concat()) else { continue };
+                let mut phrase = window.join(" ");
+                let mut color = color;
+                // "reddish brown": tint the base color towards the -ish one
+                if let Some(tint) = i
+                    .checked_sub(1)
+                    .and_then(|p| ISH_FORMS.iter().find(|(ish, _)| *ish == words[p]))
+                    .and_then(|(_, base)| Color::named(base))
+                {
+                    phrase = format!("{}-{}", words[i - 1], phrase);
+                    color = color.mix(tint, 0.3);
+                }
+                return Some((phrase, color));
+            }
+        }
+
+        // Only an -ish word on its own: "make it bluish"
+        words.iter().find_map(|w| {
+            ISH_FORMS
+                .iter()
+                .find(|(ish, _)| ish == w)
+                .and_then(|(_, base)| Color::named(base))
+                .map(|c| (w.to_string(), c))
+        })
+    }
+
+    /// Blend `amount` (0..1) of `other` into this color.
+    pub fn mix(self, other: Color, amount: f64) -> Color {
+        let t = amount.clamp(0.0, 1.0);
+        let lerp = |a: u8, b: u8| (a as f64 + (b as f64 - a as f64) * t).round() as u8;
+        Color {
+            r: lerp(self.r, other.r),
+            g: lerp(self.g, other.g),
+            b: lerp(self.b, other.b),
+            a: lerp(self.a, other.a),
+        }
+    }
+
+    /// `#rrggbb`, or `#rrggbbaa` when not fully opaque. Safe for `Gegl.Color.new('…')`.
+    pub fn to_hex(self) -> String {
+        if self.a == 255 {
+            format!("#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+        } else {
+            format!("#{:02x}{:02x}{:02x}{:02x}", self.r, self.g, self.b, self.a)
+        }
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
+
+impl Serialize for Color {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_hex())
+    }
+}
+
+impl<'de> Deserialize<'de> for Color {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        Color::parse(&text).map_err(serde::de::Error::custom)
+    }
+}
+
+fn parse_hex(hex: &str) -> Option<Color> {
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let digit = |i: usize| u8::from_str_radix(&hex[i..=i], 16).ok().map(|v| v * 17);
+    let pair = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    match hex.len() {
+        3 => Some(Color { r: digit(0)?, g: digit(1)?, b: digit(2)?, a: 255 }),
+        4 => Some(Color { r: digit(0)?, g: digit(1)?, b: digit(2)?, a: digit(3)? }),
+        6 => Some(Color { r: pair(0)?, g: pair(2)?, b: pair(4)?, a: 255 }),
+        8 => Some(Color { r: pair(0)?, g: pair(2)?, b: pair(4)?, a: pair(6)? }),
+        _ => None,
+    }
+}
+
+/// Arguments of `rgb(…)`/`hsl(…)`: comma- or space-separated, with an optional `/ alpha`.
+fn split_args(args: &str) -> Vec<String> {
+    args.replace('/', " ")
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|a| !a.is_empty())
+        .map(|a| a.to_string())
+        .collect()
+}
+
+fn number(arg: &str) -> Result<f64, String> {
+    arg.parse::<f64>()
+        .ok()
+        .filter(|v| v.is_finite())
+        .ok_or_else(|| format!("{arg:?} is not a number"))
+}
+
+/// A channel given as 0..255 or as a percentage.
+fn channel(arg: &str) -> Result<u8, String> {
+    let value = match arg.strip_suffix('%') {
+        Some(pct) => number(pct)? * 2.55,
+        None => number(arg)?,
+    };
+    Ok(value.clamp(0.0, 255.0).round() as u8)
+}
+
+/// Alpha given as 0..1 or as a percentage; opaque when absent.
+fn alpha(arg: Option<&String>) -> Result<u8, String> {
+    let Some(arg) = arg else { return Ok(255) };
+    let value = match arg.strip_suffix('%') {
+        Some(pct) => number(pct)? / 100.0,
+        None => number(arg)?,
+    };
+    Ok((value.clamp(0.0, 1.0) * 255.0).round() as u8)
+}
+
+fn parse_rgb(args: &[String]) -> Result<Color, String> {
+    if args.len() != 3 && args.len() != 4 {
+        return Err(format!("expected 3 or 4 values, got {}", args.len()));
+    }
+    Ok(Color {
+        r: channel(&args[0])?,
+        g: channel(&args[1])?,
+        b: channel(&args[2])?,
+        a: alpha(args.get(3))?,
+    })
+}
+
+fn parse_hsl(args: &[String]) -> Result<Color, String> {
+    if args.len() != 3 && args.len() != 4 {
+        return Err(format!("expected 3 or 4 values, got {}", args.len()));
+    }
+    let hue = number(args[0].trim_end_matches("deg"))?.rem_euclid(360.0);
+    let sat = number(args[1].trim_end_matches('%'))?.clamp(0.0, 100.0) / 100.0;
+    let light = number(args[2].trim_end_matches('%'))?.clamp(0.0, 100.0) / 100.0;
+
+    // CSS Color 4 hsl → rgb
+    let k = |n: f64| (n + hue / 30.0) % 12.0;
+    let a = sat * light.min(1.0 - light);
+    let f = |n: f64| light - a * (k(n) - 3.0).min(9.0 - k(n)).clamp(-1.0, 1.0);
+    let to_u8 = |v: f64| (v * 255.0).round().clamp(0.0, 255.0) as u8;
+
+    Ok(Color {
+        r: to_u8(f(0.0)),
+        g: to_u8(f(8.0)),
+        b: to_u8(f(4.0)),
+        a: alpha(args.get(3))?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_css_syntaxes_to_hex() {
+        assert_eq!(Color::parse("Pink").unwrap().to_hex(), "#ffc0cb");
+        assert_eq!(Color::parse("light blue").unwrap().to_hex(), "#add8e6");
+        assert_eq!(Color::parse("#F80").unwrap().to_hex(), "#ff8800");
+        assert_eq!(Color::parse("#11223380").unwrap().to_hex(), "#11223380");
+        assert_eq!(Color::parse("rgb(255, 0, 0)").unwrap(), Color::rgb(255, 0, 0));
+        assert_eq!(Color::parse("rgba(0 0 255 / 50%)").unwrap().to_hex(), "#0000ff80");
+        assert_eq!(Color::parse("hsl(120, 100%, 25%)").unwrap(), Color::rgb(0, 128, 0));
+        assert!(Color::parse("red'); Gimp.quit('").is_err());
+        assert!(Color::parse("#12345").is_err());
+    }
+
+    #[test]
+    fn finds_colors_in_prompts() {
+        let (phrase, color) = Color::find_in_text("draw a reddish-brown heart").unwrap();
+        assert_eq!(phrase, "reddish-brown");
+        assert_ne!(color, Color::named("red").unwrap());
+        assert_ne!(color, Color::named("brown").unwrap());
+
+        let (phrase, _) = Color::find_in_text("add a dark green circle").unwrap();
+        assert_eq!(phrase, "dark green");
+        let (_, color) = Color::find_in_text("fill with #00ff00 please").unwrap();
+        assert_eq!(color, Color::rgb(0, 255, 0));
+        assert!(Color::find_in_text("draw a circle").is_none());
+    }
+}
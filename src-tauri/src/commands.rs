@@ -7,6 +7,10 @@ use serde_json::Value;
 pub struct RunPlanResponse {
     pub plan: Value,
     pub results: Vec<StepResult>,
+    /// How far plans can undo and redo after this run.
+    pub history: crate::plan_history::HistoryDepth,
+    /// Id of the stored example when every step succeeded; pass it to `submit_plan_feedback`.
+    pub example_id: Option<u64>,
 }
 
 // #[tauri::command(rename = "run_action_plan")]
@@ -30,10 +34,26 @@ pub async fn run_action_plan(user_text: String) -> Result<RunPlanResponse, Strin
     let plan_json = serde_json::to_value(&plan)
         .map_err(|e| format!("failed to serialize plan: {e}"))?;
 
-    let results = crate::plan_execute::execute_plan(plan)
+    let results = crate::plan_execute::execute_plan(plan.clone())
         .map_err(|e| format!("plan execution failed: {e}"))?;
 
-    Ok(RunPlanResponse { plan: plan_json, results })
+    // Keep successful plans as few-shot examples for future requests
+    let mut example_id = None;
+    if !plan.steps.is_empty() && results.iter().all(|r| r.ok) {
+        match crate::plan_memory::record(&user_text, &plan).await {
+            Ok(id) => example_id = Some(id),
+            Err(e) => eprintln!("[MEMORY] Failed to store plan example: {e}"),
+        }
+    }
+
+    Ok(RunPlanResponse { plan: plan_json, results, example_id, history: crate::plan_history::depth() })
+}
+
+/// Record whether a plan did what the user wanted. Unhelpful plans are
+/// no longer offered to the planner as examples.
+#[tauri::command]
+pub fn submit_plan_feedback(example_id: u64, helpful: bool, comment: Option<String>) -> Result<(), String> {
+    crate::plan_memory::set_feedback(example_id, crate::plan_memory::Feedback { helpful, comment })
 }
 
 
@@ -46,3 +66,49 @@ pub async fn generate_action_plan(user_text: &str)
 {
     crate::plan_llm::make_plan_from_text(user_text).await
 }
+
+/// The GEGL filters `gegl_filter` steps may use, with their properties and ranges.
+#[tauri::command]
+pub fn list_gegl_filters() -> Value {
+    crate::gegl_filters::catalog()
+}
+
+#[tauri::command]
+pub fn get_file_settings() -> crate::file_access::FileSettings {
+    crate::file_access::settings()
+}
+
+/// Set the directory exports and XCF saves are confined to.
+#[tauri::command]
+pub fn set_file_settings(settings: crate::file_access::FileSettings) -> Result<(), String> {
+    crate::file_access::set_settings(settings)
+}
+
+#[tauri::command]
+pub fn get_plan_history() -> crate::plan_history::HistoryDepth {
+    crate::plan_history::depth()
+}
+
+/// Step the image back through the plan history; returns the new depth.
+#[tauri::command]
+pub fn undo_plan_step(steps: Option<usize>) -> Result<crate::plan_history::HistoryDepth, String> {
+    crate::plan_execute::undo(steps.unwrap_or(1))
+}
+
+/// Re-apply plan steps that were undone; returns the new depth.
+#[tauri::command]
+pub fn redo_plan_step(steps: Option<usize>) -> Result<crate::plan_history::HistoryDepth, String> {
+    crate::plan_execute::redo(steps.unwrap_or(1))
+}
+
+#[tauri::command]
+pub fn get_llm_settings() -> crate::llm_client::LlmSettings {
+    crate::llm_client::settings()
+}
+
+/// Replace the per-role generation options (temperature, top_p, seed, num_ctx,
+/// keep_alive, system message) and the deterministic planning flag.
+#[tauri::command]
+pub fn set_llm_settings(settings: crate::llm_client::LlmSettings) -> Result<(), String> {
+    crate::llm_client::set_settings(settings)
+}
@@ -0,0 +1,208 @@
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Where plans may write and read files.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FileSettings {
+    /// Directory `export` and `save_xcf` write into. Unset means the default
+    /// picked at start-up.
+    #[serde(default)]
+    pub output_dir: Option<PathBuf>,
+    /// Directories `open_image` may read from, besides the output directory.
+    #[serde(default)]
+    pub input_dirs: Vec<PathBuf>,
+}
+
+static SETTINGS: Lazy<Mutex<FileSettings>> = Lazy::new(|| Mutex::new(FileSettings::default()));
+
+/// Where settings are persisted. Unset (e.g. in tests) means in-memory only.
+static SETTINGS_PATH: Lazy<Mutex<Option<PathBuf>>> = Lazy::new(|| Mutex::new(None));
+
+static DEFAULT_OUTPUT_DIR: Lazy<Mutex<Option<PathBuf>>> = Lazy::new(|| Mutex::new(None));
+
+/// Longest file name stem we write.
+const MAX_STEM_CHARS: usize = 100;
+
+pub fn settings() -> FileSettings {
+    SETTINGS.lock().map(|s| s.clone()).unwrap_or_default()
+}
+
+pub fn set_settings(new_settings: FileSettings) -> Result<(), String> {
+    if let Some(dir) = &new_settings.output_dir {
+        if !dir.is_absolute() {
+            return Err(format!("Output directory must be an absolute path, got {}", dir.display()));
+        }
+    }
+    if let Some(dir) = new_settings.input_dirs.iter().find(|d| !d.is_absolute()) {
+        return Err(format!("Input directories must be absolute paths, got {}", dir.display()));
+    }
+    let mut guard = SETTINGS
+        .lock()
+        .map_err(|_| "File settings mutex poisoned".to_string())?;
+
+    if let Some(path) = SETTINGS_PATH.lock().ok().and_then(|p| p.clone()) {
+        let json = serde_json::to_string_pretty(&new_settings)
+            .map_err(|e| format!("Failed to serialize file settings: {e}"))?;
+        std::fs::write(&path, json)
+            .map_err(|e| format!("Failed to save file settings to {}: {e}", path.display()))?;
+    }
+
+    *guard = new_settings;
+    Ok(())
+}
+
+/// Load settings saved by a previous run, persist future changes to `path`, and
+/// use `default_output_dir` while no output directory is configured.
+pub fn init_settings_file(path: PathBuf, default_output_dir: PathBuf) -> Result<(), String> {
+    if let Ok(mut default) = DEFAULT_OUTPUT_DIR.lock() {
+        *default = Some(default_output_dir);
+    }
+    if path.exists() {
+        let text = std::fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read file settings from {}: {e}", path.display()))?;
+        let loaded: FileSettings = serde_json::from_str(&text)
+            .map_err(|e| format!("Invalid file settings in {}: {e}", path.display()))?;
+        let mut guard = SETTINGS
+            .lock()
+            .map_err(|_| "File settings mutex poisoned".to_string())?;
+        *guard = loaded;
+    }
+
+    let mut stored = SETTINGS_PATH
+        .lock()
+        .map_err(|_| "File settings path mutex poisoned".to_string())?;
+    *stored = Some(path);
+    Ok(())
+}
+
+/// The configured output directory (or the default), created if missing.
+pub fn output_dir() -> Result<PathBuf, String> {
+    let dir = settings()
+        .output_dir
+        .or_else(|| DEFAULT_OUTPUT_DIR.lock().ok().and_then(|d| d.clone()))
+        .ok_or_else(|| "No output directory is configured".to_string())?;
+    std::fs::create_dir_all(&dir)
+        .map_err(|e| format!("Failed to create output directory {}: {e}", dir.display()))?;
+    dir.canonicalize()
+        .map_err(|e| format!("Output directory {} is unavailable: {e}", dir.display()))
+}
+
+/// A file name stem safe to put in the output directory: no directories, no
+/// leading dot, unusual characters replaced by `_`.
+pub fn sanitize_file_stem(name: &str) -> Result<String, String> {
+    let name = name.trim();
+    if name.contains(['/', '\\']) || name.starts_with('.') {
+        return Err(format!("{name:?} must be a plain file name, not a path"));
+    }
+    let stem: String = name
+        .chars()
+        .map(|c| if c.is_alphanumeric() || matches!(c, '-' | '_' | ' ' | '.') { c } else { '_' })
+        .take(MAX_STEM_CHARS)
+        .collect();
+    let stem = stem.trim().to_string();
+    if stem.is_empty() {
+        return Err("file name is empty".to_string());
+    }
+    Ok(stem)
+}
+
+/// Create an empty `stem.ext` in `dir`, or `stem-1.ext`, `stem-2.ext`… if taken,
+/// and return its path. Creating the file claims the name, so two exports never
+/// overwrite each other or an existing file.
+pub fn reserve_path(dir: &Path, stem: &str, ext: &str) -> Result<PathBuf, String> {
+    for n in 0..1000 {
+        let name = if n == 0 { format!("{stem}.{ext}") } else { format!("{stem}-{n}.{ext}") };
+        let path = dir.join(name);
+        if path.parent() != Some(dir) {
+            return Err(format!("{} is outside the output directory", path.display()));
+        }
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(_) => return Ok(path),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(format!("Failed to create {}: {e}", path.display())),
+        }
+    }
+    Err(format!("Too many files named {stem}.{ext} in {}", dir.display()))
+}
+
+/// Reserve a collision-safe path for `stem.ext` in the output directory.
+pub fn reserve_output_path(stem: &str, ext: &str) -> Result<PathBuf, String> {
+    reserve_path(&output_dir()?, &sanitize_file_stem(stem)?, ext)
+}
+
+/// Resolve `path` to an existing file inside one of `dirs`. Relative paths are
+/// looked up in each directory in turn; symlinks and `..` are resolved before
+/// the check, so neither can reach outside.
+pub fn resolve_in_dirs(dirs: &[PathBuf], path: &str) -> Result<PathBuf, String> {
+    let requested = Path::new(path.trim());
+    let dirs: Vec<PathBuf> = dirs.iter().filter_map(|d| d.canonicalize().ok()).collect();
+    let candidate = if requested.is_absolute() {
+        requested.to_path_buf()
+    } else {
+        dirs.iter()
+            .map(|d| d.join(requested))
+            .find(|p| p.exists())
+            .ok_or_else(|| format!("{} was not found in the allowed directories", requested.display()))?
+    };
+    let file = candidate
+        .canonicalize()
+        .map_err(|e| format!("Can't open {}: {e}", candidate.display()))?;
+    if !dirs.iter().any(|d| file.starts_with(d)) {
+        return Err(format!("{} is outside the directories images may be opened from", file.display()));
+    }
+    if !file.is_file() {
+        return Err(format!("{} is not a file", file.display()));
+    }
+    Ok(file)
+}
+
+/// Resolve a path `open_image` was given against the output and input directories.
+pub fn resolve_input_path(path: &str) -> Result<PathBuf, String> {
+    let mut dirs = vec![output_dir()?];
+    dirs.extend(settings().input_dirs);
+    resolve_in_dirs(&dirs, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_names_stay_in_the_directory_and_never_collide() {
+        assert_eq!(sanitize_file_stem(" holiday: final? ").unwrap(), "holiday_ final_");
+        assert!(sanitize_file_stem("../etc/passwd").is_err());
+        assert!(sanitize_file_stem(".hidden").is_err());
+        assert!(sanitize_file_stem("  ").is_err());
+
+        let dir = std::env::temp_dir().join(format!("file-access-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let first = reserve_path(&dir, "photo", "png").unwrap();
+        let second = reserve_path(&dir, "photo", "png").unwrap();
+        assert_eq!(first.file_name().unwrap(), "photo.png");
+        assert_eq!(second.file_name().unwrap(), "photo-1.png");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn input_paths_must_resolve_inside_an_allowed_directory() {
+        let root = std::env::temp_dir().join(format!("file-access-input-{}", std::process::id()));
+        let allowed = root.join("allowed");
+        std::fs::create_dir_all(allowed.join("sub")).unwrap();
+        std::fs::write(allowed.join("sub").join("cat.png"), b"png").unwrap();
+        std::fs::write(root.join("secret.png"), b"png").unwrap();
+        let dirs = [allowed.clone()];
+
+        let found = resolve_in_dirs(&dirs, "sub/cat.png").unwrap();
+        assert!(found.ends_with("sub/cat.png"));
+        assert_eq!(resolve_in_dirs(&dirs, found.to_str().unwrap()).unwrap(), found);
+        assert!(resolve_in_dirs(&dirs, "../secret.png").unwrap_err().contains("outside"));
+        assert!(resolve_in_dirs(&dirs, root.join("secret.png").to_str().unwrap()).is_err());
+        assert!(resolve_in_dirs(&dirs, "missing.png").unwrap_err().contains("not found"));
+        assert!(resolve_in_dirs(&dirs, "sub").unwrap_err().contains("not a file"));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
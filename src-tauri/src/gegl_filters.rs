@@ -0,0 +1,260 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+use crate::color::Color;
+
+/// Type, range and GEGL default of a filter property.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PropertyKind {
+    Float { min: f64, max: f64, default: f64 },
+    Int { min: i64, max: i64, default: i64 },
+    Bool { default: bool },
+    Color { default: &'static str },
+}
+
+#[derive(Debug, Serialize)]
+pub struct FilterProperty {
+    pub name: &'static str,
+    pub description: &'static str,
+    #[serde(flatten)]
+    pub kind: PropertyKind,
+}
+
+/// A GEGL operation plans may run through `gegl_filter`.
+#[derive(Debug, Serialize)]
+pub struct FilterSpec {
+    pub operation: &'static str,
+    /// Short name the planner and UI can use instead of the operation.
+    pub name: &'static str,
+    pub description: &'static str,
+    pub properties: &'static [FilterProperty],
+}
+
+/// A property value that passed the registry's checks.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum FilterValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Color(Color),
+}
+
+const fn float(name: &'static str, description: &'static str, min: f64, max: f64, default: f64) -> FilterProperty {
+    FilterProperty { name, description, kind: PropertyKind::Float { min, max, default } }
+}
+
+const fn int(name: &'static str, description: &'static str, min: i64, max: i64, default: i64) -> FilterProperty {
+    FilterProperty { name, description, kind: PropertyKind::Int { min, max, default } }
+}
+
+const fn color(name: &'static str, description: &'static str, default: &'static str) -> FilterProperty {
+    FilterProperty { name, description, kind: PropertyKind::Color { default } }
+}
+
+/// Every filter `gegl_filter` may run. Ranges are what's useful from a chat
+/// request, sometimes narrower than GEGL allows.
+pub const FILTERS: &[FilterSpec] = &[
+    FilterSpec {
+        operation: "gegl:unsharp-mask",
+        name: "sharpen",
+        description: "Sharpen with an unsharp mask",
+        properties: &[
+            float("std-dev", "Radius of the detail to sharpen", 0.0, 100.0, 3.0),
+            float("scale", "Strength", 0.0, 10.0, 0.5),
+            float("threshold", "Leave differences below this alone", 0.0, 1.0, 0.0),
+        ],
+    },
+    FilterSpec {
+        operation: "gegl:noise-reduction",
+        name: "denoise",
+        description: "Reduce noise while keeping edges",
+        properties: &[int("iterations", "Strength", 0, 32, 4)],
+    },
+    FilterSpec {
+        operation: "gegl:gaussian-blur",
+        name: "gaussian_blur",
+        description: "Gaussian blur with separate horizontal and vertical sizes",
+        properties: &[
+            float("std-dev-x", "Horizontal blur size", 0.0, 300.0, 1.5),
+            float("std-dev-y", "Vertical blur size", 0.0, 300.0, 1.5),
+            FilterProperty {
+                name: "clip-extent",
+                description: "Keep the blur inside the layer bounds",
+                kind: PropertyKind::Bool { default: true },
+            },
+        ],
+    },
+    FilterSpec {
+        operation: "gegl:median-blur",
+        name: "median_blur",
+        description: "Median blur; smooths speckles",
+        properties: &[int("radius", "Neighborhood radius", 1, 100, 3)],
+    },
+    FilterSpec {
+        operation: "gegl:pixelize",
+        name: "pixelize",
+        description: "Pixelate into blocks",
+        properties: &[
+            int("size-x", "Block width in pixels", 1, 2048, 16),
+            int("size-y", "Block height in pixels", 1, 2048, 16),
+        ],
+    },
+    FilterSpec {
+        operation: "gegl:vignette",
+        name: "vignette",
+        description: "Darken (or color) the edges",
+        properties: &[
+            float("radius", "How far in the vignette reaches, relative to the image", 0.0, 3.0, 1.5),
+            float("softness", "Edge softness", 0.0, 1.0, 0.8),
+            float("gamma", "Falloff curve", 1.0, 20.0, 2.0),
+            float("proportion", "0 is circular, 1 follows the image's aspect ratio", 0.0, 1.0, 1.0),
+            color("color", "Vignette color", "black"),
+        ],
+    },
+    FilterSpec {
+        operation: "gegl:emboss",
+        name: "emboss",
+        description: "Emboss, as if stamped into the surface",
+        properties: &[
+            float("azimuth", "Light direction in degrees", 0.0, 360.0, 30.0),
+            float("elevation", "Light height in degrees", 0.0, 180.0, 45.0),
+            int("depth", "Depth", 1, 100, 20),
+        ],
+    },
+    FilterSpec {
+        operation: "gegl:dropshadow",
+        name: "drop_shadow",
+        description: "Drop shadow behind the layer's opaque pixels",
+        properties: &[
+            float("x", "Horizontal offset in pixels", -500.0, 500.0, 20.0),
+            float("y", "Vertical offset in pixels", -500.0, 500.0, 20.0),
+            float("radius", "Blur radius", 0.0, 300.0, 10.0),
+            int("grow-radius", "Grow (or shrink) the shadow shape", -100, 100, 0),
+            color("color", "Shadow color", "black"),
+            float("opacity", "Shadow opacity", 0.0, 2.0, 0.5),
+        ],
+    },
+    FilterSpec {
+        operation: "gegl:red-eye-removal",
+        name: "red_eye_removal",
+        description: "Remove red eye; select the eyes first",
+        properties: &[float("threshold", "How red a pixel must be to change", 0.0, 0.8, 0.4)],
+    },
+];
+
+/// Look a filter up by operation (`"gegl:pixelize"` or `"pixelize"`) or short name.
+pub fn find(operation: &str) -> Option<&'static FilterSpec> {
+    let key = operation.trim().to_ascii_lowercase();
+    let bare = key.strip_prefix("gegl:").unwrap_or(&key);
+    FILTERS
+        .iter()
+        .find(|f| f.operation.strip_prefix("gegl:") == Some(bare) || f.name == bare.replace('-', "_"))
+}
+
+impl FilterSpec {
+    /// Check `properties` against this filter: known names, right types, in range.
+    /// Properties left out keep GEGL's defaults.
+    pub fn check(&self, properties: &Map<String, Value>) -> Result<BTreeMap<String, FilterValue>, String> {
+        let mut checked = BTreeMap::new();
+        for (name, value) in properties {
+            let prop = self.properties.iter().find(|p| p.name == name).ok_or_else(|| {
+                let names: Vec<&str> = self.properties.iter().map(|p| p.name).collect();
+                format!("{} has no property {name:?} (properties: {})", self.operation, names.join(", "))
+            })?;
+            let out_of_range = |min: &dyn std::fmt::Display, max: &dyn std::fmt::Display| {
+                format!("{} property {name:?} must be between {min} and {max}, got {value}", self.operation)
+            };
+            let checked_value = match prop.kind {
+                PropertyKind::Float { min, max, .. } => {
+                    let v = value
+                        .as_f64()
+                        .ok_or_else(|| format!("{} property {name:?} must be a number", self.operation))?;
+                    if !(min..=max).contains(&v) {
+                        return Err(out_of_range(&min, &max));
+                    }
+                    FilterValue::Float(v)
+                }
+                PropertyKind::Int { min, max, .. } => {
+                    let v = value
+                        .as_i64()
+                        .or_else(|| value.as_f64().filter(|f| f.fract() == 0.0).map(|f| f as i64))
+                        .ok_or_else(|| format!("{} property {name:?} must be a whole number", self.operation))?;
+                    if !(min..=max).contains(&v) {
+                        return Err(out_of_range(&min, &max));
+                    }
+                    FilterValue::Int(v)
+                }
+                PropertyKind::Bool { .. } => FilterValue::Bool(
+                    value
+                        .as_bool()
+                        .ok_or_else(|| format!("{} property {name:?} must be true or false", self.operation))?,
+                ),
+                PropertyKind::Color { .. } => {
+                    let text = value
+                        .as_str()
+                        .ok_or_else(|| format!("{} property {name:?} must be a color string", self.operation))?;
+                    FilterValue::Color(Color::parse(text)?)
+                }
+            };
+            checked.insert(name.clone(), checked_value);
+        }
+        Ok(checked)
+    }
+}
+
+/// The registry as JSON, for the UI.
+pub fn catalog() -> Value {
+    serde_json::to_value(FILTERS).unwrap_or_default()
+}
+
+/// One line per filter for the planner prompt, e.g.
+/// `  sharpen (gegl:unsharp-mask): Sharpen… — std-dev 0..100 (3), …`.
+pub fn prompt_lines() -> String {
+    FILTERS
+        .iter()
+        .map(|f| {
+            let props: Vec<String> = f
+                .properties
+                .iter()
+                .map(|p| match p.kind {
+                    PropertyKind::Float { min, max, default } => format!("{} {min}..{max} ({default})", p.name),
+                    PropertyKind::Int { min, max, default } => format!("{} {min}..{max} ({default})", p.name),
+                    PropertyKind::Bool { default } => format!("{} true|false ({default})", p.name),
+                    PropertyKind::Color { default } => format!("{} color ({default})", p.name),
+                })
+                .collect();
+            format!("  {} ({}): {} — {}", f.name, f.operation, f.description, props.join(", "))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn registry_checks_names_types_and_ranges() {
+        let sharpen = find("sharpen").unwrap();
+        assert_eq!(sharpen.operation, "gegl:unsharp-mask");
+        assert!(std::ptr::eq(find("gegl:unsharp-mask").unwrap(), sharpen));
+        assert!(find("drop-shadow").is_some());
+        assert!(find("gegl:shell-exec").is_none());
+
+        let props = |v: Value| v.as_object().unwrap().clone();
+        let checked = sharpen.check(&props(json!({ "std-dev": 2, "scale": 1.5 }))).unwrap();
+        assert_eq!(checked["std-dev"], FilterValue::Float(2.0));
+        assert!(sharpen.check(&props(json!({ "scale": 50 }))).unwrap_err().contains("between 0 and 10"));
+        assert!(sharpen.check(&props(json!({ "amount": 1 }))).unwrap_err().contains("no property"));
+
+        let pixelize = find("pixelize").unwrap();
+        assert_eq!(pixelize.check(&props(json!({ "size-x": 8.0 }))).unwrap()["size-x"], FilterValue::Int(8));
+        assert!(pixelize.check(&props(json!({ "size-x": 8.5 }))).is_err());
+        let vignette = find("vignette").unwrap();
+        assert!(matches!(vignette.check(&props(json!({ "color": "navy" }))).unwrap()["color"], FilterValue::Color(_)));
+    }
+}
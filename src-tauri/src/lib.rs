@@ -3,14 +3,29 @@
 mod mcp;
 mod llm_client;
 mod macros; 
+mod color;
+mod file_access;
+mod gegl_filters;
 mod plan_schema;
 mod plan_validate;
 mod plan_execute;
+mod plan_history;
 mod commands;
 mod plan_llm;
+mod plan_memory;
+mod tool_calling;
+mod warmup;
+#[cfg(test)]
+mod mock_llm;
+#[cfg(test)]
+mod mock_mcp;
 
 use serde_json::{json, Value};
 use std::process::Command;
+use tauri::Manager;
+
+use crate::color::Color;
+use crate::plan_schema::Target;
 
 use serde::Serialize;
 #[derive(Serialize)]
@@ -47,28 +62,7 @@ fn mcp_list_tools() -> Result<Value, String> {
 
 #[tauri::command]
 fn mcp_call_tool(name: String, arguments: Value) -> Result<Value, String> {
-    mcp::call_tool(&name, arguments)
-}
-
-/// Extract a GEGL-safe colour value from a lowercase prompt string.
-/// Some CSS colour names (pink, orange, cyan, magenta, brown) are not recognised
-/// by GEGL in this version and return garbage values — use hex instead.
-/// Defaults to "blue" if no recognised colour is found.
-fn extract_color(lower: &str) -> &'static str {
-    if lower.contains("red")     { "red" }
-    else if lower.contains("blue")    { "blue" }
-    else if lower.contains("green")   { "green" }
-    else if lower.contains("yellow")  { "yellow" }
-    else if lower.contains("orange")  { "#FFA500" }   // "orange" broken in GEGL
-    else if lower.contains("purple")  { "purple" }
-    else if lower.contains("pink")    { "#FF69B4" }   // "pink" broken in GEGL
-    else if lower.contains("cyan")    { "#00FFFF" }   // "cyan" returns wrong alpha
-    else if lower.contains("magenta") { "#FF00FF" }   // "magenta" broken in GEGL
-    else if lower.contains("brown")   { "#8B4513" }   // "brown" broken in GEGL
-    else if lower.contains("grey") || lower.contains("gray") { "gray" }
-    else if lower.contains("black")   { "black" }
-    else if lower.contains("white")   { "white" }
-    else { "blue" }
+    plan_execute::call_tool_recorded(&name, arguments)
 }
 
 /// Detect a spatial region ("top", "bottom", "left", "right") in a lowercase prompt.
@@ -89,21 +83,195 @@ fn extract_region(lower: &str) -> Option<&'static str> {
 }
 
 /// Call an MCP tool from a macro payload `{ "name": "...", "arguments": {...} }`.
+/// Macros edit the image, so the call goes on the undo history.
 fn run_macro(payload: Value) -> Result<Value, String> {
     let tool_name = payload.get("name").and_then(|v| v.as_str())
         .ok_or_else(|| "Macro payload missing 'name'".to_string())?;
     let arguments = payload.get("arguments").cloned()
         .ok_or_else(|| "Macro payload missing 'arguments'".to_string())?;
-    mcp::call_tool(tool_name, arguments)
+    plan_execute::record_edit(|| mcp::call_tool(tool_name, arguments))
+}
+
+/// Parse the selector's `{"tool": ..., "reason": ...}` reply and check the tool name.
+fn parse_tool_selection(selection_raw: &str) -> Result<Value, String> {
+    // Strip prefix before first '{' and suffix after last '}' (handles markdown fences)
+    let sel_start = selection_raw.find('{').unwrap_or(0);
+    let sel_substr = &selection_raw[sel_start..];
+    let sel_end = sel_substr.rfind('}').map(|i| i + 1).unwrap_or(sel_substr.len());
+    let selection_str = &sel_substr[..sel_end];
+
+    let selection: Value = serde_json::from_str(selection_str).map_err(|e| {
+        format!(
+            "Failed to parse tool selection JSON: {e}\nLLM output was: {selection_raw}"
+        )
+    })?;
+
+    match selection.get("tool").and_then(|t| t.as_str()) {
+        None | Some("get_gimp_info" | "get_image_metadata" | "call_api" | "none") => Ok(selection),
+        Some(other) => Err(format!("Tool selector chose unknown tool '{other}'")),
+    }
+}
+
+/// True if an LLM error means the server isn't running (as opposed to a bad response).
+fn is_llm_unreachable(err: &str) -> bool {
+    err.contains("localhost:11434") || err.contains("connection refused") || err.contains("error sending request")
+}
+
+fn ollama_offline_reply() -> Value {
+    json!({
+        "reply": "I don't recognise that command yet, and Ollama isn't running so I can't handle custom requests.\n\nStart Ollama with:\n  ollama serve\n\nOr try one of the built-in commands: draw a circle, blur the image, increase brightness, draw a red heart, blur the top half.",
+        "undoable": false, "plan": {}, "tool_results": []
+    })
+}
+
+/// One-sentence summary of a `get_image_metadata` result: name, dimensions, base type, layers.
+fn image_metadata_summary(meta: &Value) -> String {
+    let basic = meta.get("basic").unwrap_or(&Value::Null);
+    let file = meta.get("file").unwrap_or(&Value::Null);
+
+    let width = basic
+        .get("width")
+        .and_then(|v| v.as_i64())
+        .unwrap_or(0);
+    let height = basic
+        .get("height")
+        .and_then(|v| v.as_i64())
+        .unwrap_or(0);
+    let base_type = basic
+        .get("base_type")
+        .and_then(|v| v.as_str())
+        .unwrap_or("Unknown");
+    let basename = file
+        .get("basename")
+        .and_then(|v| v.as_str())
+        .unwrap_or("unknown image");
+
+    let mut summary = format!(
+        "Your current image \"{name}\" is {w}×{h} pixels with base type {base}.",
+        name = basename,
+        w = width,
+        h = height,
+        base = base_type,
+    );
+
+    // Layers may come as a plain array or as { "count": n, "layers": [...] }
+    let layers = meta.get("layers").or_else(|| meta.get("structure").and_then(|s| s.get("layers")));
+    let layer_list = layers.and_then(|l| l.as_array().or_else(|| l.get("layers").and_then(|v| v.as_array())));
+    let layer_count = layer_list
+        .map(|l| l.len() as i64)
+        .or_else(|| layers.and_then(|l| l.get("count")).and_then(|v| v.as_i64()));
+
+    if let Some(count) = layer_count {
+        let names: Vec<&str> = layer_list
+            .map(|l| l.iter().filter_map(|layer| layer.get("name").and_then(|n| n.as_str())).collect())
+            .unwrap_or_default();
+        let noun = if count == 1 { "layer" } else { "layers" };
+        if names.is_empty() {
+            summary.push_str(&format!(" It has {count} {noun}."));
+        } else {
+            summary.push_str(&format!(" It has {count} {noun}: {}.", names.join(", ")));
+        }
+    }
+
+    summary
+}
+
+/// Text of the first content item in an MCP tool result, if any.
+fn tool_result_text(result: &Value) -> Option<&str> {
+    result
+        .get("content")
+        .and_then(|c| c.as_array())
+        .and_then(|arr| arr.first())
+        .and_then(|first| first.get("text"))
+        .and_then(|t| t.as_str())
+}
+
+/// Longest side of the preview sent to the vision model. Small keeps llava fast.
+const PREVIEW_MAX_SIZE: u32 = 512;
+
+/// Export a downscaled PNG of the current image through MCP.
+/// Returns the base64 data of the first image content item.
+fn export_image_preview() -> Result<String, String> {
+    let result = mcp::call_tool(
+        "get_image_bitmap",
+        json!({ "max_width": PREVIEW_MAX_SIZE, "max_height": PREVIEW_MAX_SIZE }),
+    )?;
+
+    if result.get("isError").and_then(|e| e.as_bool()).unwrap_or(false) {
+        let msg = tool_result_text(&result).unwrap_or("unknown error");
+        return Err(format!("Could not export image preview: {msg}"));
+    }
+
+    result
+        .get("content")
+        .and_then(|c| c.as_array())
+        .and_then(|arr| {
+            arr.iter()
+                .find(|item| item.get("type").and_then(|t| t.as_str()) == Some("image"))
+        })
+        .and_then(|item| item.get("data"))
+        .and_then(|d| d.as_str())
+        .map(|d| d.to_string())
+        .ok_or_else(|| "Image preview missing from get_image_bitmap result".to_string())
+}
+
+/// Describe the open image: a natural-language description from the vision model,
+/// followed by the metadata summary. Falls back to metadata only if the vision
+/// model or the preview export is unavailable.
+async fn describe_current_image(prompt: &str) -> Result<Value, String> {
+    let meta_result = mcp::call_tool("get_image_metadata", json!({}))?;
+
+    let is_error = meta_result
+        .get("isError")
+        .and_then(|e| e.as_bool())
+        .unwrap_or(false);
+    if is_error {
+        let msg = tool_result_text(&meta_result).unwrap_or("unknown error");
+        return Ok(json!({
+            "reply": format!("I could not get image metadata: {msg}. Please make sure an image is open in GIMP."),
+            "undoable": false, "plan": {},
+            "tool_results": [{ "tool": "get_image_metadata", "arguments": {}, "result": meta_result }]
+        }));
+    }
+
+    let summary = tool_result_text(&meta_result)
+        .and_then(|t| serde_json::from_str::<Value>(t).ok())
+        .map(|meta| image_metadata_summary(&meta));
+
+    let vision_prompt = format!(
+        "Describe the content of this image in 2-3 sentences for someone editing it in GIMP. \
+         Mention the main subject, colours and composition.\n\nUser request: {prompt}"
+    );
+    let description = match export_image_preview() {
+        Ok(preview) => llm_client::describe_image(&vision_prompt, preview).await,
+        Err(e) => Err(e),
+    };
+
+    let reply = match (description, summary) {
+        (Ok(desc), Some(sum)) => format!("{}\n\n{}", desc.trim(), sum),
+        (Ok(desc), None) => desc.trim().to_string(),
+        (Err(e), Some(sum)) => {
+            eprintln!("[VISION] Image description unavailable: {e}");
+            sum
+        }
+        (Err(e), None) => format!("I could not describe the image: {e}"),
+    };
+
+    Ok(json!({
+        "reply": reply,
+        "undoable": false,
+        "plan": { "generation": llm_client::generation_record(llm_client::Role::Vision) },
+        "tool_results": [{ "tool": "get_image_metadata", "arguments": {}, "result": meta_result }]
+    }))
 }
 
 #[tauri::command]
 async fn assistant_request(prompt: String) -> Result<Value, String> {
     let lower_prompt = prompt.to_lowercase();
 
-    // Fast Path: Describe Image
+    // Fast Path: Describe Image — vision model description plus metadata summary
     if lower_prompt.contains("describe") && lower_prompt.contains("image") {
-        return mcp::call_tool("get_image_metadata", json!({}));
+        return describe_current_image(&prompt).await;
     }
 
     // Fast Path: Drawing a line ("draw/add/paint/make/create a line", "black line", etc.)
@@ -123,12 +291,13 @@ async fn assistant_request(prompt: String) -> Result<Value, String> {
         }));
     }
 
-    // Fast Path: Draw heart — extract colour from the prompt, default to pink
+    // Fast Path: Draw heart in the colour the prompt names
     if lower_prompt.contains("heart") {
-        let color = extract_color(&lower_prompt);
-        run_macro(macros::draw_heart(color))?;
+        let (color_name, color) =
+            Color::find_in_text(&lower_prompt).unwrap_or_else(|| ("black".to_string(), Color::DEFAULT_INK));
+        run_macro(macros::draw_heart(&color))?;
         return Ok(json!({
-            "reply": format!("Done! Added a {} heart to the image.", color),
+            "reply": format!("Done! Added a {} heart to the image.", color_name),
             "explain": "To do this yourself in GIMP: use the Ellipse Select tool (press E) to draw two overlapping circles for the bumps, then the Rectangle Select tool (press R) for the body. Fill each selection with Edit → Fill with Foreground Color. Press Shift+Ctrl+A to deselect when done.",
             "undoable": true, "plan": {}, "tool_results": []
         }));
@@ -136,10 +305,11 @@ async fn assistant_request(prompt: String) -> Result<Value, String> {
 
     // Fast Path: Draw circle
     if lower_prompt.contains("circle") {
-        let color = extract_color(&lower_prompt);
-        run_macro(macros::draw_circle(color))?;
+        let (color_name, color) =
+            Color::find_in_text(&lower_prompt).unwrap_or_else(|| ("black".to_string(), Color::DEFAULT_INK));
+        run_macro(macros::draw_circle(&color))?;
         return Ok(json!({
-            "reply": format!("Done! Added a {} circle to the image.", color),
+            "reply": format!("Done! Added a {} circle to the image.", color_name),
             "explain": "To do this yourself in GIMP: choose the Ellipse Select tool (press E). Hold Shift while dragging to make a perfect circle. Then go to Edit → Fill with Foreground Color. Press Shift+Ctrl+A to deselect.",
             "undoable": true, "plan": {}, "tool_results": []
         }));
@@ -147,10 +317,11 @@ async fn assistant_request(prompt: String) -> Result<Value, String> {
 
     // Fast Path: Draw oval / ellipse
     if lower_prompt.contains("oval") || lower_prompt.contains("ellipse") {
-        let color = extract_color(&lower_prompt);
-        run_macro(macros::draw_oval(color))?;
+        let (color_name, color) =
+            Color::find_in_text(&lower_prompt).unwrap_or_else(|| ("black".to_string(), Color::DEFAULT_INK));
+        run_macro(macros::draw_oval(&color))?;
         return Ok(json!({
-            "reply": format!("Done! Added a {} oval to the image.", color),
+            "reply": format!("Done! Added a {} oval to the image.", color_name),
             "explain": "To do this yourself in GIMP: choose the Ellipse Select tool (press E) and drag to draw an oval shape. Then go to Edit → Fill with Foreground Color. Press Shift+Ctrl+A to deselect.",
             "undoable": true, "plan": {}, "tool_results": []
         }));
@@ -158,10 +329,11 @@ async fn assistant_request(prompt: String) -> Result<Value, String> {
 
     // Fast Path: Draw triangle
     if lower_prompt.contains("triangle") {
-        let color = extract_color(&lower_prompt);
-        run_macro(macros::draw_triangle(color))?;
+        let (color_name, color) =
+            Color::find_in_text(&lower_prompt).unwrap_or_else(|| ("black".to_string(), Color::DEFAULT_INK));
+        run_macro(macros::draw_triangle(&color))?;
         return Ok(json!({
-            "reply": format!("Done! Added a {} triangle to the image.", color),
+            "reply": format!("Done! Added a {} triangle to the image.", color_name),
             "explain": "To do this yourself in GIMP: use the Free Select tool (press F) and click three points to draw a triangle outline. Then fill it with Edit → Fill with Foreground Color.",
             "undoable": true, "plan": {}, "tool_results": []
         }));
@@ -172,10 +344,11 @@ async fn assistant_request(prompt: String) -> Result<Value, String> {
         && !lower_prompt.contains("crop")
         && !lower_prompt.contains("resize");
     if wants_draw_rect {
-        let color = extract_color(&lower_prompt);
-        run_macro(macros::draw_filled_rect(color))?;
+        let (color_name, color) =
+            Color::find_in_text(&lower_prompt).unwrap_or_else(|| ("black".to_string(), Color::DEFAULT_INK));
+        run_macro(macros::draw_filled_rect(&color))?;
         return Ok(json!({
-            "reply": format!("Done! Added a {} rectangle to the image.", color),
+            "reply": format!("Done! Added a {} rectangle to the image.", color_name),
             "explain": "To do this yourself in GIMP: choose the Rectangle Select tool (press R) and drag to draw a rectangle. Then go to Edit → Fill with Foreground Color. Press Shift+Ctrl+A to deselect.",
             "undoable": true, "plan": {}, "tool_results": []
         }));
@@ -187,10 +360,11 @@ async fn assistant_request(prompt: String) -> Result<Value, String> {
         && !lower_prompt.contains("crop")
         && !lower_prompt.contains("resize");
     if wants_draw_square {
-        let color = extract_color(&lower_prompt);
-        run_macro(macros::draw_filled_rect(color))?;
+        let (color_name, color) =
+            Color::find_in_text(&lower_prompt).unwrap_or_else(|| ("black".to_string(), Color::DEFAULT_INK));
+        run_macro(macros::draw_filled_rect(&color))?;
         return Ok(json!({
-            "reply": format!("Done! Added a {} square to the image.", color),
+            "reply": format!("Done! Added a {} square to the image.", color_name),
             "explain": "To do this yourself in GIMP: choose the Rectangle Select tool (press R), hold Shift while dragging to make a perfect square. Then go to Edit → Fill with Foreground Color. Press Shift+Ctrl+A to deselect.",
             "undoable": true, "plan": {}, "tool_results": []
         }));
@@ -274,7 +448,7 @@ async fn assistant_request(prompt: String) -> Result<Value, String> {
             || lower_prompt.contains("higher")
             || lower_prompt.contains("up"));
     if wants_brighter {
-        run_macro(macros::brightness_contrast(70.0, 0.0))?;
+        run_macro(macros::brightness_contrast(70.0, 0.0, &Target::Image))?;
         return Ok(json!({
             "reply": "Done! Increased the brightness.",
             "explain": "To do this yourself in GIMP: go to Colors → Brightness-Contrast. Drag the Brightness slider to the right (try around +70). Click OK.",
@@ -297,7 +471,7 @@ async fn assistant_request(prompt: String) -> Result<Value, String> {
             || lower_prompt.contains("lower")
             || lower_prompt.contains("down"));
     if wants_darker || wants_brightness_decrease {
-        run_macro(macros::brightness_contrast(-70.0, 0.0))?;
+        run_macro(macros::brightness_contrast(-70.0, 0.0, &Target::Image))?;
         return Ok(json!({
             "reply": "Done! Decreased the brightness.",
             "explain": "To do this yourself in GIMP: go to Colors → Brightness-Contrast. Drag the Brightness slider to the left (try around −70). Click OK.",
@@ -313,7 +487,7 @@ async fn assistant_request(prompt: String) -> Result<Value, String> {
             || lower_prompt.contains("higher")
             || lower_prompt.contains("up"));
     if wants_more_contrast {
-        run_macro(macros::brightness_contrast(0.0, 70.0))?;
+        run_macro(macros::brightness_contrast(0.0, 70.0, &Target::Image))?;
         return Ok(json!({
             "reply": "Done! Increased the contrast.",
             "explain": "To do this yourself in GIMP: go to Colors → Brightness-Contrast. Drag the Contrast slider to the right (try around +70). Click OK.",
@@ -329,7 +503,7 @@ async fn assistant_request(prompt: String) -> Result<Value, String> {
             || lower_prompt.contains("lower")
             || lower_prompt.contains("down"));
     if wants_less_contrast {
-        run_macro(macros::brightness_contrast(0.0, -70.0))?;
+        run_macro(macros::brightness_contrast(0.0, -70.0, &Target::Image))?;
         return Ok(json!({
             "reply": "Done! Decreased the contrast.",
             "explain": "To do this yourself in GIMP: go to Colors → Brightness-Contrast. Drag the Contrast slider to the left (try around −70). Click OK.",
@@ -343,7 +517,7 @@ async fn assistant_request(prompt: String) -> Result<Value, String> {
         && !lower_prompt.contains("remove blur")
         && !lower_prompt.contains("sharpen");
     if wants_blur {
-        run_macro(macros::blur(10.0))?;
+        run_macro(macros::blur(10.0, &Target::Image))?;
         return Ok(json!({
             "reply": "Done! Applied a blur to the image.",
             "explain": "To do this yourself in GIMP: go to Filters → Blur → Gaussian Blur. Increase the Size value (try 5–10 pixels) and click OK.",
@@ -353,11 +527,12 @@ async fn assistant_request(prompt: String) -> Result<Value, String> {
 
     // Fast Path: Undo
     if lower_prompt == "undo" || lower_prompt.starts_with("undo ") || lower_prompt == "undo last" {
-        run_macro(macros::undo())?;
+        let history = plan_execute::undo(1)?;
         return Ok(json!({
             "reply": "↩ Last change undone.",
             "explain": "To undo in GIMP yourself: press Ctrl+Z (or Cmd+Z on Mac), or go to Edit → Undo.",
-            "undoable": false, "plan": {}, "tool_results": []
+            "undoable": false, "plan": {}, "tool_results": [],
+            "history": history
         }));
     }
 
@@ -376,6 +551,39 @@ async fn assistant_request(prompt: String) -> Result<Value, String> {
             "undoable": true, "plan": {}, "tool_results": []
         }));
     }
+    // Native tool calling: the model picks tools through the `tools` API field and
+    // sees each result before deciding the next call.
+    if !llm_client::settings().legacy_tool_selector {
+        match tool_calling::run(&prompt).await {
+            Ok(run) => {
+                let reply = if run.reply.is_empty() {
+                    "Done! Changes applied to the image.".to_string()
+                } else {
+                    run.reply
+                };
+                let plan = json!({
+                    "thought": "Native tool calling",
+                    "tool_calls": run.tool_calls,
+                    "generation": llm_client::generation_record(llm_client::Role::Planner),
+                    "steps": []
+                });
+                return Ok(json!({
+                    "reply": reply,
+                    "undoable": run.edited,
+                    "plan": plan,
+                    "tool_results": run.tool_results,
+                    "history": plan_history::depth()
+                }));
+            }
+            Err(e) if is_llm_unreachable(&e) => return Ok(ollama_offline_reply()),
+            // Nothing ran yet, so the JSON selector can take over; this covers
+            // models without tool support however their server words the error
+            Err(e) => {
+                eprintln!("[TOOLS] Tool calling failed, falling back to JSON selector: {e}");
+            }
+        }
+    }
+
     // STEP 1: Tool selection, small prompt for Ollama
     let selector_prompt = format!(
         r#"
@@ -402,28 +610,13 @@ User request: {user}
         user = prompt
     );
 
-    let selection_raw = match llm_client::chat(&selector_prompt).await {
+    // Selection runs on the small model; unparseable output escalates to the large one
+    let selection_routed = match llm_client::chat_validated(llm_client::Role::Selector, &selector_prompt, parse_tool_selection).await {
         Ok(r) => r,
-        Err(e) if e.contains("localhost:11434") || e.contains("connection refused") || e.contains("error sending request") => {
-            return Ok(json!({
-                "reply": "I don't recognise that command yet, and Ollama isn't running so I can't handle custom requests.\n\nStart Ollama with:\n  ollama serve\n\nOr try one of the built-in commands: draw a circle, blur the image, increase brightness, draw a red heart, blur the top half.",
-                "undoable": false, "plan": {}, "tool_results": []
-            }));
-        }
+        Err(e) if is_llm_unreachable(&e) => return Ok(ollama_offline_reply()),
         Err(e) => return Err(e),
     };
-
-    // Strip prefix before first '{' and suffix after last '}' (handles markdown fences)
-    let sel_start = selection_raw.find('{').unwrap_or(0);
-    let sel_substr = &selection_raw[sel_start..];
-    let sel_end = sel_substr.rfind('}').map(|i| i + 1).unwrap_or(sel_substr.len());
-    let selection_str = &sel_substr[..sel_end];
-
-    let selection: Value = serde_json::from_str(selection_str).map_err(|e| {
-        format!(
-            "Failed to parse tool selection JSON: {e}\nLLM output was: {selection_raw}"
-        )
-    })?;
+    let selection = selection_routed.value;
 
     let selected_tool = selection
         .get("tool")
@@ -440,7 +633,7 @@ User request: {user}
             user = prompt
         );
 
-        let reply_text = llm_client::chat(&answer_prompt).await?;
+        let reply_text = llm_client::chat_as(llm_client::Role::Answer, &answer_prompt).await?;
 
         let plan = json!({
             "thought": "Tool selector chose 'none'. I answered without calling MCP tools.",
@@ -458,6 +651,7 @@ User request: {user}
     // STEP 2: Build a plan
     // For call_api we ask LLM to generate a detailed plan.
     // For get_gimp_info and get_image_metadata we make a simple one step plan.
+    let mut planner_model: Option<String> = None;
     let mut plan: Value = if selected_tool == "call_api" {
         let planning_prompt = format!(
             r#"
@@ -509,7 +703,7 @@ FORBIDDEN — these do NOT exist in GIMP 3 and WILL crash:
 - gimp-brightness-contrast             ← old PDB name; use drawable.brightness_contrast()
 - Gimp.polygon(), Gimp.draw_*(), Gimp.fill(), Gimp.rectangle(), Gimp.circle()
 - image.draw_*(), layer.draw_*(), drawable.draw_*()
-- Gimp.text_*() — text is not supported
+- Gimp.text_*() — text is added by the add_text op, not through call_api
 - gimp_*() — old Script-Fu style, not available in GIMP 3
 - Gegl.Color.new('pink'), Gegl.Color.new('orange'), Gegl.Color.new('cyan'),
   Gegl.Color.new('magenta'), Gegl.Color.new('brown') — these return wrong colors, use hex instead
@@ -616,18 +810,21 @@ EXAMPLE — rotate the image 90 degrees clockwise:
             user = prompt
         );
 
-        let plan_raw = llm_client::chat(&planning_prompt).await?;
-
-        // Strip any prefix before first '{' and any suffix after last '}'
-        // (handles markdown fences like ```json ... ``` wrapping the output)
-        let json_start = plan_raw.find('{').unwrap_or(0);
-        let json_substr = &plan_raw[json_start..];
-        let json_end = json_substr.rfind('}').map(|i| i + 1).unwrap_or(json_substr.len());
-        let plan_str = &json_substr[..json_end];
-
-        serde_json::from_str(plan_str).map_err(|e| {
-            format!("Failed to parse plan JSON: {e}\nLLM output was: {plan_raw}")
-        })?
+        let routed = llm_client::chat_validated(llm_client::Role::Planner, &planning_prompt, |plan_raw| {
+            // Strip any prefix before first '{' and any suffix after last '}'
+            // (handles markdown fences like ```json ... ``` wrapping the output)
+            let json_start = plan_raw.find('{').unwrap_or(0);
+            let json_substr = &plan_raw[json_start..];
+            let json_end = json_substr.rfind('}').map(|i| i + 1).unwrap_or(json_substr.len());
+            let plan_str = &json_substr[..json_end];
+
+            serde_json::from_str::<Value>(plan_str).map_err(|e| {
+                format!("Failed to parse plan JSON: {e}\nLLM output was: {plan_raw}")
+            })
+        })
+        .await?;
+        planner_model = Some(routed.model);
+        routed.value
     } else {
         // Simple one step plan
         json!({
@@ -644,6 +841,18 @@ EXAMPLE — rotate the image 90 degrees clockwise:
     // Attach the tool selection into the plan for debugging
     if let Value::Object(ref mut map) = plan {
         map.insert("tool_selection".to_string(), selection.clone());
+        // Which model handled each LLM step
+        map.insert("models".to_string(), json!({
+            "selector": selection_routed.model,
+            "selector_escalated": selection_routed.escalated,
+            "planner": planner_model,
+        }));
+        if let Some(model) = planner_model {
+            // Record the planner's sampling options so the plan can be reproduced
+            let mut generation = llm_client::generation_record(llm_client::Role::Planner);
+            generation.model = model;
+            map.insert("generation".to_string(), json!(generation));
+        }
     }
 
     // STEP 3: Execute each step via MCP
@@ -662,8 +871,7 @@ EXAMPLE — rotate the image 90 degrees clockwise:
                 .cloned()
                 .unwrap_or_else(|| json!({}));
 
-            let result = mcp::call_tool(&tool_name, arguments.clone())
-                .map(|val| val)
+            let result = plan_execute::call_tool_recorded(&tool_name, arguments.clone())
                 .unwrap_or_else(|err| {
                     json!({
                         "isError": true,
@@ -776,33 +984,7 @@ EXAMPLE — rotate the image 90 degrees clockwise:
 
             if let Some(text_json) = text_opt {
                 if let Ok(meta) = serde_json::from_str::<Value>(text_json) {
-                    let basic = meta.get("basic").unwrap_or(&Value::Null);
-                    let file = meta.get("file").unwrap_or(&Value::Null);
-
-                    let width = basic
-                        .get("width")
-                        .and_then(|v| v.as_i64())
-                        .unwrap_or(0);
-                    let height = basic
-                        .get("height")
-                        .and_then(|v| v.as_i64())
-                        .unwrap_or(0);
-                    let base_type = basic
-                        .get("base_type")
-                        .and_then(|v| v.as_str())
-                        .unwrap_or("Unknown");
-                    let basename = file
-                        .get("basename")
-                        .and_then(|v| v.as_str())
-                        .unwrap_or("unknown image");
-
-                    reply_text = format!(
-                        "Your current image \"{name}\" is {w}×{h} pixels with base type {base}.",
-                        name = basename,
-                        w = width,
-                        h = height,
-                        base = base_type,
-                    );
+                    reply_text = image_metadata_summary(&meta);
                 }
             }
         }
@@ -955,113 +1137,82 @@ async fn test_basic_mcp() -> String {
 
 #[tauri::command]
 fn macro_draw_line(x1: i32, y1: i32, x2: i32, y2: i32) -> Result<serde_json::Value, String> {
-    // Build the payload (JSON) using the macro helper
-    let payload = macros::draw_line(x1, y1, x2, y2);
-
-    //  MCP layer expects: call_tool(name, arguments)
-    // Our macro payload shape is: { "name": "...", "arguments": {...} }
-    let tool_name = payload
-        .get("name")
-        .and_then(|v| v.as_str())
-        .ok_or_else(|| "Macro payload missing 'name'".to_string())?;
-
-    let arguments = payload
-        .get("arguments")
-        .cloned()
-        .ok_or_else(|| "Macro payload missing 'arguments'".to_string())?;
-
-    // Execute via MCP
-    mcp::call_tool(tool_name, arguments)
+    run_macro(macros::draw_line(x1, y1, x2, y2, &Color::BLACK, &Target::Image))
 }
 
 #[tauri::command]
 fn macro_crop_square() -> Result<serde_json::Value, String> {
-    let payload = macros::crop_to_square();
-
-    let tool_name = payload
-        .get("name")
-        .and_then(|v| v.as_str())
-        .ok_or_else(|| "Macro payload missing 'name'".to_string())?;
-
-    let arguments = payload
-        .get("arguments")
-        .cloned()
-        .ok_or_else(|| "Macro payload missing 'arguments'".to_string())?;
-
-    mcp::call_tool(tool_name, arguments)
+    run_macro(macros::crop_to_square())
 }
 
 #[tauri::command]
 fn macro_resize(width: i32) -> Result<serde_json::Value, String> {
-    let payload = macros::resize_width(width);
-
-    let tool_name = payload
-        .get("name")
-        .and_then(|v| v.as_str())
-        .ok_or_else(|| "Macro payload missing 'name'".to_string())?;
-
-    let arguments = payload
-        .get("arguments")
-        .cloned()
-        .ok_or_else(|| "Macro payload missing 'arguments'".to_string())?;
-
-    mcp::call_tool(tool_name, arguments)
+    run_macro(macros::resize_width(width))
 }
 
 #[tauri::command]
 fn macro_brightness_contrast(brightness: f64, contrast: f64) -> Result<serde_json::Value, String> {
-    let payload = macros::brightness_contrast(brightness, contrast);
-
-    let tool_name = payload
-        .get("name")
-        .and_then(|v| v.as_str())
-        .ok_or_else(|| "Macro payload missing 'name'".to_string())?;
-
-    let arguments = payload
-        .get("arguments")
-        .cloned()
-        .ok_or_else(|| "Macro payload missing 'arguments'".to_string())?;
-
-    mcp::call_tool(tool_name, arguments)
+    run_macro(macros::brightness_contrast(brightness, contrast, &Target::Image))
 }
 
 #[tauri::command]
 fn macro_blur(radius: f64) -> Result<serde_json::Value, String> {
-    let payload = macros::blur(radius);
-
-    let tool_name = payload
-        .get("name")
-        .and_then(|v| v.as_str())
-        .ok_or_else(|| "Macro payload missing 'name'".to_string())?;
-
-    let arguments = payload
-        .get("arguments")
-        .cloned()
-        .ok_or_else(|| "Macro payload missing 'arguments'".to_string())?;
-
-    mcp::call_tool(tool_name, arguments)
+    run_macro(macros::blur(radius, &Target::Image))
 }
 
 #[tauri::command]
 fn macro_undo() -> Result<serde_json::Value, String> {
-    let payload = macros::undo();
-
-    let tool_name = payload
-        .get("name")
-        .and_then(|v| v.as_str())
-        .ok_or_else(|| "Macro payload missing 'name'".to_string())?;
-
-    let arguments = payload
-        .get("arguments")
-        .cloned()
-        .ok_or_else(|| "Macro payload missing 'arguments'".to_string())?;
-
-    mcp::call_tool(tool_name, arguments)
+    Ok(json!(plan_execute::undo(1)?))
 }
 
 
 pub fn run() {
     tauri::Builder::default()
+        .manage(warmup::OllamaServer::default())
+        .setup(|app| {
+            match app.path().app_data_dir() {
+                Ok(dir) => {
+                    let _ = std::fs::create_dir_all(&dir);
+                    if let Err(e) = plan_memory::init_store(dir.join("plan_examples.json")) {
+                        eprintln!("[MEMORY] {e}");
+                    }
+                }
+                Err(e) => eprintln!("[MEMORY] No data directory, plan examples won't be saved: {e}"),
+            }
+
+            match app.path().app_cache_dir() {
+                Ok(dir) => {
+                    if let Err(e) = plan_history::init_dir(dir.join("history")) {
+                        eprintln!("[HISTORY] {e}");
+                    }
+                }
+                Err(e) => eprintln!("[HISTORY] No cache directory, plans can't be undone: {e}"),
+            }
+
+            match app.path().app_config_dir() {
+                Ok(dir) => {
+                    let _ = std::fs::create_dir_all(&dir);
+                    if let Err(e) = llm_client::init_settings_file(dir.join("llm_settings.json")) {
+                        eprintln!("[SETTINGS] {e}");
+                    }
+                    // Exports go to Pictures/GIMP Assistant unless the user picks a folder
+                    let exports = app
+                        .path()
+                        .picture_dir()
+                        .or_else(|_| app.path().app_data_dir())
+                        .map(|d| d.join("GIMP Assistant"))
+                        .unwrap_or_else(|_| dir.join("exports"));
+                    if let Err(e) = file_access::init_settings_file(dir.join("file_settings.json"), exports) {
+                        eprintln!("[SETTINGS] {e}");
+                    }
+                }
+                Err(e) => eprintln!("[SETTINGS] No config directory, settings won't be saved: {e}"),
+            }
+
+            // Pre-load the planner model so the first request doesn't hit a cold start
+            tauri::async_runtime::spawn(warmup::run(app.handle().clone()));
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             start_gimp_mcp_server,
             mcp_list_tools,
@@ -1076,8 +1227,89 @@ pub fn run() {
             macro_blur,
             macro_undo,
             commands::run_action_plan,
+            commands::get_llm_settings,
+            commands::set_llm_settings,
+            commands::submit_plan_feedback,
+            commands::list_gegl_filters,
+            commands::get_file_settings,
+            commands::set_file_settings,
+            commands::get_plan_history,
+            commands::undo_plan_step,
+            commands::redo_plan_step,
 
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            if let tauri::RunEvent::Exit = event {
+                app.state::<warmup::OllamaServer>().stop();
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_llm::MockLlm;
+
+    #[tokio::test]
+    async fn selector_none_answers_in_plain_text() {
+        let mock = MockLlm::start();
+        let mut settings = llm_client::settings();
+        settings.legacy_tool_selector = true;
+        llm_client::set_settings(settings).unwrap();
+
+        mock.on(r"You are a tool selector", r#"{"tool": "none", "reason": "general question"}"#);
+        mock.on(r"knows about GIMP", "Use Image → Scale Image.");
+
+        let resp = assistant_request("how do I scale things?".to_string()).await.unwrap();
+
+        assert_eq!(resp["reply"], "Use Image → Scale Image.");
+        assert_eq!(resp["plan"]["tool_selection"]["tool"], "none");
+        let prompts = mock.prompts();
+        assert_eq!(prompts.len(), 2);
+        assert!(prompts[0].contains("User request: how do I scale things?"));
+        assert!(prompts[1].contains("User: how do I scale things?"));
+    }
+
+    #[tokio::test]
+    async fn failed_tool_calling_falls_back_to_the_selector() {
+        // No rule answers the tool-calling request, so the server errors with
+        // its own wording, not "does not support tools"
+        let mock = MockLlm::start();
+        let _mcp = crate::mock_mcp::MockMcp::start();
+        mock.on(r"You are a tool selector", r#"{"tool": "none", "reason": "general question"}"#);
+        mock.on(r"knows about GIMP", "Use Image → Scale Image.");
+
+        let resp = assistant_request("how do I scale things?".to_string()).await.unwrap();
+
+        assert_eq!(resp["reply"], "Use Image → Scale Image.");
+        assert!(mock.requests()[0].get("tools").is_some(), "tool calling should be tried first");
+    }
+
+    #[tokio::test]
+    async fn quick_shapes_use_the_named_colour_or_the_default_ink() {
+        let mcp = crate::mock_mcp::MockMcp::start();
+
+        let resp = assistant_request("draw a circle".to_string()).await.unwrap();
+        assert_eq!(resp["reply"], "Done! Added a black circle to the image.");
+        let ink = format!("Gegl.Color.new('{}')", Color::DEFAULT_INK.to_hex());
+        assert!(mcp.scripts().concat().iter().any(|line| line.contains(&ink)));
+
+        let resp = assistant_request("draw a reddish-brown circle".to_string()).await.unwrap();
+        assert_eq!(resp["reply"], "Done! Added a reddish-brown circle to the image.");
+    }
+
+    #[tokio::test]
+    async fn unparseable_selector_output_is_an_error() {
+        let mock = MockLlm::start();
+        let mut settings = llm_client::settings();
+        settings.legacy_tool_selector = true;
+        llm_client::set_settings(settings).unwrap();
+
+        mock.on(r"You are a tool selector", "I think you want call_api");
+
+        let err = assistant_request("how do I scale things?".to_string()).await.unwrap_err();
+        assert!(err.contains("Failed to parse tool selection JSON"));
+    }
 }
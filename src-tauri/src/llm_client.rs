@@ -1,13 +1,30 @@
-use serde::Serialize;
-use serde_json::Value;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Instant;
 
-const OLLAMA_URL: &str = "http://localhost:11434/api/chat";
+const DEFAULT_BASE_URL: &str = "http://localhost:11434";
 const MODEL_NAME: &str = "llama3"; // change if you use a different model
+const VISION_MODEL_NAME: &str = "llava"; // any multimodal Ollama model works
+const EMBED_MODEL_NAME: &str = "nomic-embed-text";
+
+/// Seed used for the planner when deterministic mode is on.
+pub const DETERMINISTIC_SEED: i64 = 42;
 
 #[derive(Serialize)]
 struct OllamaMessage {
     role: String,
     content: String,
+    /// Base64-encoded images for multimodal models.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    images: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<Value>,
+    /// Ollama identifies tool results by the tool's name.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_name: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -15,47 +32,794 @@ struct OllamaRequest {
     model: String,
     messages: Vec<OllamaMessage>,
     stream: bool,
+    #[serde(skip_serializing_if = "GenerationOptions::is_empty")]
+    options: GenerationOptions,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keep_alive: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<Value>,
 }
 
-/// Simple chat call to the local LLM (Ollama).
-pub async fn chat(prompt: &str) -> Result<String, String> {
-    let client = reqwest::Client::new();
+/// Which chat API the LLM server speaks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Backend {
+    /// Ollama's native `/api/chat`.
+    #[default]
+    Ollama,
+    /// OpenAI-compatible `/v1/chat/completions` (llama.cpp server, LM Studio, Ollama's /v1).
+    OpenAi,
+}
 
-    let req = OllamaRequest {
-        model: MODEL_NAME.to_string(),
-        messages: vec![OllamaMessage {
-            role: "user".into(),
-            content: prompt.into(),
-        }],
-        // Important: disable streaming so we get a single JSON object
-        stream: false,
+/// Which step of the assistant a chat call belongs to.
+/// Each role has its own generation settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Picks an MCP tool for the request (STEP 1 of assistant_request).
+    Selector,
+    /// Produces plans (ActionPlan or call_api Python steps).
+    Planner,
+    /// Answers questions in natural language.
+    Answer,
+    /// Describes the image content (multimodal model).
+    Vision,
+}
+
+impl Role {
+    /// Classification and chat go to the small model; plan generation to the large one.
+    pub fn default_tier(self) -> ModelTier {
+        match self {
+            Role::Selector | Role::Answer => ModelTier::Small,
+            Role::Planner | Role::Vision => ModelTier::Large,
+        }
+    }
+}
+
+/// Size class of the local model that handles a role.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModelTier {
+    /// Fast model for cheap classification.
+    Small,
+    /// Slower, more capable model for code and plan generation.
+    Large,
+}
+
+/// Which Ollama model serves each tier. Unset tiers use `MODEL_NAME`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ModelRouting {
+    #[serde(default)]
+    pub small: Option<String>,
+    #[serde(default)]
+    pub large: Option<String>,
+    /// Multimodal model for image descriptions.
+    #[serde(default)]
+    pub vision: Option<String>,
+}
+
+/// Sampling options forwarded to Ollama's `options` object.
+/// Unset fields are left out so Ollama uses the model defaults.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GenerationOptions {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub num_ctx: Option<u32>,
+}
+
+impl GenerationOptions {
+    fn is_empty(&self) -> bool {
+        self == &GenerationOptions::default()
+    }
+}
+
+/// Per-role chat settings.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RoleConfig {
+    /// Overrides the role's default model tier.
+    #[serde(default)]
+    pub tier: Option<ModelTier>,
+    /// Sent as a separate `system` message before the prompt.
+    #[serde(default)]
+    pub system: Option<String>,
+    #[serde(default)]
+    pub options: GenerationOptions,
+    /// How long Ollama keeps the model loaded after the call, e.g. "10m".
+    #[serde(default)]
+    pub keep_alive: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LlmSettings {
+    #[serde(default)]
+    pub backend: Backend,
+    /// Server root, e.g. "http://localhost:11434". Defaults to local Ollama.
+    #[serde(default)]
+    pub base_url: Option<String>,
+    #[serde(default)]
+    pub models: ModelRouting,
+    /// Pin the planner to temperature 0 and a fixed seed so plans are reproducible.
+    #[serde(default)]
+    pub deterministic: bool,
+    /// Use the old free-text JSON tool selector instead of native tool calling
+    /// (for models that don't support the `tools` field).
+    #[serde(default)]
+    pub legacy_tool_selector: bool,
+    /// Offer GIMP's raw `call_api` tool in native tool calling. It runs any
+    /// Python the model writes inside GIMP, so it's off unless asked for.
+    #[serde(default)]
+    pub allow_call_api: bool,
+    #[serde(default)]
+    pub selector: RoleConfig,
+    #[serde(default)]
+    pub planner: RoleConfig,
+    #[serde(default)]
+    pub answer: RoleConfig,
+    #[serde(default)]
+    pub vision: RoleConfig,
+    #[serde(default)]
+    pub warmup: WarmupSettings,
+}
+
+/// Start-up model loading, so the first request doesn't pay for a cold load.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WarmupSettings {
+    /// Pre-load the planner model when the app starts.
+    pub enabled: bool,
+    /// keep_alive sent with the warm-up request, e.g. "30m". Falls back to the planner's.
+    pub keep_alive: Option<String>,
+    /// Re-warm once the model has been idle this long. 0 disables re-warming.
+    pub rewarm_after_idle_secs: u64,
+    /// Run `ollama serve` ourselves if nothing answers on the base URL.
+    pub spawn_ollama: bool,
+}
+
+impl Default for WarmupSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            keep_alive: Some("30m".to_string()),
+            rewarm_after_idle_secs: 25 * 60,
+            spawn_ollama: false,
+        }
+    }
+}
+
+impl LlmSettings {
+    /// Effective config for a role, with deterministic mode applied.
+    pub fn role_config(&self, role: Role) -> RoleConfig {
+        let mut cfg = match role {
+            Role::Selector => self.selector.clone(),
+            Role::Planner => self.planner.clone(),
+            Role::Answer => self.answer.clone(),
+            Role::Vision => self.vision.clone(),
+        };
+        if self.deterministic && role == Role::Planner {
+            cfg.options.temperature = Some(0.0);
+            cfg.options.seed = Some(DETERMINISTIC_SEED);
+        }
+        cfg
+    }
+
+    /// The model a role is routed to.
+    pub fn model_for(&self, role: Role) -> String {
+        if role == Role::Vision {
+            return self.models.vision.clone().unwrap_or_else(|| VISION_MODEL_NAME.to_string());
+        }
+        let tier = self.role_config(role).tier.unwrap_or(role.default_tier());
+        self.model_for_tier(tier)
+    }
+
+    pub fn model_for_tier(&self, tier: ModelTier) -> String {
+        let model = match tier {
+            ModelTier::Small => &self.models.small,
+            ModelTier::Large => &self.models.large,
+        };
+        model.clone().unwrap_or_else(|| MODEL_NAME.to_string())
+    }
+
+    pub fn base_url(&self) -> String {
+        self.base_url
+            .as_deref()
+            .unwrap_or(DEFAULT_BASE_URL)
+            .trim_end_matches('/')
+            .to_string()
+    }
+}
+
+/// The options actually used for a call, recorded in plan output so a bad plan can be replayed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GenerationRecord {
+    pub model: String,
+    pub role: Role,
+    pub deterministic: bool,
+    pub options: GenerationOptions,
+}
+
+/// A validated LLM result and the model that produced it.
+#[derive(Debug, Clone)]
+pub struct Routed<T> {
+    pub value: T,
+    pub model: String,
+    /// True if the small model's output was rejected and the large model answered.
+    pub escalated: bool,
+}
+
+/// A function the model may call. `parameters` is a JSON Schema object.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolDefinition {
+    pub name: String,
+    pub description: String,
+    pub parameters: Value,
+}
+
+impl ToolDefinition {
+    /// Both Ollama and OpenAI use the `{ "type": "function", "function": {...} }` wrapper.
+    fn to_api(&self) -> Value {
+        json!({
+            "type": "function",
+            "function": {
+                "name": self.name,
+                "description": self.description,
+                "parameters": self.parameters,
+            }
+        })
+    }
+}
+
+/// A structured tool call returned by the model.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolCall {
+    /// Set by OpenAI-style servers; Ollama doesn't send one.
+    #[serde(default)]
+    pub id: Option<String>,
+    pub name: String,
+    #[serde(default)]
+    pub arguments: Value,
+}
+
+/// One message of a multi-turn conversation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: String,
+    pub content: String,
+    #[serde(default)]
+    pub images: Vec<String>,
+    /// Tool calls made by the assistant in this turn.
+    #[serde(default)]
+    pub tool_calls: Vec<ToolCall>,
+    /// For `tool` messages: the call this result answers.
+    #[serde(default)]
+    pub tool_call_id: Option<String>,
+    #[serde(default)]
+    pub tool_name: Option<String>,
+}
+
+impl ChatMessage {
+    pub fn new(role: &str, content: &str) -> Self {
+        Self {
+            role: role.to_string(),
+            content: content.to_string(),
+            images: Vec::new(),
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+            tool_name: None,
+        }
+    }
+
+    pub fn tool_result(call: &ToolCall, content: String) -> Self {
+        Self {
+            tool_call_id: call.id.clone(),
+            tool_name: Some(call.name.clone()),
+            ..Self::new("tool", &content)
+        }
+    }
+}
+
+/// The model's reply: plain text and/or tool calls.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatResponse {
+    pub content: String,
+    pub tool_calls: Vec<ToolCall>,
+}
+
+impl ChatResponse {
+    /// The assistant turn to append to the history before sending tool results.
+    pub fn to_message(&self) -> ChatMessage {
+        ChatMessage {
+            tool_calls: self.tool_calls.clone(),
+            ..ChatMessage::new("assistant", &self.content)
+        }
+    }
+}
+
+static SETTINGS: Lazy<Mutex<LlmSettings>> = Lazy::new(|| Mutex::new(LlmSettings::default()));
+
+/// When the model last did any work (chat or warm-up). Used to decide when to re-warm.
+static LAST_ACTIVITY: Lazy<Mutex<Instant>> = Lazy::new(|| Mutex::new(Instant::now()));
+
+fn mark_activity() {
+    if let Ok(mut last) = LAST_ACTIVITY.lock() {
+        *last = Instant::now();
+    }
+}
+
+/// When the model last did any work.
+pub fn last_activity() -> Instant {
+    LAST_ACTIVITY.lock().map(|last| *last).unwrap_or_else(|_| Instant::now())
+}
+
+/// Seconds since the model last did any work.
+pub fn idle_secs() -> u64 {
+    LAST_ACTIVITY
+        .lock()
+        .map(|last| last.elapsed().as_secs())
+        .unwrap_or(0)
+}
+
+pub fn settings() -> LlmSettings {
+    SETTINGS.lock().map(|s| s.clone()).unwrap_or_default()
+}
+
+/// Where settings are persisted. Unset (e.g. in tests) means in-memory only.
+static SETTINGS_PATH: Lazy<Mutex<Option<PathBuf>>> = Lazy::new(|| Mutex::new(None));
+
+pub fn set_settings(new_settings: LlmSettings) -> Result<(), String> {
+    let mut guard = SETTINGS
+        .lock()
+        .map_err(|_| "LLM settings mutex poisoned".to_string())?;
+
+    if let Some(path) = SETTINGS_PATH.lock().ok().and_then(|p| p.clone()) {
+        let json = serde_json::to_string_pretty(&new_settings)
+            .map_err(|e| format!("Failed to serialize LLM settings: {e}"))?;
+        std::fs::write(&path, json)
+            .map_err(|e| format!("Failed to save LLM settings to {}: {e}", path.display()))?;
+    }
+
+    *guard = new_settings;
+    Ok(())
+}
+
+/// Load settings saved by a previous run and persist future changes to `path`.
+pub fn init_settings_file(path: PathBuf) -> Result<(), String> {
+    if path.exists() {
+        let text = std::fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read LLM settings from {}: {e}", path.display()))?;
+        let loaded: LlmSettings = serde_json::from_str(&text)
+            .map_err(|e| format!("Invalid LLM settings in {}: {e}", path.display()))?;
+        let mut guard = SETTINGS
+            .lock()
+            .map_err(|_| "LLM settings mutex poisoned".to_string())?;
+        *guard = loaded;
+    }
+
+    let mut stored = SETTINGS_PATH
+        .lock()
+        .map_err(|_| "LLM settings path mutex poisoned".to_string())?;
+    *stored = Some(path);
+    Ok(())
+}
+
+/// Describe the generation settings a role would currently use.
+pub fn generation_record(role: Role) -> GenerationRecord {
+    let s = settings();
+    GenerationRecord {
+        model: s.model_for(role),
+        role,
+        deterministic: s.deterministic && role == Role::Planner,
+        options: s.role_config(role).options,
+    }
+}
+
+/// Simple chat call to the local LLM (Ollama), using the settings configured for `role`.
+pub async fn chat_as(role: Role, prompt: &str) -> Result<String, String> {
+    let model = settings().model_for(role);
+    let messages = vec![ChatMessage::new("user", prompt)];
+    let resp = send_chat(role, &model, messages, &[]).await?;
+    Ok(resp.content)
+}
+
+/// Chat call whose output must pass `validate`. If the role runs on the small
+/// model and its output is rejected, the same prompt is retried on the large model.
+pub async fn chat_validated<T>(
+    role: Role,
+    prompt: &str,
+    validate: impl Fn(&str) -> Result<T, String>,
+) -> Result<Routed<T>, String> {
+    let s = settings();
+    let model = s.model_for(role);
+    let messages = vec![ChatMessage::new("user", prompt)];
+
+    let raw = send_chat(role, &model, messages.clone(), &[]).await?.content;
+    let err = match validate(&raw) {
+        Ok(value) => return Ok(Routed { value, model, escalated: false }),
+        Err(e) => e,
     };
 
+    let large = s.model_for_tier(ModelTier::Large);
+    if large == model {
+        return Err(err);
+    }
+
+    eprintln!("[ROUTER] {role:?} output from {model} rejected, escalating to {large}: {err}");
+    let raw = send_chat(role, &large, messages, &[]).await?.content;
+    let value = validate(&raw)?;
+    Ok(Routed { value, model: large, escalated: true })
+}
+
+/// Ask the multimodal model about an image.
+/// `image_base64` is the encoded PNG/JPEG without a `data:` prefix.
+pub async fn describe_image(prompt: &str, image_base64: String) -> Result<String, String> {
+    let mut message = ChatMessage::new("user", prompt);
+    message.images.push(image_base64);
+    let model = settings().model_for(Role::Vision);
+    let resp = send_chat(Role::Vision, &model, vec![message], &[]).await?;
+    Ok(resp.content)
+}
+
+/// One turn of a tool-calling conversation. The caller executes any returned
+/// `tool_calls`, appends the results and calls again until none are left.
+pub async fn chat_with_tools(
+    role: Role,
+    messages: Vec<ChatMessage>,
+    tools: &[ToolDefinition],
+) -> Result<ChatResponse, String> {
+    let model = settings().model_for(role);
+    send_chat(role, &model, messages, tools).await
+}
+
+/// Embed `text` for similarity search (`ollama pull nomic-embed-text`).
+pub async fn embed(text: &str) -> Result<Vec<f32>, String> {
+    let s = settings();
+    let (url, body) = match s.backend {
+        Backend::Ollama => (
+            format!("{}/api/embed", s.base_url()),
+            json!({ "model": EMBED_MODEL_NAME, "input": text }),
+        ),
+        Backend::OpenAi => (
+            format!("{}/v1/embeddings", s.base_url()),
+            json!({ "model": EMBED_MODEL_NAME, "input": text }),
+        ),
+    };
+
+    let resp = reqwest::Client::new()
+        .post(&url)
+        .json(&body)
+        .send()
+        .await
+        .map_err(|e| format!("Failed to call embedding model: {e}"))?;
+
+    if !resp.status().is_success() {
+        return Err(format!("Embedding model returned HTTP {}", resp.status()));
+    }
+
+    let body: Value = resp
+        .json()
+        .await
+        .map_err(|e| format!("Failed to parse embedding response: {e}"))?;
+
+    // Ollama: { "embeddings": [[...]] }, OpenAI: { "data": [{ "embedding": [...] }] }
+    let vector = match s.backend {
+        Backend::Ollama => body
+            .get("embeddings")
+            .and_then(|e| e.as_array())
+            .and_then(|e| e.first()),
+        Backend::OpenAi => body
+            .get("data")
+            .and_then(|d| d.as_array())
+            .and_then(|d| d.first())
+            .and_then(|d| d.get("embedding")),
+    };
+
+    vector
+        .and_then(|v| v.as_array())
+        .map(|v| v.iter().filter_map(|x| x.as_f64()).map(|x| x as f32).collect())
+        .filter(|v: &Vec<f32>| !v.is_empty())
+        .ok_or_else(|| format!("Unexpected embedding response shape: {body}"))
+}
+
+/// Name of the planner model, for status messages.
+pub fn planner_model() -> String {
+    settings().model_for(Role::Planner)
+}
+
+/// True if the LLM server answers on its model-list endpoint.
+pub async fn server_reachable() -> bool {
+    let s = settings();
+    let url = match s.backend {
+        Backend::Ollama => format!("{}/api/tags", s.base_url()),
+        Backend::OpenAi => format!("{}/v1/models", s.base_url()),
+    };
+    match reqwest::get(&url).await {
+        Ok(resp) => resp.status().is_success(),
+        Err(_) => false,
+    }
+}
+
+/// Load the planner model into memory without generating anything.
+/// Ollama loads a model when it receives a chat request with no messages;
+/// `keep_alive` controls how long it stays loaded afterwards.
+/// OpenAI-style servers have no equivalent, so this is a no-op for them.
+pub async fn warm_up(keep_alive: Option<String>) -> Result<(), String> {
+    let s = settings();
+    if s.backend != Backend::Ollama {
+        return Ok(());
+    }
+
+    let keep_alive = keep_alive.or_else(|| s.role_config(Role::Planner).keep_alive);
+    let mut body = json!({ "model": s.model_for(Role::Planner), "messages": [], "stream": false });
+    if let Some(k) = keep_alive {
+        body["keep_alive"] = json!(k);
+    }
+
+    let resp = reqwest::Client::new()
+        .post(format!("{}/api/chat", s.base_url()))
+        .json(&body)
+        .send()
+        .await
+        .map_err(|e| format!("Failed to call LLM: {e}"))?;
+
+    if !resp.status().is_success() {
+        let status = resp.status();
+        let detail = resp.text().await.unwrap_or_default();
+        return Err(format!("LLM returned HTTP {status}: {detail}"));
+    }
+
+    mark_activity();
+    Ok(())
+}
+
+async fn send_chat(
+    role: Role,
+    model: &str,
+    mut messages: Vec<ChatMessage>,
+    tools: &[ToolDefinition],
+) -> Result<ChatResponse, String> {
+    let s = settings();
+    let cfg = s.role_config(role);
+
+    if let Some(system) = cfg.system.as_ref().filter(|s| !s.trim().is_empty()) {
+        if messages.first().is_none_or(|m| m.role != "system") {
+            messages.insert(0, ChatMessage::new("system", system));
+        }
+    }
+
+    let (url, body) = match s.backend {
+        Backend::Ollama => (
+            format!("{}/api/chat", s.base_url()),
+            ollama_request_body(model, messages, cfg, tools),
+        ),
+        Backend::OpenAi => (
+            format!("{}/v1/chat/completions", s.base_url()),
+            openai_request_body(model, messages, cfg, tools),
+        ),
+    };
+
+    eprintln!("[LLM] role={role:?} model={model}");
+
+    let client = reqwest::Client::new();
+
     let resp = client
-        .post(OLLAMA_URL)
-        .json(&req)
+        .post(&url)
+        .json(&body)
         .send()
         .await
         .map_err(|e| format!("Failed to call LLM: {e}"))?;
 
     if !resp.status().is_success() {
-        return Err(format!("LLM returned HTTP {}", resp.status()));
+        let status = resp.status();
+        let detail = resp.text().await.unwrap_or_default();
+        return Err(format!("LLM returned HTTP {status}: {detail}"));
     }
 
+    mark_activity();
+
     // Parse as generic JSON so extra fields don't break us
     let body: Value = resp
         .json()
         .await
         .map_err(|e| format!("Failed to parse LLM response: {e}"))?;
 
-    // Expect something like: { "message": { "content": "..." }, ... }
-    if let Some(content) = body
-        .get("message")
-        .and_then(|m| m.get("content"))
+    // Ollama: { "message": {...} }, OpenAI: { "choices": [{ "message": {...} }] }
+    let message = match s.backend {
+        Backend::Ollama => body.get("message"),
+        Backend::OpenAi => body
+            .get("choices")
+            .and_then(|c| c.as_array())
+            .and_then(|c| c.first())
+            .and_then(|c| c.get("message")),
+    };
+
+    let Some(message) = message else {
+        return Err(format!("Unexpected LLM response shape: {body}"));
+    };
+
+    let tool_calls = message
+        .get("tool_calls")
+        .and_then(|t| t.as_array())
+        .map(|calls| calls.iter().filter_map(parse_tool_call).collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    if tool_calls.is_empty() && message.get("content").and_then(|c| c.as_str()).is_none() {
+        return Err(format!("Unexpected LLM response shape: {body}"));
+    }
+
+    let content = message
+        .get("content")
         .and_then(|c| c.as_str())
-    {
-        Ok(content.to_string())
-    } else {
-        Err(format!("Unexpected LLM response shape: {body}"))
+        .unwrap_or("")
+        .to_string();
+
+    Ok(ChatResponse { content, tool_calls })
+}
+
+fn ollama_request_body(
+    model: &str,
+    messages: Vec<ChatMessage>,
+    cfg: RoleConfig,
+    tools: &[ToolDefinition],
+) -> Value {
+    let messages = messages
+        .into_iter()
+        .map(|m| OllamaMessage {
+            role: m.role,
+            content: m.content,
+            images: m.images,
+            tool_calls: m
+                .tool_calls
+                .iter()
+                .map(|c| json!({ "function": { "name": c.name, "arguments": c.arguments } }))
+                .collect(),
+            tool_name: m.tool_name,
+        })
+        .collect();
+
+    let req = OllamaRequest {
+        model: model.to_string(),
+        messages,
+        // Important: disable streaming so we get a single JSON object
+        stream: false,
+        options: cfg.options,
+        keep_alive: cfg.keep_alive,
+        tools: tools.iter().map(ToolDefinition::to_api).collect(),
+    };
+    json!(req)
+}
+
+fn openai_request_body(
+    model: &str,
+    messages: Vec<ChatMessage>,
+    cfg: RoleConfig,
+    tools: &[ToolDefinition],
+) -> Value {
+    let messages: Vec<Value> = messages
+        .into_iter()
+        .map(|m| {
+            let mut msg = json!({ "role": m.role, "content": m.content });
+            if !m.images.is_empty() {
+                // OpenAI takes images as content parts with data URLs
+                let mut parts = vec![json!({ "type": "text", "text": m.content })];
+                parts.extend(m.images.iter().map(|img| {
+                    json!({ "type": "image_url", "image_url": { "url": format!("data:image/png;base64,{img}") } })
+                }));
+                msg["content"] = json!(parts);
+            }
+            if !m.tool_calls.is_empty() {
+                msg["tool_calls"] = m
+                    .tool_calls
+                    .iter()
+                    .enumerate()
+                    .map(|(i, c)| {
+                        json!({
+                            "id": c.id.clone().unwrap_or_else(|| format!("call_{i}")),
+                            "type": "function",
+                            // OpenAI expects arguments as a JSON-encoded string
+                            "function": { "name": c.name, "arguments": c.arguments.to_string() }
+                        })
+                    })
+                    .collect();
+            }
+            if let Some(id) = m.tool_call_id {
+                msg["tool_call_id"] = json!(id);
+            }
+            msg
+        })
+        .collect();
+
+    let mut req = json!({
+        "model": model,
+        "messages": messages,
+        "stream": false,
+    });
+    let opts = cfg.options;
+    if let Some(t) = opts.temperature { req["temperature"] = json!(t); }
+    if let Some(p) = opts.top_p { req["top_p"] = json!(p); }
+    if let Some(seed) = opts.seed { req["seed"] = json!(seed); }
+    if !tools.is_empty() {
+        req["tools"] = tools.iter().map(ToolDefinition::to_api).collect();
+    }
+    req
+}
+
+/// Accepts both shapes: Ollama sends `arguments` as an object, OpenAI as a JSON string.
+fn parse_tool_call(call: &Value) -> Option<ToolCall> {
+    let function = call.get("function")?;
+    let name = function.get("name")?.as_str()?.to_string();
+    let arguments = match function.get("arguments") {
+        Some(Value::String(s)) => serde_json::from_str(s).unwrap_or_else(|_| json!({})),
+        Some(v) => v.clone(),
+        None => json!({}),
+    };
+    Some(ToolCall {
+        id: call.get("id").and_then(|i| i.as_str()).map(|s| s.to_string()),
+        name,
+        arguments,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_llm::MockLlm;
+
+    #[tokio::test]
+    async fn openai_backend_reads_choices() {
+        let mock = MockLlm::start_with(Backend::OpenAi);
+        mock.on(r"hello", "hi there");
+
+        let reply = chat_as(Role::Answer, "hello").await.unwrap();
+
+        assert_eq!(reply, "hi there");
+        assert_eq!(mock.prompts(), vec!["hello".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn rejected_small_model_output_escalates_to_large() {
+        let mock = MockLlm::start();
+        let mut settings = settings();
+        settings.models.small = Some("small".into());
+        settings.models.large = Some("large".into());
+        set_settings(settings).unwrap();
+        mock.on_model("small", r"classify", "not json");
+        mock.on_model("large", r"classify", r#"{"ok": true}"#);
+
+        let routed = chat_validated(Role::Selector, "classify this", |raw| {
+            serde_json::from_str::<Value>(raw).map_err(|e| e.to_string())
+        })
+        .await
+        .unwrap();
+
+        assert!(routed.escalated);
+        assert_eq!(routed.model, "large");
+        let models: Vec<Value> = mock.requests().iter().map(|r| r["model"].clone()).collect();
+        assert_eq!(models, vec![json!("small"), json!("large")]);
+    }
+
+    #[tokio::test]
+    async fn tool_calls_are_parsed_for_both_backends() {
+        for backend in [Backend::Ollama, Backend::OpenAi] {
+            let mock = MockLlm::start_with(backend);
+            mock.on_tool_call(r"blur", "blur", json!({ "radius": 4 }));
+            let tools = [ToolDefinition {
+                name: "blur".into(),
+                description: "Blur".into(),
+                parameters: json!({ "type": "object" }),
+            }];
+
+            let resp = chat_with_tools(Role::Planner, vec![ChatMessage::new("user", "blur it")], &tools)
+                .await
+                .unwrap();
+
+            assert_eq!(resp.tool_calls.len(), 1);
+            assert_eq!(resp.tool_calls[0].name, "blur");
+            assert_eq!(resp.tool_calls[0].arguments["radius"], 4);
+            assert_eq!(mock.requests()[0]["tools"][0]["function"]["name"], "blur");
+        }
     }
 }
@@ -1,4 +1,12 @@
 use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+use crate::color::Color;
+use crate::gegl_filters::FilterValue;
+use crate::plan_schema::{
+    BlendMode, Channel, DesaturateMode, ExportFormat, FillKind, FlipOrientation, GradientKind, HueRange, Interpolation, LayerFill, LayerPosition, RegionShape,
+    SelectionMode, ShapeKind, Target, TextAlign, ToneRange,
+};
 
 fn clamp_f64(x: f64, lo: f64, hi: f64) -> f64 {
     if x < lo { lo } else if x > hi { hi } else { x }
@@ -20,54 +28,95 @@ fn call_api_exec(python_lines: Vec<String>) -> Value {
     })
 }
 
-/// Save the current layer content to GIMP's clipboard so it can be restored by undo().
-/// Call this BEFORE making any changes.
-fn save_clipboard_lines() -> Vec<String> {
-    vec![
-        // If there is no active selection, Gimp.edit_copy copies the whole drawable.
-        "Gimp.edit_copy([layer])".to_string(),
-    ]
-}
-
 /// Standard setup lines shared by all drawing macros.
 /// `image.flatten()` is CRITICAL: it merges any floating selections or extra layers
 /// (e.g. left behind by blur_region) back into a single full-canvas layer, ensuring
 /// that w//2 always refers to the true centre of the drawable area.
 fn setup_lines(import_gegl: bool) -> Vec<String> {
+    target_setup_lines(&Target::Image, import_gegl)
+}
+
+/// Setup lines binding `image`, `w`, `h`, `layer` and `drawable` for a plan target.
+/// Only `Target::Image` flattens; region targets replace the selection, which
+/// `target_finish_lines` clears again.
+fn target_setup_lines(target: &Target, import_gegl: bool) -> Vec<String> {
     let import = if import_gegl {
         "from gi.repository import Gimp, Gegl".to_string()
     } else {
         "from gi.repository import Gimp".to_string()
     };
-    vec![
+    let mut lines = vec![
         import,
+        "if not Gimp.get_images(): raise Exception('No image is open in GIMP')".to_string(),
         "image = Gimp.get_images()[0]".to_string(),
+    ];
+
+    let active_layer = "layer = (image.get_selected_layers() or image.get_layers())[0]".to_string();
+    match target {
         // flatten() merges everything to one full-canvas layer AND returns that layer
-        "layer = image.flatten()".to_string(),
+        Target::Image => lines.push("layer = image.flatten()".to_string()),
+        // The executor runs all_layers steps once per layer index; on its own it
+        // means the active layer
+        Target::ActiveLayer | Target::AllLayers => lines.push(active_layer),
+        Target::Layer(name) => {
+            // A JSON string literal is also a valid Python one, quotes escaped
+            let name = serde_json::to_string(name).unwrap_or_default();
+            lines.push(format!(
+                "layer = next((l for l in image.get_layers() if l.get_name() == {name}), None)"
+            ));
+            lines.push(format!("if layer is None: raise Exception('No layer named ' + {name})"));
+        }
+        Target::LayerIndex(index) => lines.push(format!("layer = image.get_layers()[{index}]")),
+        Target::Selection => {
+            lines.push(active_layer);
+            lines.push("if Gimp.Selection.is_empty(image): raise Exception('Nothing is selected')".to_string());
+        }
+        Target::Region(region) => {
+            lines.push(active_layer);
+            let select = match region.shape {
+                RegionShape::Rect => "select_rectangle",
+                RegionShape::Ellipse => "select_ellipse",
+            };
+            lines.push(format!(
+                "Gimp.Image.{select}(image, Gimp.ChannelOps.REPLACE, {}, {}, {}, {})",
+                region.x, region.y, region.width, region.height
+            ));
+        }
+    }
+
+    lines.extend(vec![
         "w = image.get_width()".to_string(),
         "h = image.get_height()".to_string(),
         "drawable = layer".to_string(),
-    ]
+    ]);
+    lines
+}
+
+/// Lines that undo the temporary selection a region target made.
+fn target_finish_lines(target: &Target) -> Vec<String> {
+    match target {
+        Target::Region(_) => vec!["Gimp.Selection.none(image)".to_string()],
+        _ => vec![],
+    }
 }
 
 /// Draw a line between specific coordinates using Gimp.pencil (GIMP 3).
-pub fn draw_line(x1: i32, y1: i32, x2: i32, y2: i32) -> Value {
-    let mut python_lines = setup_lines(true);
-    python_lines.extend(save_clipboard_lines());
+pub fn draw_line(x1: i32, y1: i32, x2: i32, y2: i32, color: &Color, target: &Target) -> Value {
+    let mut python_lines = target_setup_lines(target, true);
     python_lines.extend(vec![
         "layer.add_alpha() if not layer.has_alpha() else None".to_string(),
-        "black = Gegl.Color.new('black')".to_string(),
-        "Gimp.context_set_foreground(black)".to_string(),
+        format!("line_color = Gegl.Color.new('{}')", color.to_hex()),
+        "Gimp.context_set_foreground(line_color)".to_string(),
         format!("Gimp.pencil(drawable, [{}, {}, {}, {}])", x1, y1, x2, y2),
-        "Gimp.displays_flush()".to_string(),
     ]);
+    python_lines.extend(target_finish_lines(target));
+    python_lines.push("Gimp.displays_flush()".to_string());
     call_api_exec(python_lines)
 }
 
 /// Draw a black line from corner to corner across the entire image.
 pub fn draw_line_across_image() -> Value {
     let mut python_lines = setup_lines(true);
-    python_lines.extend(save_clipboard_lines());
     python_lines.extend(vec![
         "layer.add_alpha() if not layer.has_alpha() else None".to_string(),
         "black = Gegl.Color.new('black')".to_string(),
@@ -79,12 +128,10 @@ pub fn draw_line_across_image() -> Value {
 }
 
 /// Draw a filled heart shape in the centre of the image.
-/// `color` is a CSS color name (red, pink, blue…) or rgb(r,g,b).
-pub fn draw_heart(color: &str) -> Value {
+pub fn draw_heart(color: &Color) -> Value {
     let mut python_lines = setup_lines(true);
-    python_lines.extend(save_clipboard_lines());
     python_lines.extend(vec![
-        format!("fill_color = Gegl.Color.new('{}')", color),
+        format!("fill_color = Gegl.Color.new('{}')", color.to_hex()),
         "Gimp.context_set_foreground(fill_color)".to_string(),
         "cx = w // 2".to_string(),
         "cy = h // 2".to_string(),
@@ -105,42 +152,61 @@ pub fn draw_heart(color: &str) -> Value {
     call_api_exec(python_lines)
 }
 
-/// Undo the last drawing change.
-///
-/// This does NOT use GIMP's built-in undo stack (which doesn't work from within
-/// a long-running plugin). Instead it restores the clipboard content that was
-/// saved by each draw macro before making changes.
-pub fn undo() -> Value {
+pub fn crop_to_square() -> Value {
     let python_lines = vec![
         "from gi.repository import Gimp".to_string(),
         "image = Gimp.get_images()[0]".to_string(),
-        // Flatten first so we have a single full-canvas layer to paste onto
-        "layer = image.flatten()".to_string(),
+        "image.flatten()".to_string(),
         "w = image.get_width()".to_string(),
         "h = image.get_height()".to_string(),
-        // Select the full layer so the paste covers everything
-        "Gimp.Image.select_rectangle(image, Gimp.ChannelOps.REPLACE, 0, 0, w, h)".to_string(),
-        // Paste the saved state back (paste_into=True to replace the selection area)
-        "floating_sel = Gimp.edit_paste(layer, True)[0]".to_string(),
-        "Gimp.floating_sel_anchor(floating_sel)".to_string(),
-        "Gimp.Selection.none(image)".to_string(),
+        "size = min(w, h)".to_string(),
+        "x_offset = (w - size) // 2".to_string(),
+        "y_offset = (h - size) // 2".to_string(),
+        // FIXED: Correct GIMP 3 parameter order (new_width, new_height, offx, offy)
+        "image.crop(size, size, x_offset, y_offset)".to_string(),
         "Gimp.displays_flush()".to_string(),
     ];
     call_api_exec(python_lines)
 }
 
-pub fn crop_to_square() -> Value {
+/// Crop the image (all layers) to a rectangle in image pixels.
+pub fn crop(x: i32, y: i32, width: i32, height: i32) -> Value {
     let python_lines = vec![
         "from gi.repository import Gimp".to_string(),
         "image = Gimp.get_images()[0]".to_string(),
-        "image.flatten()".to_string(),
-        "w = image.get_width()".to_string(),
-        "h = image.get_height()".to_string(),
-        "size = min(w, h)".to_string(),
-        "x_offset = (w - size) // 2".to_string(),
-        "y_offset = (h - size) // 2".to_string(),
-        // FIXED: Correct GIMP 3 parameter order (new_width, new_height, offx, offy)
-        "image.crop(size, size, x_offset, y_offset)".to_string(),
+        // GIMP 3 parameter order: new_width, new_height, offx, offy
+        format!("image.crop({}, {}, {}, {})", width.max(1), height.max(1), x, y),
+        "Gimp.displays_flush()".to_string(),
+    ];
+    call_api_exec(python_lines)
+}
+
+/// Trim borders of uniform color with GIMP's Autocrop Image.
+pub fn auto_crop() -> Value {
+    let python_lines = vec![
+        "from gi.repository import Gimp".to_string(),
+        "image = Gimp.get_images()[0]".to_string(),
+        "layer = (image.get_selected_layers() or image.get_layers())[0]".to_string(),
+        "proc = Gimp.get_pdb().lookup_procedure('plug-in-autocrop')".to_string(),
+        "cfg = proc.create_config()".to_string(),
+        "cfg.set_property('run-mode', Gimp.RunMode.NONINTERACTIVE)".to_string(),
+        "cfg.set_property('image', image)".to_string(),
+        "cfg.set_core_object_array('drawables', [layer])".to_string(),
+        "proc.run(cfg)".to_string(),
+        "Gimp.displays_flush()".to_string(),
+    ];
+    call_api_exec(python_lines)
+}
+
+/// Crop the image to the bounding box of the current selection.
+pub fn crop_to_selection() -> Value {
+    let python_lines = vec![
+        "from gi.repository import Gimp".to_string(),
+        "image = Gimp.get_images()[0]".to_string(),
+        "_ok, non_empty, x1, y1, x2, y2 = Gimp.Selection.bounds(image)".to_string(),
+        "if not non_empty: raise Exception('Nothing is selected')".to_string(),
+        "image.crop(x2 - x1, y2 - y1, x1, y1)".to_string(),
+        "Gimp.Selection.none(image)".to_string(),
         "Gimp.displays_flush()".to_string(),
     ];
     call_api_exec(python_lines)
@@ -163,26 +229,75 @@ pub fn resize_width(width: i32) -> Value {
     call_api_exec(python_lines)
 }
 
-pub fn brightness_contrast(brightness: f64, contrast: f64) -> Value {
+/// Scale the image to `scale_width`×`scale_height`, then centre-crop to
+/// `width`×`height` when that is smaller (fill-and-crop).
+pub fn resize(
+    scale_width: i32,
+    scale_height: i32,
+    width: i32,
+    height: i32,
+    interpolation: Interpolation,
+) -> Value {
+    let mut python_lines = vec![
+        "from gi.repository import Gimp".to_string(),
+        "image = Gimp.get_images()[0]".to_string(),
+        format!("Gimp.context_set_interpolation({})", interpolation.gimp_enum()),
+        format!("image.scale({}, {})", scale_width.max(1), scale_height.max(1)),
+    ];
+    if width < scale_width || height < scale_height {
+        python_lines.push(format!(
+            "image.crop({}, {}, {}, {})",
+            width.max(1), height.max(1), (scale_width - width) / 2, (scale_height - height) / 2
+        ));
+    }
+    python_lines.push("Gimp.displays_flush()".to_string());
+    call_api_exec(python_lines)
+}
+
+/// Grow the canvas by the given margins. Layers keep their pixels and are only
+/// moved; the new area shows a full-canvas `color` layer added at the bottom
+/// (none for a transparent color).
+pub fn pad(left: i32, top: i32, right: i32, bottom: i32, color: &Color) -> Value {
+    let (left, top, right, bottom) = (left.max(0), top.max(0), right.max(0), bottom.max(0));
+    let mut python_lines = vec![
+        "from gi.repository import Gimp, Gegl".to_string(),
+        "image = Gimp.get_images()[0]".to_string(),
+        format!("w = image.get_width() + {}", left.saturating_add(right)),
+        format!("h = image.get_height() + {}", top.saturating_add(bottom)),
+        format!("image.resize(w, h, {left}, {top})"),
+    ];
+    if color.a > 0 {
+        python_lines.extend(vec![
+            "pad_layer = Gimp.Layer.new(image, 'Padding', w, h, image.get_layers()[0].type_with_alpha(), 100.0, Gimp.LayerMode.NORMAL)".to_string(),
+            "image.insert_layer(pad_layer, None, len(image.get_layers()))".to_string(),
+            format!("fill_color = Gegl.Color.new('{}')", color.to_hex()),
+            "Gimp.context_set_foreground(fill_color)".to_string(),
+            "pad_layer.fill(Gimp.FillType.FOREGROUND)".to_string(),
+        ]);
+    }
+    python_lines.push("Gimp.displays_flush()".to_string());
+    call_api_exec(python_lines)
+}
+
+pub fn brightness_contrast(brightness: f64, contrast: f64, target: &Target) -> Value {
     // In GIMP 3, brightness_contrast takes floats in -1.0..1.0 (not -127..127).
     // Callers pass values in -127..127 range, so we normalise here.
     let b = clamp_f64(brightness / 127.0, -1.0, 1.0);
     let c = clamp_f64(contrast   / 127.0, -1.0, 1.0);
-    let mut python_lines = setup_lines(false);
-    python_lines.extend(save_clipboard_lines());
+    let mut python_lines = target_setup_lines(target, false);
     python_lines.extend(vec![
         format!("drawable.brightness_contrast({:.4}, {:.4})", b, c),
-        "Gimp.displays_flush()".to_string(),
     ]);
+    python_lines.extend(target_finish_lines(target));
+    python_lines.push("Gimp.displays_flush()".to_string());
     call_api_exec(python_lines)
 }
 
-pub fn blur(radius: f64) -> Value {
+pub fn blur(radius: f64, target: &Target) -> Value {
     // In GIMP 3, plug-in-gauss is gone. Use DrawableFilter with gegl:gaussian-blur,
     // then merge it so the change is destructive (baked into the layer).
     let std_dev = (radius / 3.0).max(1.0); // convert radius to std-dev approx
-    let mut python_lines = setup_lines(false);
-    python_lines.extend(save_clipboard_lines());
+    let mut python_lines = target_setup_lines(target, false);
     python_lines.extend(vec![
         format!("_f = Gimp.DrawableFilter.new(drawable, 'gegl:gaussian-blur', 'blur')"),
         format!("_f.get_config().set_property('std-dev-x', {:.1})", std_dev),
@@ -190,18 +305,211 @@ pub fn blur(radius: f64) -> Value {
         "_f.set_opacity(1.0)".to_string(),
         "drawable.append_filter(_f)".to_string(),
         "drawable.merge_filters()".to_string(),
-        "Gimp.displays_flush()".to_string(),
     ]);
+    python_lines.extend(target_finish_lines(target));
+    python_lines.push("Gimp.displays_flush()".to_string());
+    call_api_exec(python_lines)
+}
+
+/// Remove color, keeping the image in RGB mode.
+pub fn desaturate(mode: DesaturateMode, target: &Target) -> Value {
+    let mut python_lines = target_setup_lines(target, false);
+    python_lines.extend(vec![
+        format!("drawable.desaturate({})", mode.gimp_enum()),
+    ]);
+    python_lines.extend(target_finish_lines(target));
+    python_lines.push("Gimp.displays_flush()".to_string());
+    call_api_exec(python_lines)
+}
+
+/// Hue (-180..180), lightness and saturation (-100..100) for one hue range or all.
+pub fn hue_saturation(range: HueRange, hue: f64, lightness: f64, saturation: f64, target: &Target) -> Value {
+    let hue = clamp_f64(hue, -180.0, 180.0);
+    let lightness = clamp_f64(lightness, -100.0, 100.0);
+    let saturation = clamp_f64(saturation, -100.0, 100.0);
+    let mut python_lines = target_setup_lines(target, false);
+    python_lines.extend(vec![
+        // Last argument is the overlap between neighbouring ranges; GIMP's default is 0
+        format!(
+            "drawable.hue_saturation({}, {:.2}, {:.2}, {:.2}, 0.0)",
+            range.gimp_enum(), hue, lightness, saturation
+        ),
+    ]);
+    python_lines.extend(target_finish_lines(target));
+    python_lines.push("Gimp.displays_flush()".to_string());
+    call_api_exec(python_lines)
+}
+
+pub fn invert(target: &Target) -> Value {
+    let mut python_lines = target_setup_lines(target, false);
+    python_lines.extend(vec![
+        // linear=False inverts in perceptual space, like Colors → Invert
+        "drawable.invert(False)".to_string(),
+    ]);
+    python_lines.extend(target_finish_lines(target));
+    python_lines.push("Gimp.displays_flush()".to_string());
+    call_api_exec(python_lines)
+}
+
+/// Pixels with a value in `low..=high` (0..255) become white, the rest black.
+pub fn threshold(low: i32, high: i32, target: &Target) -> Value {
+    // GIMP 3 takes thresholds as 0.0..1.0
+    let lo = clamp_i32(low, 0, 255) as f64 / 255.0;
+    let hi = clamp_i32(high, 0, 255) as f64 / 255.0;
+    let mut python_lines = target_setup_lines(target, false);
+    python_lines.extend(vec![
+        format!("drawable.threshold(Gimp.HistogramChannel.VALUE, {:.4}, {:.4})", lo, hi),
+    ]);
+    python_lines.extend(target_finish_lines(target));
+    python_lines.push("Gimp.displays_flush()".to_string());
+    call_api_exec(python_lines)
+}
+
+pub fn posterize(levels: i32, target: &Target) -> Value {
+    let levels = clamp_i32(levels, 2, 255);
+    let mut python_lines = target_setup_lines(target, false);
+    python_lines.extend(vec![
+        format!("drawable.posterize({})", levels),
+    ]);
+    python_lines.extend(target_finish_lines(target));
+    python_lines.push("Gimp.displays_flush()".to_string());
+    call_api_exec(python_lines)
+}
+
+/// Run `lines` on the target drawable, saving it for undo first.
+fn adjust_exec(target: &Target, lines: Vec<String>) -> Value {
+    let mut python_lines = target_setup_lines(target, false);
+    python_lines.extend(lines);
+    python_lines.extend(target_finish_lines(target));
+    python_lines.push("Gimp.displays_flush()".to_string());
+    call_api_exec(python_lines)
+}
+
+/// Levels on one channel; `input`/`output` are (black, white) in 0–255.
+pub fn levels(channel: Channel, input: (f64, f64), gamma: f64, output: (f64, f64), target: &Target) -> Value {
+    // GIMP 3 takes levels as 0..1
+    let unit = |v: f64| clamp_f64(v, 0.0, 255.0) / 255.0;
+    adjust_exec(target, vec![format!(
+        "drawable.levels({}, {:.4}, {:.4}, True, {:.3}, {:.4}, {:.4}, True)",
+        channel.gimp_enum(), unit(input.0), unit(input.1), clamp_f64(gamma, 0.1, 10.0), unit(output.0), unit(output.1)
+    )])
+}
+
+/// Curves on one channel through `(input, output)` points in 0–255.
+pub fn curves(channel: Channel, points: &[(f64, f64)], target: &Target) -> Value {
+    let coords: Vec<String> = points
+        .iter()
+        .map(|(x, y)| format!("{:.4}, {:.4}", clamp_f64(*x, 0.0, 255.0) / 255.0, clamp_f64(*y, 0.0, 255.0) / 255.0))
+        .collect();
+    adjust_exec(target, vec![format!("drawable.curves_spline({}, [{}])", channel.gimp_enum(), coords.join(", "))])
+}
+
+/// Color balance of one tonal range; `shifts` are cyan–red, magenta–green and
+/// yellow–blue in -100..100.
+pub fn color_balance(range: ToneRange, shifts: [f64; 3], preserve_luminosity: bool, target: &Target) -> Value {
+    let [cyan_red, magenta_green, yellow_blue] = shifts.map(|s| clamp_f64(s, -100.0, 100.0));
+    adjust_exec(target, vec![format!(
+        "drawable.color_balance({}, {}, {cyan_red:.1}, {magenta_green:.1}, {yellow_blue:.1})",
+        range.gimp_enum(), if preserve_luminosity { "True" } else { "False" }
+    )])
+}
+
+/// Correct from `original` to `intended` color temperature (Kelvin).
+pub fn color_temperature(original: f64, intended: f64, target: &Target) -> Value {
+    adjust_exec(target, vec![
+        "_f = Gimp.DrawableFilter.new(drawable, 'gegl:color-temperature', 'color temperature')".to_string(),
+        format!("_f.get_config().set_property('original-temperature', {:.1})", clamp_f64(original, 1000.0, 12000.0)),
+        format!("_f.get_config().set_property('intended-temperature', {:.1})", clamp_f64(intended, 1000.0, 12000.0)),
+        "drawable.append_filter(_f)".to_string(),
+        "drawable.merge_filters()".to_string(),
+    ])
+}
+
+/// Stretch each channel's histogram to the full range.
+pub fn stretch_contrast(target: &Target) -> Value {
+    adjust_exec(target, vec!["drawable.levels_stretch()".to_string()])
+}
+
+/// Apply a registry-checked GEGL operation to the target and merge it into the
+/// layer, the way `blur` does.
+pub fn gegl_filter(operation: &str, properties: &BTreeMap<String, FilterValue>, target: &Target) -> Value {
+    let mut python_lines = target_setup_lines(target, true);
+    python_lines.push(format!("_f = Gimp.DrawableFilter.new(drawable, {}, 'assistant filter')", py_str(operation)));
+    for (name, value) in properties {
+        let value = match value {
+            FilterValue::Bool(b) => if *b { "True".to_string() } else { "False".to_string() },
+            FilterValue::Int(i) => i.to_string(),
+            FilterValue::Float(f) => format!("{f:.4}"),
+            FilterValue::Color(c) => format!("Gegl.Color.new('{}')", c.to_hex()),
+        };
+        python_lines.push(format!("_f.get_config().set_property({}, {value})", py_str(name)));
+    }
+    python_lines.extend(vec![
+        "_f.set_opacity(1.0)".to_string(),
+        "drawable.append_filter(_f)".to_string(),
+        "drawable.merge_filters()".to_string(),
+    ]);
+    python_lines.extend(target_finish_lines(target));
+    python_lines.push("Gimp.displays_flush()".to_string());
+    call_api_exec(python_lines)
+}
+
+/// Rotate the whole image clockwise by `degrees` (0..360).
+/// Quarter turns use `image.rotate` (lossless); other angles rotate every layer
+/// around the image centre with the given interpolation, keeping the layers
+/// apart, and grow the canvas to fit the rotated corners.
+pub fn rotate(degrees: f64, interpolation: Interpolation) -> Value {
+    let quarter = match degrees.round() as i32 {
+        90 if (degrees - 90.0).abs() < 0.01 => Some("DEGREES90"),
+        180 if (degrees - 180.0).abs() < 0.01 => Some("DEGREES180"),
+        270 if (degrees - 270.0).abs() < 0.01 => Some("DEGREES270"),
+        _ => None,
+    };
+
+    let python_lines = match quarter {
+        Some(rotation) => vec![
+            "from gi.repository import Gimp".to_string(),
+            "image = Gimp.get_images()[0]".to_string(),
+            format!("image.rotate(Gimp.RotationType.{})", rotation),
+            "Gimp.displays_flush()".to_string(),
+        ],
+        None => vec![
+            "from gi.repository import Gimp".to_string(),
+            "import math".to_string(),
+            "if not Gimp.get_images(): raise Exception('No image is open in GIMP')".to_string(),
+            "image = Gimp.get_images()[0]".to_string(),
+            format!("Gimp.context_set_interpolation({})", interpolation.gimp_enum()),
+            "Gimp.context_set_transform_resize(Gimp.TransformResize.ADJUST)".to_string(),
+            "cx = image.get_width() / 2".to_string(),
+            "cy = image.get_height() / 2".to_string(),
+            // Group layers carry their children along
+            format!(
+                "[l.transform_rotate(math.radians({:.4}), False, cx, cy) for l in image.get_layers()]",
+                degrees
+            ),
+            "image.resize_to_layers()".to_string(),
+            "Gimp.displays_flush()".to_string(),
+        ],
+    };
+    call_api_exec(python_lines)
+}
+
+/// Mirror the whole image.
+pub fn flip(orientation: FlipOrientation) -> Value {
+    let python_lines = vec![
+        "from gi.repository import Gimp".to_string(),
+        "image = Gimp.get_images()[0]".to_string(),
+        format!("image.flip({})", orientation.gimp_enum()),
+        "Gimp.displays_flush()".to_string(),
+    ];
     call_api_exec(python_lines)
 }
 
 /// Draw a filled circle centred in the image.
-/// `color` is a CSS colour name (red, blue, green, …) or rgb(r,g,b).
-pub fn draw_circle(color: &str) -> Value {
+pub fn draw_circle(color: &Color) -> Value {
     let mut python_lines = setup_lines(true);
-    python_lines.extend(save_clipboard_lines());
     python_lines.extend(vec![
-        format!("fill_color = Gegl.Color.new('{}')", color),
+        format!("fill_color = Gegl.Color.new('{}')", color.to_hex()),
         "Gimp.context_set_foreground(fill_color)".to_string(),
         "r = min(w, h) // 3".to_string(),
         "cx = w // 2".to_string(),
@@ -215,12 +523,10 @@ pub fn draw_circle(color: &str) -> Value {
 }
 
 /// Draw a filled oval/ellipse centred in the image (wider than tall).
-/// `color` is a CSS colour name or rgb(r,g,b).
-pub fn draw_oval(color: &str) -> Value {
+pub fn draw_oval(color: &Color) -> Value {
     let mut python_lines = setup_lines(true);
-    python_lines.extend(save_clipboard_lines());
     python_lines.extend(vec![
-        format!("fill_color = Gegl.Color.new('{}')", color),
+        format!("fill_color = Gegl.Color.new('{}')", color.to_hex()),
         "Gimp.context_set_foreground(fill_color)".to_string(),
         "ew = w * 2 // 3".to_string(),
         "eh = h // 3".to_string(),
@@ -235,12 +541,10 @@ pub fn draw_oval(color: &str) -> Value {
 }
 
 /// Draw a filled triangle (staircase approximation) pointing upward in the centre.
-/// `color` is a CSS colour name or rgb(r,g,b).
-pub fn draw_triangle(color: &str) -> Value {
+pub fn draw_triangle(color: &Color) -> Value {
     let mut python_lines = setup_lines(true);
-    python_lines.extend(save_clipboard_lines());
     python_lines.extend(vec![
-        format!("fill_color = Gegl.Color.new('{}')", color),
+        format!("fill_color = Gegl.Color.new('{}')", color.to_hex()),
         "Gimp.context_set_foreground(fill_color)".to_string(),
         "cx = w // 2".to_string(),
         "s = min(w, h) // 3".to_string(),
@@ -260,6 +564,530 @@ pub fn draw_triangle(color: &str) -> Value {
     call_api_exec(python_lines)
 }
 
+/// Python lines that replace the selection with `kind` inside the given bbox.
+fn shape_selection_lines(kind: ShapeKind, x: i32, y: i32, width: i32, height: i32) -> Vec<String> {
+    match kind {
+        ShapeKind::Rectangle => vec![format!(
+            "Gimp.Image.select_rectangle(image, Gimp.ChannelOps.REPLACE, {x}, {y}, {width}, {height})"
+        )],
+        ShapeKind::Ellipse | ShapeKind::Circle => vec![format!(
+            "Gimp.Image.select_ellipse(image, Gimp.ChannelOps.REPLACE, {x}, {y}, {width}, {height})"
+        )],
+        ShapeKind::Triangle => vec![format!(
+            "Gimp.Image.select_polygon(image, Gimp.ChannelOps.REPLACE, [{}, {}, {}, {}, {}, {}])",
+            x + width / 2, y, x + width, y + height, x, y + height
+        )],
+        ShapeKind::Heart => {
+            // Two lobes across the top half, a point at the bottom centre
+            let (hw, hh) = (width / 2, height / 2);
+            let lobe_mid = y + height * 3 / 10;
+            vec![
+                format!("Gimp.Image.select_ellipse(image, Gimp.ChannelOps.REPLACE, {x}, {y}, {hw}, {hh})"),
+                format!("Gimp.Image.select_ellipse(image, Gimp.ChannelOps.ADD, {}, {y}, {hw}, {hh})", x + hw),
+                format!(
+                    "Gimp.Image.select_polygon(image, Gimp.ChannelOps.ADD, [{x}, {lobe_mid}, {}, {lobe_mid}, {}, {}])",
+                    x + width, x + hw, y + height
+                ),
+            ]
+        }
+    }
+}
+
+/// Draw a shape inside the bbox (image pixels), filled or as an outline. The
+/// shape is drawn through a selection, so the image's selection is parked in
+/// a channel meanwhile and put back afterwards.
+#[allow(clippy::too_many_arguments)]
+pub fn draw_shape(
+    kind: ShapeKind,
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+    color: &Color,
+    fill: bool,
+    stroke_width: f64,
+    target: &Target,
+) -> Value {
+    let mut python_lines = target_setup_lines(target, true);
+    python_lines.extend(vec![
+        "_saved_sel = Gimp.Selection.save(image)".to_string(),
+        format!("fill_color = Gegl.Color.new('{}')", color.to_hex()),
+        "Gimp.context_set_foreground(fill_color)".to_string(),
+    ]);
+    python_lines.extend(shape_selection_lines(kind, x, y, width, height));
+    if fill {
+        python_lines.push("Gimp.Drawable.edit_fill(drawable, Gimp.FillType.FOREGROUND)".to_string());
+    } else {
+        python_lines.extend(vec![
+            "Gimp.context_set_stroke_method(Gimp.StrokeMethod.LINE)".to_string(),
+            format!("Gimp.context_set_line_width({:.1})", clamp_f64(stroke_width, 1.0, 200.0)),
+            "Gimp.Drawable.edit_stroke_selection(drawable)".to_string(),
+        ]);
+    }
+    python_lines.extend(vec![
+        "image.select_item(Gimp.ChannelOps.REPLACE, _saved_sel)".to_string(),
+        "image.remove_channel(_saved_sel)".to_string(),
+    ]);
+    python_lines.extend(target_finish_lines(target));
+    python_lines.push("Gimp.displays_flush()".to_string());
+    call_api_exec(python_lines)
+}
+
+/// Print the installed font names as a JSON array.
+pub fn list_fonts() -> Value {
+    call_api_exec(vec![
+        "import json".to_string(),
+        "from gi.repository import Gimp".to_string(),
+        "print(json.dumps([f.get_name() for f in Gimp.fonts_get_list('')]))".to_string(),
+    ])
+}
+
+/// Print how many images GIMP has open.
+pub fn count_images() -> Value {
+    call_api_exec(vec![
+        "from gi.repository import Gimp".to_string(),
+        "print(len(Gimp.get_images()))".to_string(),
+    ])
+}
+
+/// Add `text` as a new text layer above the target layer. The text box's point at
+/// `anchor` (0–1 of its size) sits at `at`, pulled `margin` in from the edges it
+/// is anchored to, so "bottom" text ends up just above the bottom edge.
+#[allow(clippy::too_many_arguments)]
+pub fn add_text(
+    text: &str,
+    font: &str,
+    size: i32,
+    color: &Color,
+    align: TextAlign,
+    at: (i32, i32),
+    anchor: (f64, f64),
+    margin: i32,
+    target: &Target,
+) -> Value {
+    // A text layer is its own layer: never flatten for it
+    let target = if matches!(target, Target::Image) { &Target::ActiveLayer } else { target };
+    // JSON string literals are valid Python ones, quotes and newlines escaped
+    let text = serde_json::to_string(text).unwrap_or_default();
+    let font = serde_json::to_string(font).unwrap_or_default();
+    let (fx, fy) = anchor;
+    let mut python_lines = target_setup_lines(target, true);
+    python_lines.extend(vec![
+        format!("font = Gimp.Font.get_by_name({font})"),
+        format!("if font is None: raise Exception('Font not found: ' + {font})"),
+        format!("text_layer = Gimp.TextLayer.new(image, {text}, font, {}, Gimp.Unit.pixel())", size.max(1)),
+        "image.insert_layer(text_layer, layer.get_parent(), image.get_item_position(layer))".to_string(),
+        format!("text_color = Gegl.Color.new('{}')", color.to_hex()),
+        "text_layer.set_color(text_color)".to_string(),
+        format!("text_layer.set_justification({})", align.gimp_enum()),
+        "tw = text_layer.get_width()".to_string(),
+        "th = text_layer.get_height()".to_string(),
+        format!(
+            "text_layer.set_offsets(int({} - tw * {fx:.3} + {margin} * {:.3}), int({} - th * {fy:.3} + {margin} * {:.3}))",
+            at.0, 1.0 - 2.0 * fx, at.1, 1.0 - 2.0 * fy
+        ),
+        "Gimp.displays_flush()".to_string(),
+    ]);
+    call_api_exec(python_lines)
+}
+
+// ---------------------------------------------------------------------------
+// Layer management: these bind `layer` from the target and never flatten.
+// ---------------------------------------------------------------------------
+
+/// Run `lines` on the target layer, then refresh the display.
+fn layer_exec(target: &Target, lines: Vec<String>) -> Value {
+    let mut python_lines = target_setup_lines(target, false);
+    python_lines.extend(lines);
+    python_lines.push("Gimp.displays_flush()".to_string());
+    call_api_exec(python_lines)
+}
+
+/// A JSON string literal, which is also a valid Python one.
+fn py_str(s: &str) -> String {
+    serde_json::to_string(s).unwrap_or_default()
+}
+
+/// Create a canvas-sized layer above the target layer and make it active.
+pub fn new_layer(name: &str, fill: LayerFill, opacity: f64, blend_mode: BlendMode, target: &Target) -> Value {
+    layer_exec(target, vec![
+        format!(
+            "new_layer = Gimp.Layer.new(image, {}, w, h, layer.type_with_alpha(), {:.1}, {})",
+            py_str(name), clamp_f64(opacity, 0.0, 100.0), blend_mode.gimp_enum()
+        ),
+        "image.insert_layer(new_layer, layer.get_parent(), image.get_item_position(layer))".to_string(),
+        format!("new_layer.fill({})", fill.gimp_enum()),
+        "image.set_selected_layers([new_layer])".to_string(),
+    ])
+}
+
+/// Copy the target layer into a new layer directly above it.
+pub fn duplicate_layer(name: Option<&str>, target: &Target) -> Value {
+    let mut lines = vec![
+        "copy = layer.copy()".to_string(),
+        "image.insert_layer(copy, layer.get_parent(), image.get_item_position(layer))".to_string(),
+    ];
+    if let Some(name) = name {
+        lines.push(format!("copy.set_name({})", py_str(name)));
+    }
+    lines.push("image.set_selected_layers([copy])".to_string());
+    layer_exec(target, lines)
+}
+
+pub fn delete_layer(target: &Target) -> Value {
+    layer_exec(target, vec!["image.remove_layer(layer)".to_string()])
+}
+
+pub fn rename_layer(name: &str, target: &Target) -> Value {
+    layer_exec(target, vec![format!("layer.set_name({})", py_str(name))])
+}
+
+/// Move the target layer within its stack; index 0 is the top.
+pub fn move_layer(to: LayerPosition, target: &Target) -> Value {
+    let line = match to {
+        LayerPosition::Up => "image.raise_item(layer)".to_string(),
+        LayerPosition::Down => "image.lower_item(layer)".to_string(),
+        LayerPosition::Top => "image.raise_item_to_top(layer)".to_string(),
+        LayerPosition::Bottom => "image.lower_item_to_bottom(layer)".to_string(),
+        LayerPosition::Index(index) => format!("image.reorder_item(layer, layer.get_parent(), {index})"),
+    };
+    layer_exec(target, vec![line])
+}
+
+pub fn set_layer_visibility(visible: bool, target: &Target) -> Value {
+    let visible = if visible { "True" } else { "False" };
+    layer_exec(target, vec![format!("layer.set_visible({visible})")])
+}
+
+/// Merge the target layer into the one below, growing it to fit both.
+pub fn merge_down(target: &Target) -> Value {
+    layer_exec(target, vec![
+        "merged = image.merge_down(layer, Gimp.MergeType.EXPAND_AS_NECESSARY)".to_string(),
+        "image.set_selected_layers([merged])".to_string(),
+    ])
+}
+
+pub fn set_layer_opacity(opacity: f64, target: &Target) -> Value {
+    layer_exec(target, vec![format!("layer.set_opacity({:.1})", clamp_f64(opacity, 0.0, 100.0))])
+}
+
+pub fn set_layer_blend_mode(mode: BlendMode, target: &Target) -> Value {
+    layer_exec(target, vec![format!("layer.set_mode({})", mode.gimp_enum())])
+}
+
+pub fn set_active_layer(target: &Target) -> Value {
+    layer_exec(target, vec!["image.set_selected_layers([layer])".to_string()])
+}
+
+/// Fill the target (limited to the selection, if any) with `fill`. `color` sets
+/// the foreground or background color it uses.
+pub fn fill(fill: FillKind, color: Option<&Color>, pattern: Option<&str>, target: &Target) -> Value {
+    let mut python_lines = target_setup_lines(target, true);
+    if let Some(color) = color {
+        let set = if fill == FillKind::Background { "background" } else { "foreground" };
+        python_lines.push(format!("fill_color = Gegl.Color.new('{}')", color.to_hex()));
+        python_lines.push(format!("Gimp.context_set_{set}(fill_color)"));
+    }
+    if let Some(pattern) = pattern {
+        let name = py_str(pattern);
+        python_lines.push(format!("fill_pattern = Gimp.Pattern.get_by_name({name})"));
+        python_lines.push(format!("if fill_pattern is None: raise Exception('No pattern named ' + {name})"));
+        python_lines.push("Gimp.context_set_pattern(fill_pattern)".to_string());
+    }
+    if fill == FillKind::Transparent {
+        python_lines.push("if not layer.has_alpha(): layer.add_alpha()".to_string());
+    }
+    python_lines.push(format!("Gimp.Drawable.edit_fill(drawable, {})", fill.gimp_enum()));
+    python_lines.extend(target_finish_lines(target));
+    python_lines.push("Gimp.displays_flush()".to_string());
+    call_api_exec(python_lines)
+}
+
+/// Fill the target (limited to the selection, if any) with a gradient through
+/// `colors`, evenly spaced. Without `points` it spans the selection bounds:
+/// top to bottom for linear, centre to the right edge for radial and conical.
+pub fn gradient(kind: GradientKind, colors: &[Color], points: Option<((i32, i32), (i32, i32))>, target: &Target) -> Value {
+    let mut python_lines = target_setup_lines(target, true);
+    python_lines.extend(vec![
+        "gradient = Gimp.Gradient.new('Assistant gradient')".to_string(),
+        format!("gradient.segment_range_split_uniform(0, 0, {})", colors.len().saturating_sub(1).max(1)),
+    ]);
+    for (i, pair) in colors.windows(2).enumerate() {
+        python_lines.push(format!("gradient.segment_set_left_color({i}, Gegl.Color.new('{}'))", pair[0].to_hex()));
+        python_lines.push(format!("gradient.segment_set_right_color({i}, Gegl.Color.new('{}'))", pair[1].to_hex()));
+    }
+    python_lines.push("Gimp.context_set_gradient(gradient)".to_string());
+    if colors.iter().any(|c| c.a < 255) {
+        python_lines.push("if not layer.has_alpha(): layer.add_alpha()".to_string());
+    }
+
+    match points {
+        Some(((x1, y1), (x2, y2))) => python_lines.push(format!("x1, y1, x2, y2 = {x1}, {y1}, {x2}, {y2}")),
+        None => {
+            python_lines.push("_, _, sx1, sy1, sx2, sy2 = Gimp.Selection.bounds(image)".to_string());
+            python_lines.push(match kind {
+                GradientKind::Linear => "x1, y1, x2, y2 = (sx1 + sx2) / 2, sy1, (sx1 + sx2) / 2, sy2".to_string(),
+                _ => "x1, y1, x2, y2 = (sx1 + sx2) / 2, (sy1 + sy2) / 2, sx2, (sy1 + sy2) / 2".to_string(),
+            });
+        }
+    }
+    python_lines.extend(vec![
+        format!(
+            "Gimp.Drawable.edit_gradient_fill(drawable, {}, 0.0, False, 1, 0.0, True, x1, y1, x2, y2)",
+            kind.gimp_enum()
+        ),
+        "gradient.delete()".to_string(),
+    ]);
+    python_lines.extend(target_finish_lines(target));
+    python_lines.push("Gimp.displays_flush()".to_string());
+    call_api_exec(python_lines)
+}
+
+// ---------------------------------------------------------------------------
+// Selection: these change the image's selection and leave it in place for later
+// steps. They never flatten.
+// ---------------------------------------------------------------------------
+
+/// Run `lines` against the image, then refresh the display.
+fn selection_exec(lines: Vec<String>) -> Value {
+    let mut python_lines = vec![
+        "from gi.repository import Gimp".to_string(),
+        "image = Gimp.get_images()[0]".to_string(),
+    ];
+    python_lines.extend(lines);
+    python_lines.push("Gimp.displays_flush()".to_string());
+    call_api_exec(python_lines)
+}
+
+pub fn select_all() -> Value {
+    selection_exec(vec!["Gimp.Selection.all(image)".to_string()])
+}
+
+pub fn select_none() -> Value {
+    selection_exec(vec!["Gimp.Selection.none(image)".to_string()])
+}
+
+pub fn select_invert() -> Value {
+    selection_exec(vec!["Gimp.Selection.invert(image)".to_string()])
+}
+
+/// Select a rectangle, or the ellipse inscribed in it.
+pub fn select_rect(x: i32, y: i32, width: i32, height: i32, mode: SelectionMode, ellipse: bool) -> Value {
+    let select = if ellipse { "select_ellipse" } else { "select_rectangle" };
+    selection_exec(vec![format!(
+        "image.{select}({}, {x}, {y}, {}, {})",
+        mode.gimp_enum(), width.max(1), height.max(1)
+    )])
+}
+
+/// Select the polygon through `points` (image pixels).
+pub fn select_polygon(points: &[(i32, i32)], mode: SelectionMode) -> Value {
+    let coords: Vec<String> = points.iter().map(|(x, y)| format!("{x}.0, {y}.0")).collect();
+    selection_exec(vec![format!("image.select_polygon({}, [{}])", mode.gimp_enum(), coords.join(", "))])
+}
+
+/// Apply a `Gimp.Selection` modifier, e.g. `"grow(image, 10)"`.
+pub fn modify_selection(call: &str) -> Value {
+    selection_exec(vec![
+        "if Gimp.Selection.is_empty(image): raise Exception('Nothing is selected')".to_string(),
+        format!("Gimp.Selection.{call}"),
+    ])
+}
+
+/// Select pixels of the target layer within `threshold` (0–255) of `color`.
+pub fn select_color(color: &Color, threshold: i32, mode: SelectionMode, sample_merged: bool, target: &Target) -> Value {
+    // The whole image means "as it looks": sample all layers instead of flattening
+    let sample_merged = sample_merged || matches!(target, Target::Image);
+    let target = if matches!(target, Target::Image) { &Target::ActiveLayer } else { target };
+    let mut python_lines = target_setup_lines(target, true);
+    python_lines.extend(vec![
+        format!("Gimp.context_set_sample_threshold_int({})", clamp_i32(threshold, 0, 255)),
+        format!("Gimp.context_set_sample_merged({})", if sample_merged { "True" } else { "False" }),
+        format!("select_color = Gegl.Color.new('{}')", color.to_hex()),
+        format!("image.select_color({}, drawable, select_color)", mode.gimp_enum()),
+        "Gimp.displays_flush()".to_string(),
+    ]);
+    call_api_exec(python_lines)
+}
+
+/// Export the image to `path` with the format's export procedure. `flatten`
+/// exports a flattened duplicate, so the open image keeps its layers.
+pub fn export_image(
+    path: &str,
+    format: ExportFormat,
+    quality: i32,
+    compression: i32,
+    strip_metadata: bool,
+    flatten: bool,
+) -> Value {
+    let mut python_lines = vec![
+        "from gi.repository import Gimp, Gio".to_string(),
+        "image = Gimp.get_images()[0]".to_string(),
+    ];
+    if flatten {
+        python_lines.push("export_image = image.duplicate()".to_string());
+        python_lines.push("export_image.flatten()".to_string());
+    } else {
+        python_lines.push("export_image = image".to_string());
+    }
+    python_lines.extend(vec![
+        format!("proc = Gimp.get_pdb().lookup_procedure('{}')", format.procedure()),
+        "cfg = proc.create_config()".to_string(),
+        "cfg.set_property('run-mode', Gimp.RunMode.NONINTERACTIVE)".to_string(),
+        "cfg.set_property('image', export_image)".to_string(),
+        format!("cfg.set_property('file', Gio.File.new_for_path({}))", py_str(path)),
+    ]);
+    match format {
+        ExportFormat::Png => python_lines.push(format!("cfg.set_property('compression', {})", clamp_i32(compression, 0, 9))),
+        // GIMP 3's JPEG quality is 0..1, WebP's 0..100
+        ExportFormat::Jpeg => python_lines.push(format!("cfg.set_property('quality', {:.2})", clamp_i32(quality, 1, 100) as f64 / 100.0)),
+        ExportFormat::Webp => python_lines.push(format!("cfg.set_property('quality', {:.1})", clamp_i32(quality, 1, 100) as f64)),
+        ExportFormat::Tiff => {
+            let method = if compression > 0 { "lzw" } else { "none" };
+            python_lines.push(format!("cfg.set_property('compression', '{method}')"));
+        }
+        ExportFormat::Gif => {}
+    }
+    if strip_metadata && format != ExportFormat::Gif {
+        for prop in ["include-exif", "include-xmp", "include-iptc", "include-comment"] {
+            python_lines.push(format!("cfg.set_property('{prop}', False)"));
+        }
+    }
+    python_lines.extend(vec![
+        "result = proc.run(cfg)".to_string(),
+        "status = result.index(0)".to_string(),
+    ]);
+    if flatten {
+        python_lines.push("export_image.delete()".to_string());
+    }
+    python_lines.push("if status != Gimp.PDBStatusType.SUCCESS: raise Exception('Export failed: ' + str(status))".to_string());
+    call_api_exec(python_lines)
+}
+
+/// Save the image, layers and all, as XCF at `path`.
+pub fn save_xcf(path: &str) -> Value {
+    call_api_exec(vec![
+        "from gi.repository import Gimp, Gio".to_string(),
+        "image = Gimp.get_images()[0]".to_string(),
+        format!(
+            "if not Gimp.file_save(Gimp.RunMode.NONINTERACTIVE, image, Gio.File.new_for_path({}), None): raise Exception('Saving the XCF failed')",
+            py_str(path)
+        ),
+    ])
+}
+
+/// Save a copy of the image as XCF at `path` for the plan history. The copy is
+/// saved rather than the image so its file name and clean state don't change.
+pub fn save_snapshot(path: &str) -> Value {
+    call_api_exec(vec![
+        "from gi.repository import Gimp, Gio".to_string(),
+        "image = Gimp.get_images()[0]".to_string(),
+        "snapshot = image.duplicate()".to_string(),
+        format!(
+            "saved = Gimp.file_save(Gimp.RunMode.NONINTERACTIVE, snapshot, Gio.File.new_for_path({}), None)",
+            py_str(path)
+        ),
+        "snapshot.delete()".to_string(),
+        "if not saved: raise Exception('Saving the undo snapshot failed')".to_string(),
+    ])
+}
+
+/// Put the image back into the state saved at `path`. The snapshot is loaded
+/// as a new image and takes over the old one's displays, file and display
+/// parasite, so everything XCF keeps (groups, text layers, masks, channels,
+/// paths, precision) comes back as it was. The old image is then deleted.
+pub fn restore_snapshot(path: &str) -> Value {
+    call_api_exec(vec![
+        "from gi.repository import Gimp, Gio".to_string(),
+        "if not Gimp.get_images(): raise Exception('No image is open in GIMP')".to_string(),
+        "image = Gimp.get_images()[0]".to_string(),
+        format!("snapshot = Gimp.file_load(Gimp.RunMode.NONINTERACTIVE, Gio.File.new_for_path({}))", py_str(path)),
+        "if snapshot is None: raise Exception('The undo snapshot could not be loaded')".to_string(),
+        // False when the image has no display (GIMP running headless); the
+        // snapshot then replaces it unseen
+        "Gimp.displays_reconnect(image, snapshot)".to_string(),
+        "file = image.get_file()".to_string(),
+        "if file is not None: snapshot.set_file(file)".to_string(),
+        format!("parasite = image.get_parasite('{DISPLAY_PARASITE}')"),
+        "if parasite is not None: snapshot.attach_parasite(parasite)".to_string(),
+        "image.clean_all()".to_string(),
+        "if not image.delete(): raise Exception('The image could not be replaced by the undo snapshot')".to_string(),
+        "Gimp.displays_flush()".to_string(),
+    ])
+}
+
+/// Image parasite holding the id of the display `new_image`/`open_image` made,
+/// so `close_image` can close it (GIMP only deletes an image with no displays).
+const DISPLAY_PARASITE: &str = "gimp-assistant-display";
+
+/// Lines that show `image` in a new display and remember the display on it.
+fn show_image_lines() -> Vec<String> {
+    vec![
+        "display = Gimp.Display.new(image)".to_string(),
+        format!(
+            "image.attach_parasite(Gimp.Parasite.new('{DISPLAY_PARASITE}', 0, list(str(display.get_id()).encode())))"
+        ),
+        "Gimp.displays_flush()".to_string(),
+    ]
+}
+
+/// Create a `width`×`height` RGB image with one 'Background' layer and show it.
+/// GIMP lists the newest image first, so later steps edit this one.
+pub fn new_image(width: i32, height: i32, background: &Color, transparent: bool) -> Value {
+    let (w, h) = (width.max(1), height.max(1));
+    let mut python_lines = vec![
+        "from gi.repository import Gimp, Gegl".to_string(),
+        format!("image = Gimp.Image.new({w}, {h}, Gimp.ImageBaseType.RGB)"),
+        format!("layer = Gimp.Layer.new(image, 'Background', {w}, {h}, Gimp.ImageType.RGBA_IMAGE, 100.0, Gimp.LayerMode.NORMAL)"),
+        "image.insert_layer(layer, None, 0)".to_string(),
+    ];
+    if transparent {
+        python_lines.push("layer.fill(Gimp.FillType.TRANSPARENT)".to_string());
+    } else {
+        python_lines.extend(vec![
+            format!("Gimp.context_set_foreground(Gegl.Color.new('{}'))", background.to_hex()),
+            "layer.fill(Gimp.FillType.FOREGROUND)".to_string(),
+        ]);
+    }
+    python_lines.extend(show_image_lines());
+    python_lines.push("image.clean_all()".to_string());
+    call_api_exec(python_lines)
+}
+
+/// Load the image file at `path` and show it.
+pub fn open_image(path: &str) -> Value {
+    let mut python_lines = vec![
+        "from gi.repository import Gimp, Gio".to_string(),
+        format!("image = Gimp.file_load(Gimp.RunMode.NONINTERACTIVE, Gio.File.new_for_path({}))", py_str(path)),
+        "if image is None: raise Exception('GIMP could not open the file')".to_string(),
+    ];
+    python_lines.extend(show_image_lines());
+    call_api_exec(python_lines)
+}
+
+/// Close the image through the display `new_image`/`open_image` made for it.
+/// Images the user opened have displays we can't close, so those fail.
+pub fn close_image(discard_changes: bool) -> Value {
+    let mut python_lines = vec![
+        "from gi.repository import Gimp".to_string(),
+        "if not Gimp.get_images(): raise Exception('No image is open in GIMP')".to_string(),
+        "image = Gimp.get_images()[0]".to_string(),
+    ];
+    if !discard_changes {
+        python_lines.push(
+            "if image.is_dirty(): raise Exception('The image has unsaved changes; save or export it first, or close with discard_changes')".to_string(),
+        );
+    }
+    python_lines.extend(vec![
+        format!("parasite = image.get_parasite('{DISPLAY_PARASITE}')"),
+        "display = Gimp.Display.get_by_id(int(bytes(parasite.get_data()).decode())) if parasite else None".to_string(),
+        "if display is None and not image.delete(): raise Exception('This image was opened in GIMP directly; close it there')".to_string(),
+        "if display is not None: image.clean_all()".to_string(),
+        "if display is not None: display.delete()".to_string(),
+    ]);
+    call_api_exec(python_lines)
+}
+
 /// Return the GIMP 3 Python lines to select a named half of the image.
 /// region: "top" | "bottom" | "left" | "right"
 /// Returns an empty vec for any unrecognised value (→ full-image operation).
@@ -280,7 +1108,6 @@ pub fn brightness_contrast_region(brightness: f64, contrast: f64, region: &str)
     let b = clamp_f64(brightness / 127.0, -1.0, 1.0);
     let c = clamp_f64(contrast   / 127.0, -1.0, 1.0);
     let mut python_lines = setup_lines(false);
-    python_lines.extend(save_clipboard_lines());
     python_lines.extend(region_selection_lines(region));
     python_lines.push(format!("drawable.brightness_contrast({:.4}, {:.4})", b, c));
     python_lines.push("Gimp.Selection.none(image)".to_string());
@@ -296,7 +1123,6 @@ pub fn brightness_contrast_region(brightness: f64, contrast: f64, region: &str)
 pub fn blur_region(radius: f64, region: &str) -> Value {
     let std_dev = (radius / 3.0).max(1.0);
     let mut python_lines = setup_lines(false);
-    python_lines.extend(save_clipboard_lines());
     // Select the region, copy it to a temp layer, blur that layer, flatten back
     python_lines.extend(region_selection_lines(region));
     python_lines.extend(vec![
@@ -324,12 +1150,10 @@ pub fn blur_region(radius: f64, region: &str) -> Value {
 }
 
 /// Draw a filled rectangle (half the image size) centred in the image.
-/// `color` is a CSS colour name or rgb(r,g,b).
-pub fn draw_filled_rect(color: &str) -> Value {
+pub fn draw_filled_rect(color: &Color) -> Value {
     let mut python_lines = setup_lines(true);
-    python_lines.extend(save_clipboard_lines());
     python_lines.extend(vec![
-        format!("fill_color = Gegl.Color.new('{}')", color),
+        format!("fill_color = Gegl.Color.new('{}')", color.to_hex()),
         "Gimp.context_set_foreground(fill_color)".to_string(),
         "rw = w // 2".to_string(),
         "rh = h // 2".to_string(),
@@ -342,3 +1166,82 @@ pub fn draw_filled_rect(color: &str) -> Value {
     ]);
     call_api_exec(python_lines)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan_schema::Region;
+
+    /// The Python lines of a macro payload.
+    fn lines(payload: &Value) -> Vec<String> {
+        serde_json::from_value(payload["arguments"]["args"][1].clone()).unwrap()
+    }
+
+    #[test]
+    fn free_rotation_turns_every_layer_around_the_centre_without_flattening() {
+        let script = lines(&rotate(30.0, Interpolation::default()));
+        assert!(!script.iter().any(|line| line.contains("flatten")));
+        assert!(script.iter().any(|line| line.contains("transform_rotate(math.radians(30.0000), False, cx, cy) for l in image.get_layers()")));
+        assert!(script.iter().any(|line| line == "image.resize_to_layers()"));
+
+        let script = lines(&rotate(90.0, Interpolation::default()));
+        assert!(script.contains(&"image.rotate(Gimp.RotationType.DEGREES90)".to_string()));
+    }
+
+    #[test]
+    fn draw_shape_puts_the_selection_back() {
+        let script = lines(&draw_shape(ShapeKind::Circle, 10, 10, 50, 50, &Color::BLACK, true, 2.0, &Target::ActiveLayer));
+        let at = |wanted: &str| script.iter().position(|line| line.starts_with(wanted)).unwrap();
+        assert!(at("_saved_sel = Gimp.Selection.save(image)") < at("Gimp.Image.select_ellipse"));
+        assert!(at("Gimp.Drawable.edit_fill") < at("image.select_item(Gimp.ChannelOps.REPLACE, _saved_sel)"));
+        assert!(!script.iter().any(|line| line == "Gimp.Selection.none(image)"));
+    }
+
+    #[test]
+    fn only_the_image_target_flattens() {
+        let region = Region { shape: RegionShape::Ellipse, x: 1, y: 2, width: 30, height: 40 };
+        let targets = [
+            Target::ActiveLayer,
+            Target::Selection,
+            Target::Layer("Sky".to_string()),
+            Target::LayerIndex(2),
+            Target::AllLayers,
+            Target::Region(region),
+        ];
+        for target in &targets {
+            for payload in [invert(target), blur(6.0, target), draw_line(0, 0, 9, 9, &Color::BLACK, target)] {
+                let script = lines(&payload);
+                assert!(!script.iter().any(|line| line.contains("flatten")), "{target:?}: {script:?}");
+            }
+        }
+        assert!(lines(&invert(&Target::Image)).contains(&"layer = image.flatten()".to_string()));
+        // Text goes on its own layer even when the plan targets the whole image
+        let text = add_text("Hi", "Sans", 20, &Color::BLACK, TextAlign::Left, (0, 0), (0.0, 0.0), 0, &Target::Image);
+        assert!(!lines(&text).iter().any(|line| line.contains("flatten")));
+    }
+
+    /// A string that breaks out of a Python literal unless it is escaped.
+    const HOSTILE: &str = "Sky \"x\"\n' + __import__('os').getcwd() + '";
+
+    /// Whether `script` holds `HOSTILE` as an escaped literal and never raw.
+    fn escapes_hostile(script: &[String]) -> bool {
+        let literal = serde_json::to_string(HOSTILE).unwrap();
+        script.iter().any(|line| line.contains(&literal))
+            && !script.iter().any(|line| line.contains('\n') || line.contains("\"x\""))
+    }
+
+    #[test]
+    fn names_text_and_paths_are_escaped() {
+        assert!(escapes_hostile(&lines(&invert(&Target::Layer(HOSTILE.to_string())))));
+
+        let text = |text: &str, font: &str| {
+            lines(&add_text(text, font, 20, &Color::BLACK, TextAlign::Left, (0, 0), (0.0, 0.0), 0, &Target::ActiveLayer))
+        };
+        assert!(escapes_hostile(&text(HOSTILE, "Sans")));
+        assert!(escapes_hostile(&text("Hi", HOSTILE)));
+
+        let export = export_image(HOSTILE, ExportFormat::Png, 90, 6, false, false);
+        assert!(escapes_hostile(&lines(&export)));
+        assert!(escapes_hostile(&lines(&restore_snapshot(HOSTILE))));
+    }
+}
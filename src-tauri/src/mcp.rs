@@ -210,6 +210,11 @@ where
 /// Public API used by Tauri commands
 
 pub fn list_tools() -> Result<Value, String> {
+    #[cfg(test)]
+    if let Some(reply) = crate::mock_mcp::list_tools() {
+        return reply;
+    }
+
     with_connection(|conn| conn.send_request("tools/list", json!({ "cursor": null })))
 }
 
@@ -225,6 +230,11 @@ pub fn list_tools() -> Result<Value, String> {
 //     })
 // }
 pub fn call_tool(name: &str, arguments: Value) -> Result<Value, String> {
+    #[cfg(test)]
+    if let Some(reply) = crate::mock_mcp::handle(name, &arguments) {
+        return reply;
+    }
+
     let start = std::time::Instant::now();
 
     println!("\n================ MCP CALL ================");
@@ -0,0 +1,281 @@
+//! Scripted stand-in for the LLM server, for tests.
+//!
+//! Serves Ollama's `/api/chat` and `/api/tags` and the OpenAI-style
+//! `/v1/chat/completions` on a random local port. Replies are picked by the
+//! first rule whose regex matches the request's message text, and every
+//! request is recorded so tests can assert on the prompts that were sent.
+//!
+//! ```ignore
+//! let mock = MockLlm::start();
+//! mock.on(r"planner for a GIMP assistant", r#"{"steps": []}"#);
+//! let plan = plan_llm::make_plan_from_text("blur it").await?;
+//! assert!(mock.prompts()[0].contains("blur it"));
+//! ```
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::llm_client::{self, Backend, LlmSettings};
+
+/// LLM settings are process-wide, so tests using the mock run one at a time.
+static TEST_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+#[derive(Clone)]
+enum Reply {
+    Text(String),
+    ToolCalls(Vec<(String, Value)>),
+}
+
+struct Rule {
+    pattern: Regex,
+    /// Only match requests for this model.
+    model: Option<String>,
+    reply: Reply,
+}
+
+#[derive(Default)]
+struct State {
+    rules: Vec<Rule>,
+    requests: Vec<Value>,
+}
+
+pub struct MockLlm {
+    addr: String,
+    state: Arc<Mutex<State>>,
+    stop: Arc<AtomicBool>,
+    previous_settings: LlmSettings,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl MockLlm {
+    /// Start a mock speaking Ollama's API and point `llm_client` at it.
+    pub fn start() -> Self {
+        Self::start_with(Backend::Ollama)
+    }
+
+    /// Start a mock and point `llm_client` at it using the given backend.
+    /// Previous LLM settings are restored when the mock is dropped.
+    pub fn start_with(backend: Backend) -> Self {
+        let lock = TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind mock LLM server");
+        let addr = format!("http://{}", listener.local_addr().expect("mock LLM address"));
+
+        let state = Arc::new(Mutex::new(State::default()));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let thread_state = state.clone();
+        let thread_stop = stop.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                if thread_stop.load(Ordering::SeqCst) {
+                    break;
+                }
+                if let Ok(stream) = stream {
+                    handle_connection(stream, &thread_state);
+                }
+            }
+        });
+
+        let previous_settings = llm_client::settings();
+        let mut settings = previous_settings.clone();
+        settings.backend = backend;
+        settings.base_url = Some(addr.clone());
+        llm_client::set_settings(settings).expect("install mock LLM settings");
+
+        Self {
+            addr,
+            state,
+            stop,
+            previous_settings,
+            _lock: lock,
+        }
+    }
+
+    /// Reply with `text` to requests whose message text matches `pattern`.
+    pub fn on(&self, pattern: &str, text: &str) -> &Self {
+        self.add_rule(pattern, Reply::Text(text.to_string()))
+    }
+
+    /// Like `on`, but only for requests addressed to `model`.
+    pub fn on_model(&self, model: &str, pattern: &str, text: &str) -> &Self {
+        let pattern = Regex::new(pattern).expect("invalid mock LLM pattern");
+        let rule = Rule { pattern, model: Some(model.to_string()), reply: Reply::Text(text.to_string()) };
+        self.state.lock().unwrap().rules.push(rule);
+        self
+    }
+
+    /// Reply with a single tool call to requests matching `pattern`.
+    pub fn on_tool_call(&self, pattern: &str, name: &str, arguments: Value) -> &Self {
+        self.add_rule(pattern, Reply::ToolCalls(vec![(name.to_string(), arguments)]))
+    }
+
+    fn add_rule(&self, pattern: &str, reply: Reply) -> &Self {
+        let pattern = Regex::new(pattern).expect("invalid mock LLM pattern");
+        self.state.lock().unwrap().rules.push(Rule { pattern, model: None, reply });
+        self
+    }
+
+    /// Full JSON bodies of every chat request received, in order.
+    pub fn requests(&self) -> Vec<Value> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// Content of the last user message of every chat request, in order.
+    pub fn prompts(&self) -> Vec<String> {
+        self.requests()
+            .iter()
+            .map(|req| {
+                req.get("messages")
+                    .and_then(|m| m.as_array())
+                    .and_then(|msgs| {
+                        msgs.iter()
+                            .rev()
+                            .find(|m| m.get("role").and_then(|r| r.as_str()) == Some("user"))
+                    })
+                    .map(message_text)
+                    .unwrap_or_default()
+            })
+            .collect()
+    }
+}
+
+impl Drop for MockLlm {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // Wake the accept loop so the thread can exit
+        let _ = TcpStream::connect(self.addr.trim_start_matches("http://"));
+        let _ = llm_client::set_settings(self.previous_settings.clone());
+    }
+}
+
+/// Text of a message, for both plain string content and OpenAI content parts.
+fn message_text(msg: &Value) -> String {
+    match msg.get("content") {
+        Some(Value::String(s)) => s.clone(),
+        Some(Value::Array(parts)) => parts
+            .iter()
+            .filter_map(|p| p.get("text").and_then(|t| t.as_str()))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+fn handle_connection(mut stream: TcpStream, state: &Arc<Mutex<State>>) {
+    let Some((method, path, body)) = read_request(&stream) else { return };
+
+    let (status, response) = match (method.as_str(), path.as_str()) {
+        ("GET", "/api/tags") => (200, json!({ "models": [{ "name": "mock:latest", "model": "mock:latest" }] })),
+        ("GET", "/") => (200, json!("Ollama is running")),
+        ("POST", "/api/chat") | ("POST", "/v1/chat/completions") => {
+            let request: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+            let openai = path.starts_with("/v1/");
+            chat_response(state, request, openai)
+        }
+        _ => (404, json!({ "error": format!("mock LLM: no route for {method} {path}") })),
+    };
+
+    let body = response.to_string();
+    let reason = match status {
+        200 => "OK",
+        404 => "Not Found",
+        _ => "Internal Server Error",
+    };
+    let _ = write!(
+        stream,
+        "HTTP/1.1 {status} {reason}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    let _ = stream.flush();
+}
+
+fn read_request(stream: &TcpStream) -> Option<(String, String, Vec<u8>)> {
+    let mut reader = BufReader::new(stream);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).ok()?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let path = parts.next()?.to_string();
+
+    let mut content_length = 0usize;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).ok()? == 0 || line == "\r\n" {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+
+    let mut body = vec![0u8; content_length];
+    reader.read_exact(&mut body).ok()?;
+    Some((method, path, body))
+}
+
+fn chat_response(state: &Arc<Mutex<State>>, request: Value, openai: bool) -> (u16, Value) {
+    let mut state = state.lock().unwrap();
+
+    let text = request
+        .get("messages")
+        .and_then(|m| m.as_array())
+        .map(|msgs| msgs.iter().map(message_text).collect::<Vec<_>>().join("\n"))
+        .unwrap_or_default();
+    let model = request.get("model").cloned().unwrap_or(Value::Null);
+    state.requests.push(request);
+
+    let Some(reply) = state
+        .rules
+        .iter()
+        .filter(|r| r.model.as_deref().is_none_or(|m| model.as_str() == Some(m)))
+        .find(|r| r.pattern.is_match(&text))
+        .map(|r| r.reply.clone())
+    else {
+        return (500, json!({ "error": format!("mock LLM: no scripted response for prompt:\n{text}") }));
+    };
+
+    let (content, tool_calls) = match reply {
+        Reply::Text(t) => (t, Vec::new()),
+        Reply::ToolCalls(calls) => (String::new(), calls),
+    };
+
+    let body = if openai {
+        let calls: Vec<Value> = tool_calls
+            .iter()
+            .enumerate()
+            .map(|(i, (name, args))| {
+                json!({
+                    "id": format!("call_{i}"),
+                    "type": "function",
+                    "function": { "name": name, "arguments": args.to_string() }
+                })
+            })
+            .collect();
+        let mut message = json!({ "role": "assistant", "content": content });
+        if !calls.is_empty() {
+            message["tool_calls"] = json!(calls);
+        }
+        json!({ "model": model, "choices": [{ "index": 0, "message": message, "finish_reason": "stop" }] })
+    } else {
+        let calls: Vec<Value> = tool_calls
+            .iter()
+            .map(|(name, args)| json!({ "function": { "name": name, "arguments": args } }))
+            .collect();
+        let mut message = json!({ "role": "assistant", "content": content });
+        if !calls.is_empty() {
+            message["tool_calls"] = json!(calls);
+        }
+        json!({ "model": model, "message": message, "done": true })
+    };
+
+    (200, body)
+}
@@ -0,0 +1,136 @@
+//! Scripted stand-in for the GIMP MCP server, for tests.
+//!
+//! While a `MockMcp` is alive, `mcp::call_tool` answers from its rules instead
+//! of the real server: the first rule whose regex matches `"<tool> <arguments
+//! JSON>"` wins, and calls no rule matches succeed with empty output, like a
+//! console script that printed nothing. Every call is recorded so tests can
+//! assert on the Python the macros sent.
+//!
+//! ```ignore
+//! let mcp = MockMcp::start();
+//! mcp.with_image(800, 600, &["Background"]);
+//! mcp.on_error(r"No layer named", "Exception: No layer named Sky");
+//! let results = plan_execute::execute_plan(plan)?;
+//! assert!(mcp.scripts()[0].iter().any(|line| line.contains("gaussian-blur")));
+//! ```
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// The mock is process-wide, so tests using it run one at a time.
+static TEST_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+static ACTIVE: Lazy<Mutex<Option<Arc<Mutex<State>>>>> = Lazy::new(|| Mutex::new(None));
+
+struct Rule {
+    pattern: Regex,
+    result: Value,
+}
+
+#[derive(Default)]
+struct State {
+    rules: Vec<Rule>,
+    calls: Vec<(String, Value)>,
+    tools: Vec<String>,
+}
+
+pub struct MockMcp {
+    state: Arc<Mutex<State>>,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl MockMcp {
+    /// Route MCP calls to a new mock until it is dropped.
+    pub fn start() -> Self {
+        let lock = TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let state = Arc::new(Mutex::new(State::default()));
+        *ACTIVE.lock().unwrap_or_else(|e| e.into_inner()) = Some(state.clone());
+        Self { state, _lock: lock }
+    }
+
+    /// Reply with `result` to calls matching `pattern`.
+    pub fn on(&self, pattern: &str, result: Value) -> &Self {
+        let pattern = Regex::new(pattern).expect("invalid mock MCP pattern");
+        self.state.lock().unwrap().rules.push(Rule { pattern, result });
+        self
+    }
+
+    /// Reply with output `text` to calls matching `pattern`.
+    pub fn on_text(&self, pattern: &str, text: &str) -> &Self {
+        self.on(pattern, text_result(text, false))
+    }
+
+    /// Fail calls matching `pattern` the way the server reports a Python
+    /// exception: an `isError` result carrying `text`.
+    pub fn on_error(&self, pattern: &str, text: &str) -> &Self {
+        self.on(pattern, text_result(text, true))
+    }
+
+    /// Answer `get_image_metadata` with a `width`×`height` image and these layers.
+    pub fn with_image(&self, width: i32, height: i32, layers: &[&str]) -> &Self {
+        let layers: Vec<Value> = layers.iter().map(|name| json!({ "name": name })).collect();
+        let meta = json!({ "basic": { "width": width, "height": height }, "layers": layers });
+        self.on_text(r"^get_image_metadata ", &meta.to_string())
+    }
+
+    /// List these tools in the server's catalog.
+    pub fn with_tools(&self, names: &[&str]) -> &Self {
+        self.state.lock().unwrap().tools = names.iter().map(|n| n.to_string()).collect();
+        self
+    }
+
+    /// Tool name and arguments of every call, in order.
+    pub fn calls(&self) -> Vec<(String, Value)> {
+        self.state.lock().unwrap().calls.clone()
+    }
+
+    /// Python lines of every `call_api` exec, in order.
+    pub fn scripts(&self) -> Vec<Vec<String>> {
+        self.calls()
+            .iter()
+            .filter(|(name, _)| name == "call_api")
+            .filter_map(|(_, args)| args.pointer("/args/1").and_then(|l| l.as_array()).cloned())
+            .map(|lines| lines.iter().filter_map(|l| l.as_str().map(str::to_string)).collect())
+            .collect()
+    }
+}
+
+impl Drop for MockMcp {
+    fn drop(&mut self) {
+        *ACTIVE.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    }
+}
+
+fn text_result(text: &str, is_error: bool) -> Value {
+    json!({ "content": [{ "type": "text", "text": text }], "isError": is_error })
+}
+
+/// The mock's tool catalog, or `None` when no mock is running.
+pub fn list_tools() -> Option<Result<Value, String>> {
+    let state = ACTIVE.lock().ok()?.clone()?;
+    let tools: Vec<Value> = state
+        .lock()
+        .unwrap()
+        .tools
+        .iter()
+        .map(|name| json!({ "name": name, "description": "", "inputSchema": { "type": "object", "properties": {} } }))
+        .collect();
+    Some(Ok(json!({ "tools": tools })))
+}
+
+/// The mock's reply to a call, or `None` when no mock is running.
+pub fn handle(name: &str, arguments: &Value) -> Option<Result<Value, String>> {
+    let state = ACTIVE.lock().ok()?.clone()?;
+    let mut state = state.lock().unwrap();
+    let key = format!("{name} {arguments}");
+    state.calls.push((name.to_string(), arguments.clone()));
+    let result = state
+        .rules
+        .iter()
+        .find(|r| r.pattern.is_match(&key))
+        .map(|r| r.result.clone())
+        .unwrap_or_else(|| text_result("", false));
+    Some(Ok(result))
+}
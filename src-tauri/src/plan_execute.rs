@@ -1,78 +1,157 @@
 use serde_json::Value;
+use std::path::{Path, PathBuf};
 
-use crate::plan_schema::ActionPlan;
-use crate::plan_validate::{validate_step, ValidatedParams};
+use crate::plan_history::{with_history, History};
+use crate::plan_schema::{ActionPlan, ActionStep, MissingImage, Op, Target};
+use crate::plan_validate::{match_font, validate_step_for_image, ImageInfo, ValidatedParams};
 
 use serde::{Deserialize, Serialize};
 
+/// Failures the UI can act on rather than just show.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepError {
+    /// The step needs an image and GIMP has none open.
+    NoImageOpen,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StepResult {
     pub index: usize,
     pub ok: bool,
     pub message: String,
     pub raw: Option<Value>,
+    /// The params the step actually ran with: percentages, units and anchors
+    /// resolved to pixels against the image at that point in the plan.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolved: Option<Value>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+    /// File the step wrote (export, save_xcf).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<StepError>,
 }
 
-
-
 /// Execute a plan sequentially using deterministic macros.
 /// Returns per-step results for UI/debug.
 pub fn execute_plan(plan: ActionPlan) -> Result<Vec<StepResult>, String> {
     let mut results: Vec<StepResult> = Vec::new();
 
     for (i, step) in plan.steps.iter().enumerate() {
-        // Validate & clamp params
-        let v = validate_step(step)?;
-
-        // Route to existing macro commands / MCP calls
-        let exec_result: Result<Value, String> = match v.params {
-            ValidatedParams::DrawLine { x1, y1, x2, y2 } => {
-                // Directly call your existing command logic via macros + MCP
-                let payload = crate::macros::draw_line(x1, y1, x2, y2);
-                run_payload(payload)
+        // Validate & clamp params against the image as it is now (earlier steps may
+        // have resized it)
+        let mut image = current_image_info()
+            .map_err(|e| eprintln!("[PLAN] Validating step {i} without image info: {e}"))
+            .ok();
+        // Metadata also fails for other reasons, so ask GIMP before deciding
+        // there's no image; if that fails too the step runs and reports its own error
+        let mut created_image = None;
+        if image.is_none() && step.op.needs_image() && !image_is_open().unwrap_or(true) {
+            let outcome = match plan.missing_image {
+                MissingImage::Fail => {
+                    Err("No image is open in GIMP; open one first or start the plan with new_image".to_string())
+                }
+                MissingImage::Create => create_missing_image()
+                    .map_err(|e| format!("No image is open in GIMP and creating one failed: {e}")),
+            };
+            match outcome {
+                Ok(note) => {
+                    created_image = Some(note);
+                    image = current_image_info().ok();
+                }
+                Err(message) => {
+                    results.push(StepResult {
+                        index: i,
+                        ok: false,
+                        message,
+                        raw: None,
+                        resolved: None,
+                        warnings: Vec::new(),
+                        path: None,
+                        error: Some(StepError::NoImageOpen),
+                    });
+                    if step.stop_on_error {
+                        break;
+                    }
+                    continue;
+                }
             }
-
-            ValidatedParams::CropSquare {} => {
-                let payload = crate::macros::crop_to_square();
-                run_payload(payload)
+        }
+        // Earlier steps already changed the image, so a step that doesn't
+        // validate fails on its own rather than failing the plan
+        let mut v = match validate_step_for_image(step, image.as_ref()) {
+            Ok(v) => v,
+            Err(message) => {
+                results.push(failed_step(i, message, Vec::new()));
+                if step.stop_on_error {
+                    break;
+                }
+                continue;
             }
-
-            ValidatedParams::ResizeWidth { width } => {
-                let payload = crate::macros::resize_width(width);
-                run_payload(payload)
+        };
+        // An image is open but GIMP didn't describe it: pixels from relative
+        // lengths would be placeholder 0s, so don't run them
+        if image.is_none() && v.placeholder_pixels {
+            let message = format!(
+                "{} needs the image size for its sizes or positions, but GIMP didn't report it; \
+                 try again, or give them in pixels",
+                step.op.name()
+            );
+            results.push(failed_step(i, message, v.warnings));
+            if step.stop_on_error {
+                break;
             }
-
-            ValidatedParams::BrightnessContrast { brightness, contrast } => {
-                let payload = crate::macros::brightness_contrast(brightness, contrast);
-                run_payload(payload)
+            continue;
+        }
+        if let Some(note) = created_image {
+            v.warnings.insert(0, note);
+        }
+        if let Err(message) = prepare_params(&mut v.params) {
+            results.push(failed_step(i, message, v.warnings));
+            if v.stop_on_error {
+                break;
             }
+            continue;
+        }
+        let path = output_path(&v.params);
+        let resolved = serde_json::to_value(&v.params).ok();
+        for warning in &v.warnings {
+            eprintln!("[PLAN] Step {i}: {warning}");
+        }
 
-            ValidatedParams::Blur { radius } => {
-                let payload = crate::macros::blur(radius);
-                run_payload(payload)
-            }
+        let edits_image = changes_image(&v.params);
+        if edits_image {
+            begin_edit(&format!("Step {i}"));
+        }
 
-            ValidatedParams::Undo { steps } => {
-                let mut result: Result<Value, String> = Ok(Value::Null);
-                for _ in 0..steps {
-                    let payload = crate::macros::undo();
-                    result = run_payload(payload);
-                    if result.is_err() { break; }
-                }
-                result
-            }
+        // all_layers becomes one run per layer, other targets run once
+        let exec_result = resolve_targets(&v.target, image.as_ref())
+            .and_then(|targets| run_for_targets(&v.params, &targets));
 
-            ValidatedParams::Redo { steps } => {
-                Err(format!("Redo not implemented yet (requested steps={steps})"))
-            }
-        };
+        if edits_image {
+            let _ = with_history(History::after_edit);
+        }
+        // The history belongs to the image it was recorded on
+        let switches_image = matches!(
+            v.params,
+            ValidatedParams::NewImage { .. } | ValidatedParams::OpenImage { .. } | ValidatedParams::CloseImage { .. }
+        );
+        if switches_image && exec_result.is_ok() {
+            let _ = with_history(History::clear);
+        }
 
         match exec_result {
             Ok(raw) => results.push(StepResult {
                 index: i,
                 ok: true,
-                message: "ok".to_string(),
+                message: success_message(&v.params),
                 raw: Some(raw),
+                resolved,
+                warnings: v.warnings.clone(),
+                path,
+                error: None,
             }),
             Err(e) => {
                 results.push(StepResult {
@@ -80,7 +159,17 @@ pub fn execute_plan(plan: ActionPlan) -> Result<Vec<StepResult>, String> {
                     ok: false,
                     message: e.clone(),
                     raw: None,
+                    resolved,
+                    warnings: v.warnings.clone(),
+                    path: None,
+                    error: None,
                 });
+                // Give back the name we reserved if nothing was written to it
+                if let Some(path) = &path {
+                    if std::fs::metadata(path).is_ok_and(|m| m.len() == 0) {
+                        let _ = std::fs::remove_file(path);
+                    }
+                }
                 if v.stop_on_error {
                     break;
                 }
@@ -91,6 +180,551 @@ pub fn execute_plan(plan: ActionPlan) -> Result<Vec<StepResult>, String> {
     Ok(results)
 }
 
+/// Settle what only GIMP or the file system knows before the step runs.
+fn prepare_params(params: &mut ValidatedParams) -> Result<(), String> {
+    match params {
+        // Fonts are only known to GIMP, so they're checked here rather than in validation
+        ValidatedParams::AddText { font, .. } => *font = match_font(font, &available_fonts()?)?,
+        // Claim the output file now so the step runs with a path that is inside
+        // the output directory and not taken
+        ValidatedParams::Export { format, file_name, path, .. } => {
+            *path = Some(reserve_output(file_name, format.extension())?);
+        }
+        ValidatedParams::SaveXcf { file_name, path } => *path = Some(reserve_output(file_name, "xcf")?),
+        // Only files inside the allowed directories may be opened
+        ValidatedParams::OpenImage { path } => {
+            *path = crate::file_access::resolve_input_path(path)?.to_string_lossy().into_owned();
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Result for a step that failed before it ran.
+fn failed_step(index: usize, message: String, warnings: Vec<String>) -> StepResult {
+    StepResult { index, ok: false, message, raw: None, resolved: None, warnings, path: None, error: None }
+}
+
+/// Undo `steps` plan steps outside a plan, e.g. from the UI.
+pub fn undo(steps: usize) -> Result<crate::plan_history::HistoryDepth, String> {
+    with_history(|h| h.undo(steps, snapshot_image, restore_image))??;
+    Ok(crate::plan_history::depth())
+}
+
+/// Redo `steps` undone plan steps outside a plan.
+pub fn redo(steps: usize) -> Result<crate::plan_history::HistoryDepth, String> {
+    with_history(|h| h.redo(steps, restore_image))??;
+    Ok(crate::plan_history::depth())
+}
+
+/// Run an edit made outside a plan (a quick command or a raw `call_api`
+/// script) on the undo history, so undo can step back over it too.
+pub fn record_edit<T>(edit: impl FnOnce() -> Result<T, String>) -> Result<T, String> {
+    begin_edit("Edit");
+    let result = edit();
+    let _ = with_history(History::after_edit);
+    result
+}
+
+/// Call an MCP tool, putting raw `call_api` scripts on the undo history since
+/// they may edit the image.
+pub fn call_tool_recorded(name: &str, arguments: Value) -> Result<Value, String> {
+    if name == "call_api" {
+        record_edit(|| crate::mcp::call_tool(name, arguments))
+    } else {
+        crate::mcp::call_tool(name, arguments)
+    }
+}
+
+/// Save the state an edit starts from so a later undo can return to it.
+/// Without that snapshot the history can't step over the edit, so drop it.
+fn begin_edit(what: &str) {
+    if let Err(e) = with_history(|h| h.before_edit(snapshot_image)).and_then(|r| r) {
+        eprintln!("[HISTORY] {what} runs without undo history: {e}");
+        let _ = with_history(History::clear);
+    }
+}
+
+/// Whether a step edits the image and so goes on the undo history. Selection
+/// changes don't: restoring a snapshot leaves the selection alone.
+fn changes_image(params: &ValidatedParams) -> bool {
+    !matches!(
+        params,
+        ValidatedParams::Undo { .. }
+            | ValidatedParams::Redo { .. }
+            | ValidatedParams::Export { .. }
+            | ValidatedParams::SaveXcf { .. }
+            | ValidatedParams::NewImage { .. }
+            | ValidatedParams::OpenImage { .. }
+            | ValidatedParams::CloseImage { .. }
+            | ValidatedParams::SelectAll {}
+            | ValidatedParams::SelectNone {}
+            | ValidatedParams::SelectInvert {}
+            | ValidatedParams::SelectRect { .. }
+            | ValidatedParams::SelectEllipse { .. }
+            | ValidatedParams::SelectPolygon { .. }
+            | ValidatedParams::GrowSelection { .. }
+            | ValidatedParams::ShrinkSelection { .. }
+            | ValidatedParams::FeatherSelection { .. }
+            | ValidatedParams::BorderSelection { .. }
+            | ValidatedParams::SelectColor { .. }
+    )
+}
+
+/// Save the open image to a new history snapshot.
+fn snapshot_image() -> Result<PathBuf, String> {
+    let path = crate::plan_history::new_snapshot_path()?;
+    let saved = run_payload(crate::macros::save_snapshot(&path.to_string_lossy()))
+        .and_then(|result| console_output(&result, "Saving the undo snapshot").map(|_| ()));
+    match saved {
+        Ok(()) => Ok(path),
+        Err(e) => {
+            let _ = std::fs::remove_file(&path);
+            Err(e)
+        }
+    }
+}
+
+/// Put the open image back into a history snapshot.
+fn restore_image(path: &Path) -> Result<(), String> {
+    let result = run_payload(crate::macros::restore_snapshot(&path.to_string_lossy()))?;
+    console_output(&result, "Restoring the image").map(|_| ())
+}
+
+/// Create the canvas `missing_image: create` asks for (`new_image` with its
+/// defaults) and return a note for the step's warnings.
+fn create_missing_image() -> Result<String, String> {
+    let step = ActionStep {
+        op: Op::NewImage,
+        params: serde_json::json!({}),
+        target: Target::default(),
+        stop_on_error: true,
+    };
+    let v = validate_step_for_image(&step, None)?;
+    run_step(&v.params, &v.target)?;
+    match v.params {
+        ValidatedParams::NewImage { width, height, .. } => {
+            Ok(format!("No image was open, so a blank {width}×{height} image was created"))
+        }
+        _ => Ok("No image was open, so a blank image was created".to_string()),
+    }
+}
+
+fn reserve_output(file_name: &str, ext: &str) -> Result<String, String> {
+    let path = crate::file_access::reserve_output_path(file_name, ext)?;
+    Ok(path.to_string_lossy().into_owned())
+}
+
+/// The file an export or save step writes.
+fn output_path(params: &ValidatedParams) -> Option<String> {
+    match params {
+        ValidatedParams::Export { path, .. } | ValidatedParams::SaveXcf { path, .. } => path.clone(),
+        _ => None,
+    }
+}
+
+/// Message for a step that ran, with the resulting size where the step changes it.
+fn success_message(params: &ValidatedParams) -> String {
+    match params {
+        ValidatedParams::Resize { width, height, .. }
+        | ValidatedParams::Crop { width, height, .. }
+        | ValidatedParams::Pad { width, height, .. } => {
+            format!("ok: image is now {width}×{height}")
+        }
+        ValidatedParams::Export { path: Some(path), .. } | ValidatedParams::SaveXcf { path: Some(path), .. } => {
+            format!("ok: saved {path}")
+        }
+        ValidatedParams::NewImage { width, height, .. } => format!("ok: created a {width}×{height} image"),
+        ValidatedParams::OpenImage { path } => format!("ok: opened {path}"),
+        _ => "ok".to_string(),
+    }
+}
+
+/// Expand a step's target into the concrete targets the macros run on.
+fn resolve_targets(target: &Target, image: Option<&ImageInfo>) -> Result<Vec<Target>, String> {
+    match target {
+        Target::AllLayers => {
+            let count = image.map(|i| i.layers.len()).unwrap_or(0);
+            if count == 0 {
+                return Err("Can't apply to all layers: the image's layer list is unavailable".to_string());
+            }
+            Ok((0..count).map(Target::LayerIndex).collect())
+        }
+        other => Ok(vec![other.clone()]),
+    }
+}
+
+/// Run a validated step once per target, stopping at the first failure.
+fn run_for_targets(params: &ValidatedParams, targets: &[Target]) -> Result<Value, String> {
+    // Undo/redo and opening/closing act on the whole image, never per layer
+    if matches!(
+        params,
+        ValidatedParams::Undo { .. }
+            | ValidatedParams::Redo { .. }
+            | ValidatedParams::NewImage { .. }
+            | ValidatedParams::OpenImage { .. }
+            | ValidatedParams::CloseImage { .. }
+    ) {
+        return run_step(params, &Target::default());
+    }
+    let mut last = Value::Null;
+    for target in targets {
+        last = run_step(params, target)?;
+    }
+    Ok(last)
+}
+
+/// Route one validated step to its macro and run it.
+fn run_step(params: &ValidatedParams, target: &Target) -> Result<Value, String> {
+    match params.clone() {
+        ValidatedParams::DrawLine { x1, y1, x2, y2, color } => {
+            // Directly call your existing command logic via macros + MCP
+            let payload = crate::macros::draw_line(x1, y1, x2, y2, &color, target);
+            run_payload(payload)
+        }
+
+        ValidatedParams::CropSquare {} => {
+            let payload = crate::macros::crop_to_square();
+            run_payload(payload)
+        }
+
+        ValidatedParams::Crop { x, y, width, height } => {
+            let payload = crate::macros::crop(x, y, width, height);
+            run_payload(payload)
+        }
+
+        ValidatedParams::AutoCrop {} => {
+            let payload = crate::macros::auto_crop();
+            run_payload(payload)
+        }
+
+        ValidatedParams::CropToSelection {} => {
+            let payload = crate::macros::crop_to_selection();
+            run_payload(payload)
+        }
+
+        ValidatedParams::ResizeWidth { width } => {
+            let payload = crate::macros::resize_width(width);
+            run_payload(payload)
+        }
+
+        ValidatedParams::Resize { scale_width, scale_height, width, height, interpolation } => {
+            let payload = crate::macros::resize(scale_width, scale_height, width, height, interpolation);
+            run_payload(payload)
+        }
+
+        ValidatedParams::Pad { left, top, right, bottom, color, .. } => {
+            let payload = crate::macros::pad(left, top, right, bottom, &color);
+            run_payload(payload)
+        }
+
+        ValidatedParams::BrightnessContrast { brightness, contrast } => {
+            let payload = crate::macros::brightness_contrast(brightness, contrast, target);
+            run_payload(payload)
+        }
+
+        ValidatedParams::Blur { radius } => {
+            let payload = crate::macros::blur(radius, target);
+            run_payload(payload)
+        }
+
+        ValidatedParams::Rotate { degrees, interpolation } => {
+            let payload = crate::macros::rotate(degrees, interpolation);
+            run_payload(payload)
+        }
+
+        ValidatedParams::Flip { orientation } => {
+            let payload = crate::macros::flip(orientation);
+            run_payload(payload)
+        }
+
+        ValidatedParams::Desaturate { mode } => {
+            let payload = crate::macros::desaturate(mode, target);
+            run_payload(payload)
+        }
+
+        ValidatedParams::HueSaturation { range, hue, lightness, saturation } => {
+            let payload = crate::macros::hue_saturation(range, hue, lightness, saturation, target);
+            run_payload(payload)
+        }
+
+        ValidatedParams::Invert {} => {
+            let payload = crate::macros::invert(target);
+            run_payload(payload)
+        }
+
+        ValidatedParams::Threshold { low, high } => {
+            let payload = crate::macros::threshold(low, high, target);
+            run_payload(payload)
+        }
+
+        ValidatedParams::Posterize { levels } => {
+            let payload = crate::macros::posterize(levels, target);
+            run_payload(payload)
+        }
+
+        ValidatedParams::Levels { channel, input_black, input_white, gamma, output_black, output_white } => {
+            let payload = crate::macros::levels(
+                channel, (input_black, input_white), gamma, (output_black, output_white), target,
+            );
+            run_payload(payload)
+        }
+
+        ValidatedParams::Curves { channel, points } => {
+            let payload = crate::macros::curves(channel, &points, target);
+            run_payload(payload)
+        }
+
+        ValidatedParams::ColorBalance { range, cyan_red, magenta_green, yellow_blue, preserve_luminosity } => {
+            let payload = crate::macros::color_balance(
+                range, [cyan_red, magenta_green, yellow_blue], preserve_luminosity, target,
+            );
+            run_payload(payload)
+        }
+
+        ValidatedParams::ColorTemperature { original, intended } => {
+            let payload = crate::macros::color_temperature(original, intended, target);
+            run_payload(payload)
+        }
+
+        ValidatedParams::StretchContrast {} => {
+            let payload = crate::macros::stretch_contrast(target);
+            run_payload(payload)
+        }
+
+        ValidatedParams::GeglFilter { operation, properties } => {
+            let payload = crate::macros::gegl_filter(&operation, &properties, target);
+            run_payload(payload)
+        }
+
+        ValidatedParams::DrawShape { kind, x, y, width, height, color, fill, stroke_width } => {
+            let payload = crate::macros::draw_shape(kind, x, y, width, height, &color, fill, stroke_width, target);
+            run_payload(payload)
+        }
+
+        ValidatedParams::AddText { text, font, size, color, align, x, y, anchor_x, anchor_y, margin } => {
+            let payload = crate::macros::add_text(
+                &text, &font, size, &color, align, (x, y), (anchor_x, anchor_y), margin, target,
+            );
+            run_payload(payload)
+        }
+
+        ValidatedParams::NewLayer { name, fill, opacity, blend_mode } => {
+            let payload = crate::macros::new_layer(&name, fill, opacity, blend_mode, target);
+            run_payload(payload)
+        }
+
+        ValidatedParams::DuplicateLayer { name } => {
+            let payload = crate::macros::duplicate_layer(name.as_deref(), target);
+            run_payload(payload)
+        }
+
+        ValidatedParams::DeleteLayer {} => {
+            let payload = crate::macros::delete_layer(target);
+            run_payload(payload)
+        }
+
+        ValidatedParams::RenameLayer { name } => {
+            let payload = crate::macros::rename_layer(&name, target);
+            run_payload(payload)
+        }
+
+        ValidatedParams::MoveLayer { to } => {
+            let payload = crate::macros::move_layer(to, target);
+            run_payload(payload)
+        }
+
+        ValidatedParams::SetLayerVisibility { visible } => {
+            let payload = crate::macros::set_layer_visibility(visible, target);
+            run_payload(payload)
+        }
+
+        ValidatedParams::MergeDown {} => {
+            let payload = crate::macros::merge_down(target);
+            run_payload(payload)
+        }
+
+        ValidatedParams::SetLayerOpacity { opacity } => {
+            let payload = crate::macros::set_layer_opacity(opacity, target);
+            run_payload(payload)
+        }
+
+        ValidatedParams::SetLayerBlendMode { mode } => {
+            let payload = crate::macros::set_layer_blend_mode(mode, target);
+            run_payload(payload)
+        }
+
+        ValidatedParams::SetActiveLayer {} => {
+            let payload = crate::macros::set_active_layer(target);
+            run_payload(payload)
+        }
+
+        ValidatedParams::SelectAll {} => {
+            let payload = crate::macros::select_all();
+            run_payload(payload)
+        }
+
+        ValidatedParams::SelectNone {} => {
+            let payload = crate::macros::select_none();
+            run_payload(payload)
+        }
+
+        ValidatedParams::SelectInvert {} => {
+            let payload = crate::macros::select_invert();
+            run_payload(payload)
+        }
+
+        ValidatedParams::SelectRect { x, y, width, height, mode } => {
+            let payload = crate::macros::select_rect(x, y, width, height, mode, false);
+            run_payload(payload)
+        }
+
+        ValidatedParams::SelectEllipse { x, y, width, height, mode } => {
+            let payload = crate::macros::select_rect(x, y, width, height, mode, true);
+            run_payload(payload)
+        }
+
+        ValidatedParams::SelectPolygon { points, mode } => {
+            let payload = crate::macros::select_polygon(&points, mode);
+            run_payload(payload)
+        }
+
+        ValidatedParams::GrowSelection { pixels } => {
+            let payload = crate::macros::modify_selection(&format!("grow(image, {pixels})"));
+            run_payload(payload)
+        }
+
+        ValidatedParams::ShrinkSelection { pixels } => {
+            let payload = crate::macros::modify_selection(&format!("shrink(image, {pixels})"));
+            run_payload(payload)
+        }
+
+        ValidatedParams::FeatherSelection { radius } => {
+            let payload = crate::macros::modify_selection(&format!("feather(image, {radius:.1})"));
+            run_payload(payload)
+        }
+
+        ValidatedParams::BorderSelection { pixels } => {
+            let payload = crate::macros::modify_selection(&format!("border(image, {pixels})"));
+            run_payload(payload)
+        }
+
+        ValidatedParams::SelectColor { color, threshold, mode, sample_merged } => {
+            let payload = crate::macros::select_color(&color, threshold, mode, sample_merged, target);
+            run_payload(payload)
+        }
+
+        ValidatedParams::Fill { fill, color, pattern } => {
+            let payload = crate::macros::fill(fill, color.as_ref(), pattern.as_deref(), target);
+            run_payload(payload)
+        }
+
+        ValidatedParams::Gradient { kind, colors, from, to } => {
+            let payload = crate::macros::gradient(kind, &colors, from.zip(to), target);
+            run_payload(payload)
+        }
+
+        ValidatedParams::Undo { steps } => {
+            let undone = with_history(|h| h.undo(steps as usize, snapshot_image, restore_image))??;
+            Ok(serde_json::json!({ "undone": undone, "history": crate::plan_history::depth() }))
+        }
+
+        ValidatedParams::Redo { steps } => {
+            let redone = with_history(|h| h.redo(steps as usize, restore_image))??;
+            Ok(serde_json::json!({ "redone": redone, "history": crate::plan_history::depth() }))
+        }
+
+        ValidatedParams::Export { format, quality, compression, strip_metadata, flatten, path, .. } => {
+            let path = path.ok_or("export has no output path")?;
+            let payload = crate::macros::export_image(&path, format, quality, compression, strip_metadata, flatten);
+            run_payload(payload)
+        }
+
+        ValidatedParams::SaveXcf { path, .. } => {
+            let path = path.ok_or("save_xcf has no output path")?;
+            let payload = crate::macros::save_xcf(&path);
+            run_payload(payload)
+        }
+
+        ValidatedParams::NewImage { width, height, background, transparent } => {
+            let payload = crate::macros::new_image(width, height, &background, transparent);
+            run_payload(payload)
+        }
+
+        ValidatedParams::OpenImage { path } => {
+            let payload = crate::macros::open_image(&path);
+            run_payload(payload)
+        }
+
+        ValidatedParams::CloseImage { discard_changes } => {
+            let payload = crate::macros::close_image(discard_changes);
+            run_payload(payload)
+        }
+    }
+}
+
+/// Size and layers of the open image, from MCP `get_image_metadata`.
+pub fn current_image_info() -> Result<ImageInfo, String> {
+    let result = crate::mcp::call_tool("get_image_metadata", serde_json::json!({}))?;
+    let text = result
+        .get("content")
+        .and_then(|c| c.as_array())
+        .and_then(|arr| arr.first())
+        .and_then(|first| first.get("text"))
+        .and_then(|t| t.as_str())
+        .unwrap_or("");
+    if result.get("isError").and_then(|e| e.as_bool()).unwrap_or(false) {
+        return Err(format!("get_image_metadata failed: {text}"));
+    }
+    let meta: Value = serde_json::from_str(text)
+        .map_err(|e| format!("get_image_metadata returned invalid JSON: {e}"))?;
+    ImageInfo::from_metadata(&meta)
+}
+
+/// Whether GIMP has any image open.
+fn image_is_open() -> Result<bool, String> {
+    let result = run_payload(crate::macros::count_images())?;
+    let text = console_output(&result, "Image count query")?;
+    text.split_whitespace()
+        .rev()
+        .find_map(|word| word.parse::<usize>().ok())
+        .map(|count| count > 0)
+        .ok_or_else(|| format!("Image count query returned no count: {text}"))
+}
+
+/// Font names installed in GIMP, queried over MCP.
+pub fn available_fonts() -> Result<Vec<String>, String> {
+    let result = run_payload(crate::macros::list_fonts())?;
+    let text = console_output(&result, "Font query")?;
+    // The console echoes other output too; the font list is the JSON array in it
+    let json = match (text.find('['), text.rfind(']')) {
+        (Some(start), Some(end)) if start < end => &text[start..=end],
+        _ => return Err(format!("Font query returned no font list: {text}")),
+    };
+    serde_json::from_str(json).map_err(|e| format!("Font query returned invalid JSON: {e}"))
+}
+
+/// What a console `call_api` printed, or an error naming `what` if it failed.
+fn console_output<'a>(result: &'a Value, what: &str) -> Result<&'a str, String> {
+    let text = result
+        .get("structuredContent")
+        .and_then(|sc| sc.get("result"))
+        .and_then(|r| r.as_str())
+        .or_else(|| {
+            result
+                .get("content")
+                .and_then(|c| c.as_array())
+                .and_then(|arr| arr.first())
+                .and_then(|first| first.get("text"))
+                .and_then(|t| t.as_str())
+        })
+        .unwrap_or("");
+    if result.get("isError").and_then(|e| e.as_bool()).unwrap_or(false) {
+        return Err(format!("{what} failed: {text}"));
+    }
+    Ok(text)
+}
+
 /// Helper: execute a macro payload shaped like:
 /// { "name": "call_api", "arguments": {...} }
 fn run_payload(payload: Value) -> Result<Value, String> {
@@ -104,5 +738,295 @@ fn run_payload(payload: Value) -> Result<Value, String> {
         .cloned()
         .ok_or_else(|| "Macro payload missing 'arguments'".to_string())?;
 
-    crate::mcp::call_tool(tool_name, arguments)
+    let result = crate::mcp::call_tool(tool_name, arguments)?;
+    // A Python exception in the script comes back as a result flagged isError
+    console_output(&result, "GIMP")?;
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_mcp::MockMcp;
+    use serde_json::json;
+
+    fn step(op: Op, params: Value) -> ActionStep {
+        ActionStep { op, params, target: Target::default(), stop_on_error: true }
+    }
+
+    fn plan(steps: Vec<ActionStep>) -> ActionPlan {
+        ActionPlan { summary: None, steps, generation: None, missing_image: MissingImage::default() }
+    }
+
+    #[test]
+    fn failed_export_is_reported_and_gives_back_the_file_name() {
+        let dir = std::env::temp_dir().join(format!("plan-execute-export-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let previous = crate::file_access::settings();
+        crate::file_access::set_settings(crate::file_access::FileSettings {
+            output_dir: Some(dir.clone()),
+            ..previous.clone()
+        })
+        .unwrap();
+
+        let mcp = MockMcp::start();
+        mcp.with_image(800, 600, &["Background"]);
+        mcp.on_error(r"file-png-export", "Exception: Export failed: PDBStatusType.EXECUTION_ERROR");
+        let results = execute_plan(plan(vec![step(Op::Export, json!({ "file_name": "card" }))])).unwrap();
+        assert!(!results[0].ok);
+        assert!(results[0].message.contains("Export failed"), "{}", results[0].message);
+        assert_eq!(results[0].path, None);
+        assert!(!dir.join("card.png").exists());
+        assert_eq!(mcp.calls()[0].0, "get_image_metadata");
+        assert!(mcp.scripts()[0].iter().any(|line| line.contains("card.png")));
+
+        let results = execute_plan(plan(vec![step(Op::SaveXcf, json!({ "file_name": "card" }))])).unwrap();
+        assert!(results[0].ok);
+        assert!(results[0].path.as_deref().is_some_and(|p| p.ends_with("card.xcf")));
+
+        // An output directory that can't be written fails the step, not the plan
+        crate::file_access::set_settings(crate::file_access::FileSettings {
+            output_dir: Some(dir.join("card.xcf")),
+            ..previous.clone()
+        })
+        .unwrap();
+        let results = execute_plan(plan(vec![
+            step(Op::Invert, json!({})),
+            ActionStep { stop_on_error: false, ..step(Op::Export, json!({ "file_name": "card" })) },
+            step(Op::Invert, json!({})),
+        ]))
+        .unwrap();
+        assert_eq!(results.iter().map(|r| r.ok).collect::<Vec<_>>(), [true, false, true]);
+        assert_eq!(results[1].path, None);
+
+        drop(mcp);
+        crate::file_access::set_settings(previous).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_layer_and_empty_selection_fail_the_step() {
+        // Metadata without layers can't rule the name out, so GIMP has to
+        let mcp = MockMcp::start();
+        mcp.with_image(800, 600, &[]);
+        mcp.on_error(r"No layer named", "Exception: No layer named Sky");
+        let sky = ActionStep { target: Target::Layer("Sky".to_string()), ..step(Op::Invert, json!({})) };
+        let results = execute_plan(plan(vec![sky, step(Op::Invert, json!({}))])).unwrap();
+        assert_eq!(results.len(), 1, "stop_on_error should skip the second step");
+        assert!(!results[0].ok);
+        assert!(results[0].message.contains("No layer named Sky"), "{}", results[0].message);
+        drop(mcp);
+
+        let mcp = MockMcp::start();
+        mcp.with_image(800, 600, &["Background"]);
+        mcp.on_error(r"Nothing is selected", "Exception: Nothing is selected");
+        let selected = ActionStep { target: Target::Selection, ..step(Op::Invert, json!({})) };
+        let results = execute_plan(plan(vec![selected])).unwrap();
+        assert!(!results[0].ok);
+        assert!(results[0].message.contains("Nothing is selected"), "{}", results[0].message);
+    }
+
+    #[test]
+    fn step_that_fails_validation_fails_on_its_own() {
+        let mcp = MockMcp::start();
+        mcp.with_image(800, 600, &["Background"]);
+        let sky = || ActionStep { target: Target::Layer("Sky".to_string()), ..step(Op::Invert, json!({})) };
+        let results = execute_plan(plan(vec![
+            step(Op::Invert, json!({})),
+            ActionStep { stop_on_error: false, ..sky() },
+            step(Op::Invert, json!({})),
+        ]))
+        .unwrap();
+        assert_eq!(results.iter().map(|r| r.ok).collect::<Vec<_>>(), [true, false, true]);
+        assert!(results[1].message.contains("Sky"), "{}", results[1].message);
+        // The missing layer was caught before anything was sent to GIMP
+        assert_eq!(mcp.scripts().iter().filter(|s| s.contains(&"drawable.invert(False)".to_string())).count(), 2);
+
+        let results = execute_plan(plan(vec![step(Op::Invert, json!({})), sky(), step(Op::Invert, json!({}))])).unwrap();
+        assert_eq!(results.iter().map(|r| r.ok).collect::<Vec<_>>(), [true, false]);
+    }
+
+    #[test]
+    fn no_image_errors_reach_the_step_result() {
+        // GIMP says there are no images: a typed error without running the step
+        let mcp = MockMcp::start();
+        mcp.on_error(r"^get_image_metadata ", "No image open");
+        mcp.on_text(r"len\(Gimp.get_images\(\)\)", "0");
+        let results = execute_plan(plan(vec![step(Op::Invert, json!({}))])).unwrap();
+        assert!(!results[0].ok);
+        assert_eq!(results[0].error, Some(StepError::NoImageOpen));
+        assert_eq!(mcp.scripts().len(), 1, "only the image count should have run");
+
+        // missing_image: create makes a canvas first and notes it
+        let mut create = plan(vec![step(Op::Invert, json!({}))]);
+        create.missing_image = MissingImage::Create;
+        let results = execute_plan(create).unwrap();
+        assert!(results[0].ok, "{}", results[0].message);
+        assert!(results[0].warnings[0].contains("1920×1080"));
+        assert!(mcp.scripts().concat().iter().any(|line| line.contains("Gimp.Image.new(1920, 1080")));
+        drop(mcp);
+
+        // The image count failed too, so the step ran and GIMP's own check raised
+        let mcp = MockMcp::start();
+        mcp.on_error(r"No image is open in GIMP", "Exception: No image is open in GIMP");
+        let results = execute_plan(plan(vec![step(Op::Invert, json!({}))])).unwrap();
+        assert!(!results[0].ok);
+        assert!(results[0].message.contains("No image is open in GIMP"), "{}", results[0].message);
+        drop(mcp);
+
+        // A file outside the allowed directories fails open_image, not the plan
+        let mcp = MockMcp::start();
+        mcp.with_image(800, 600, &["Background"]);
+        let open = ActionStep { stop_on_error: false, ..step(Op::OpenImage, json!({ "path": "/etc/no-such-photo.png" })) };
+        let results = execute_plan(plan(vec![step(Op::Invert, json!({})), open, step(Op::Invert, json!({}))])).unwrap();
+        assert_eq!(results.iter().map(|r| r.ok).collect::<Vec<_>>(), [true, false, true]);
+        assert!(!mcp.scripts().concat().iter().any(|line| line.contains("no-such-photo")));
+        drop(mcp);
+
+        let mcp = MockMcp::start();
+        mcp.with_image(800, 600, &["Background"]);
+        mcp.on_error(r"is_dirty", "Exception: The image has unsaved changes");
+        let results = execute_plan(plan(vec![step(Op::CloseImage, json!({}))])).unwrap();
+        assert!(!results[0].ok);
+        assert!(results[0].message.contains("unsaved changes"), "{}", results[0].message);
+    }
+
+    #[test]
+    fn relative_lengths_need_the_image_size() {
+        // Metadata fails but GIMP says an image is open
+        let mcp = MockMcp::start();
+        mcp.on_error(r"^get_image_metadata ", "metadata unavailable");
+        mcp.on_text(r"len\(Gimp.get_images\(\)\)", "1");
+        let results = execute_plan(plan(vec![
+            step(Op::DrawLine, json!({ "x1": 0, "y1": 0, "x2": 100, "y2": 40 })),
+            step(Op::DrawLine, json!({ "x1": 0, "y1": 0, "x2": "50%", "y2": "2cm" })),
+        ]))
+        .unwrap();
+        assert!(results[0].ok, "pixel lengths don't need the image: {}", results[0].message);
+        assert!(!results[1].ok);
+        assert!(results[1].message.contains("image size"), "{}", results[1].message);
+        assert_eq!(mcp.scripts().concat().iter().filter(|line| line.contains("Gimp.pencil")).count(), 1);
+    }
+
+    #[test]
+    fn unknown_font_fails_its_step_and_keeps_earlier_results() {
+        let mcp = MockMcp::start();
+        mcp.with_image(800, 600, &["Background"]);
+        mcp.on_text(r"fonts_get_list", r#"["DejaVu Sans"]"#);
+        let text = step(Op::AddText, json!({ "text": "Hi", "font": "Comic Neue" }));
+        let results = execute_plan(plan(vec![step(Op::Invert, json!({})), text, step(Op::Invert, json!({}))])).unwrap();
+        assert_eq!(results.len(), 2, "stop_on_error should skip the last step");
+        assert!(results[0].ok);
+        assert!(!results[1].ok);
+        assert!(results[1].message.contains("Comic Neue"), "{}", results[1].message);
+
+        // Without stop_on_error the plan carries on
+        let text = ActionStep { stop_on_error: false, ..step(Op::AddText, json!({ "text": "Hi", "font": "Comic Neue" })) };
+        let results = execute_plan(plan(vec![text, step(Op::Invert, json!({}))])).unwrap();
+        assert!(!results[0].ok);
+        assert!(results[1].ok);
+    }
+
+    #[test]
+    fn font_gimp_cannot_load_fails_the_step() {
+        let mcp = MockMcp::start();
+        mcp.with_image(800, 600, &["Background"]);
+        mcp.on_text(r"fonts_get_list", r#"["DejaVu Sans", "Sans-serif"]"#);
+        mcp.on_error(r"Font not found", "Exception: Font not found: DejaVu Sans");
+        let results = execute_plan(plan(vec![step(Op::AddText, json!({ "text": "Hi", "font": "dejavu sans" }))])).unwrap();
+        assert!(!results[0].ok);
+        assert!(results[0].message.contains("Font not found: DejaVu Sans"), "{}", results[0].message);
+        // The font was matched against GIMP's list before the step ran
+        assert!(mcp.scripts().concat().iter().any(|line| line.contains(r#"Gimp.Font.get_by_name("DejaVu Sans")"#)));
+    }
+
+    #[test]
+    fn raw_scripts_go_on_the_history_and_undo_reopens_the_snapshot() {
+        use crate::plan_history::HistoryDepth;
+
+        let dir = std::env::temp_dir().join(format!("plan-execute-history-{}", std::process::id()));
+        let mcp = MockMcp::start();
+        crate::plan_history::init_dir(dir.clone()).unwrap();
+        let _ = with_history(History::clear);
+        mcp.with_image(800, 600, &["Background"]);
+
+        let results = execute_plan(plan(vec![step(Op::Invert, json!({}))])).unwrap();
+        assert!(results[0].ok, "{}", results[0].message);
+        let script = json!({ "api_path": "exec", "args": ["pyGObject-console", ["pass"]], "kwargs": {} });
+        call_tool_recorded("call_api", script).unwrap();
+        call_tool_recorded("get_image_metadata", json!({})).unwrap();
+        assert_eq!(crate::plan_history::depth(), HistoryDepth { undo: 2, redo: 0 });
+
+        // Undo saves the current state, then reopens the one before the script
+        assert_eq!(undo(1).unwrap(), HistoryDepth { undo: 1, redo: 1 });
+        let scripts = mcp.scripts();
+        let restore = scripts.last().unwrap();
+        assert!(restore.iter().any(|line| line.contains("state-1.xcf")), "{restore:?}");
+        assert!(restore.iter().any(|line| line.contains("Gimp.displays_reconnect(image, snapshot)")));
+        assert!(!restore.iter().any(|line| line.contains("new_from_drawable")));
+
+        let _ = with_history(History::clear);
+        drop(mcp);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn all_layers_steps_run_once_per_layer_and_ops_reach_their_macros() {
+        let mcp = MockMcp::start();
+        mcp.with_image(800, 600, &["Top", "Middle", "Bottom"]);
+        let every_layer = ActionStep { target: Target::AllLayers, ..step(Op::Invert, json!({})) };
+        let flattened = ActionStep { target: Target::Image, ..step(Op::Blur, json!({ "radius": 6 })) };
+        let results = execute_plan(plan(vec![every_layer, flattened])).unwrap();
+        assert!(results.iter().all(|r| r.ok), "{results:?}");
+
+        // History snapshots may run in between, so look only at the edits
+        let scripts = mcp.scripts();
+        let inverts: Vec<_> = scripts.iter().filter(|s| s.contains(&"drawable.invert(False)".to_string())).collect();
+        assert_eq!(inverts.len(), 3);
+        for (index, script) in inverts.iter().enumerate() {
+            assert!(script.contains(&format!("layer = image.get_layers()[{index}]")), "{script:?}");
+            assert!(!script.iter().any(|line| line.contains("flatten")));
+        }
+        let blurs: Vec<_> = scripts.iter().filter(|s| s.iter().any(|line| line.contains("gegl:gaussian-blur"))).collect();
+        assert_eq!(blurs.len(), 1);
+        assert!(blurs[0].contains(&"layer = image.flatten()".to_string()));
+    }
+
+    #[test]
+    fn undo_and_redo_steps_move_through_the_plan_history() {
+        use crate::plan_history::HistoryDepth;
+
+        let dir = std::env::temp_dir().join(format!("plan-execute-undo-{}", std::process::id()));
+        let mcp = MockMcp::start();
+        crate::plan_history::init_dir(dir.clone()).unwrap();
+        let _ = with_history(History::clear);
+        mcp.with_image(800, 600, &["Background"]);
+
+        let results = execute_plan(plan(vec![
+            step(Op::Invert, json!({})),
+            step(Op::Blur, json!({ "radius": 6 })),
+            step(Op::Undo, json!({ "steps": 1 })),
+        ]))
+        .unwrap();
+        assert!(results.iter().all(|r| r.ok), "{results:?}");
+        assert_eq!(crate::plan_history::depth(), HistoryDepth { undo: 1, redo: 1 });
+        // Undo reopened the state from before the blur
+        let restore = mcp.scripts().last().unwrap().clone();
+        assert!(restore.iter().any(|line| line.contains("state-1.xcf")), "{restore:?}");
+
+        let results = execute_plan(plan(vec![step(Op::Redo, json!({ "steps": 1 }))])).unwrap();
+        assert!(results[0].ok, "{}", results[0].message);
+        assert_eq!(crate::plan_history::depth(), HistoryDepth { undo: 2, redo: 0 });
+        let restore = mcp.scripts().last().unwrap().clone();
+        assert!(restore.iter().any(|line| line.contains("state-2.xcf")), "{restore:?}");
+
+        // Nothing left to redo fails the step
+        let results = execute_plan(plan(vec![step(Op::Redo, json!({}))])).unwrap();
+        assert!(!results[0].ok);
+        assert!(results[0].message.contains("Nothing to redo"), "{}", results[0].message);
+
+        let _ = with_history(History::clear);
+        drop(mcp);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,200 @@
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Most states kept, enough for the 50 steps `undo` accepts; older ones are
+/// dropped along with their snapshots.
+const MAX_STATES: usize = 51;
+
+/// How many steps plans can currently undo and redo.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryDepth {
+    pub undo: usize,
+    pub redo: usize,
+}
+
+/// Saved states of the image, oldest first, each an XCF snapshot file.
+#[derive(Debug, Default)]
+pub struct History {
+    states: Vec<PathBuf>,
+    /// Index of the state the image is in. Equal to `states.len()` after an
+    /// edit, until the next edit or an undo saves that state.
+    cursor: usize,
+}
+
+impl History {
+    pub fn depth(&self) -> HistoryDepth {
+        HistoryDepth {
+            undo: self.cursor,
+            redo: self.states.len().saturating_sub(self.cursor + 1),
+        }
+    }
+
+    /// Call before an edit runs: drops the redo branch and saves the current
+    /// state with `snapshot` unless it's saved already.
+    pub fn before_edit(&mut self, snapshot: impl FnOnce() -> Result<PathBuf, String>) -> Result<(), String> {
+        self.truncate(self.cursor + 1);
+        if self.cursor == self.states.len() {
+            self.states.push(snapshot()?);
+        }
+        while self.states.len() > MAX_STATES {
+            remove_snapshot(&self.states.remove(0));
+            self.cursor -= 1;
+        }
+        Ok(())
+    }
+
+    /// Call after an edit ran (or failed partway): the image is in a new state.
+    pub fn after_edit(&mut self) {
+        self.cursor = self.states.len();
+    }
+
+    /// Go back up to `steps` states and return how many it went back. The
+    /// current state is saved first so redo can return to it.
+    pub fn undo(
+        &mut self,
+        steps: usize,
+        snapshot: impl FnOnce() -> Result<PathBuf, String>,
+        restore: impl FnOnce(&Path) -> Result<(), String>,
+    ) -> Result<usize, String> {
+        let steps = steps.min(self.cursor);
+        if steps == 0 {
+            return Err("Nothing to undo".to_string());
+        }
+        if self.cursor == self.states.len() {
+            self.states.push(snapshot()?);
+        }
+        let target = self.cursor - steps;
+        restore(&self.states[target])?;
+        self.cursor = target;
+        Ok(steps)
+    }
+
+    /// Go forward up to `steps` undone states and return how many it went forward.
+    pub fn redo(&mut self, steps: usize, restore: impl FnOnce(&Path) -> Result<(), String>) -> Result<usize, String> {
+        let steps = steps.min(self.depth().redo);
+        if steps == 0 {
+            return Err("Nothing to redo".to_string());
+        }
+        let target = self.cursor + steps;
+        restore(&self.states[target])?;
+        self.cursor = target;
+        Ok(steps)
+    }
+
+    /// Forget every state, e.g. when a different image becomes the current one.
+    pub fn clear(&mut self) {
+        self.truncate(0);
+        self.cursor = 0;
+    }
+
+    fn truncate(&mut self, len: usize) {
+        let len = len.min(self.states.len());
+        for path in self.states.drain(len..) {
+            remove_snapshot(&path);
+        }
+    }
+}
+
+fn remove_snapshot(path: &Path) {
+    if let Err(e) = std::fs::remove_file(path) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            eprintln!("[HISTORY] Failed to remove snapshot {}: {e}", path.display());
+        }
+    }
+}
+
+static HISTORY: Lazy<Mutex<History>> = Lazy::new(|| Mutex::new(History::default()));
+
+/// Where snapshots are written. Unset means plans run without undo history.
+static SNAPSHOT_DIR: Lazy<Mutex<Option<PathBuf>>> = Lazy::new(|| Mutex::new(None));
+
+/// Keep snapshots in `dir`, deleting any a previous run left behind.
+pub fn init_dir(dir: PathBuf) -> Result<(), String> {
+    std::fs::create_dir_all(&dir)
+        .map_err(|e| format!("Failed to create history directory {}: {e}", dir.display()))?;
+    let entries = std::fs::read_dir(&dir)
+        .map_err(|e| format!("Failed to read history directory {}: {e}", dir.display()))?;
+    for entry in entries.flatten() {
+        if entry.path().extension().is_some_and(|ext| ext == "xcf") {
+            remove_snapshot(&entry.path());
+        }
+    }
+    let mut stored = SNAPSHOT_DIR
+        .lock()
+        .map_err(|_| "History directory mutex poisoned".to_string())?;
+    *stored = Some(dir);
+    Ok(())
+}
+
+/// Claim a new, empty snapshot file.
+pub fn new_snapshot_path() -> Result<PathBuf, String> {
+    let dir = SNAPSHOT_DIR
+        .lock()
+        .ok()
+        .and_then(|d| d.clone())
+        .ok_or_else(|| "Undo history is unavailable: no history directory".to_string())?;
+    crate::file_access::reserve_path(&dir, "state", "xcf")
+}
+
+/// Run `f` on the app's history.
+pub fn with_history<T>(f: impl FnOnce(&mut History) -> T) -> Result<T, String> {
+    let mut history = HISTORY.lock().map_err(|_| "History mutex poisoned".to_string())?;
+    Ok(f(&mut history))
+}
+
+pub fn depth() -> HistoryDepth {
+    with_history(|h| h.depth()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_moves_back_and_forward_and_edits_drop_redo() {
+        let mut saved = 0;
+        let mut snapshot = || {
+            saved += 1;
+            Ok(PathBuf::from(format!("state-{saved}.xcf")))
+        };
+        let mut history = History::default();
+        assert!(history.undo(1, || unreachable!(), |_| Ok(())).is_err());
+
+        // Two edits: the state before each is saved
+        history.before_edit(&mut snapshot).unwrap();
+        history.after_edit();
+        history.before_edit(&mut snapshot).unwrap();
+        history.after_edit();
+        assert_eq!(history.depth(), HistoryDepth { undo: 2, redo: 0 });
+
+        // Undo saves the current state, then restores the one before the last edit
+        let restored = std::cell::RefCell::new(PathBuf::new());
+        let record = |p: &Path| {
+            *restored.borrow_mut() = p.to_path_buf();
+            Ok(())
+        };
+        assert_eq!(history.undo(1, &mut snapshot, record).unwrap(), 1);
+        assert_eq!(*restored.borrow(), PathBuf::from("state-2.xcf"));
+        assert_eq!(history.depth(), HistoryDepth { undo: 1, redo: 1 });
+        assert_eq!(history.undo(5, || unreachable!(), record).unwrap(), 1);
+        assert_eq!(*restored.borrow(), PathBuf::from("state-1.xcf"));
+        assert_eq!(history.redo(5, record).unwrap(), 2);
+        assert_eq!(*restored.borrow(), PathBuf::from("state-3.xcf"));
+        assert_eq!(history.depth(), HistoryDepth { undo: 2, redo: 0 });
+
+        // A new edit after an undo clears what could have been redone
+        history.undo(1, || unreachable!(), |_| Ok(())).unwrap();
+        history.before_edit(|| unreachable!()).unwrap();
+        history.after_edit();
+        assert_eq!(history.depth(), HistoryDepth { undo: 2, redo: 0 });
+        assert!(history.redo(1, |_| Ok(())).is_err());
+
+        // A failed restore leaves the cursor where it was
+        assert!(history.undo(1, &mut snapshot, |_| Err("gone".to_string())).is_err());
+        assert_eq!(history.depth(), HistoryDepth { undo: 2, redo: 0 });
+        history.clear();
+        assert_eq!(history.depth(), HistoryDepth::default());
+    }
+}
@@ -1,9 +1,28 @@
+use crate::llm_client::Role;
+use crate::plan_memory::PlanExample;
 use crate::plan_schema::{ActionPlan, Op, Target};
 use serde_json::json;
 
-fn planner_prompt(user_text: &str) -> String {
+/// How many past plans to show the planner as few-shot examples.
+const FEW_SHOT_EXAMPLES: usize = 3;
+
+/// Render past successful plans as few-shot examples for the planner prompt.
+fn examples_section(examples: &[PlanExample]) -> String {
+    if examples.is_empty() {
+        return String::new();
+    }
+    let mut section = String::from("Examples of past requests and plans that worked:\n");
+    for ex in examples {
+        let plan = json!({ "summary": ex.plan.summary, "steps": ex.plan.steps });
+        section.push_str(&format!("\nRequest: {}\nPlan: {}\n", ex.prompt, plan));
+    }
+    section
+}
+
+fn planner_prompt(user_text: &str, examples: &[PlanExample]) -> String {
     // IMPORTANT: LLM must output ONLY JSON matching ActionPlan schema.
     // No Python. No tools. No prose.
+    let op_names: Vec<String> = Op::ALL.iter().map(|op| op.name()).collect();
     format!(
 r#"
 You are a planner for a GIMP assistant.
@@ -11,13 +30,7 @@ You are a planner for a GIMP assistant.
 Your job: convert the user's request into an ActionPlan JSON object.
 
 Allowed ops (must be one of these):
-- draw_line
-- crop_square
-- resize_width
-- brightness_contrast
-- blur
-- undo
-- redo
+{op_list}
 
 Rules:
 - Output MUST be valid JSON only.
@@ -25,47 +38,207 @@ Rules:
 - Use this schema exactly:
 {{
   "summary": "optional short summary",
+  "missing_image": "fail" | "create",
   "steps": [
     {{
-      "op": "blur|brightness_contrast|resize_width|crop_square|draw_line|undo|redo",
+      "op": "{op_choices}",
       "params": {{ ... }},
-      "target": "active_layer",
+      "target": "active_layer" | "selection" | "all_layers" | "image" | {{ "layer": "name" }} | {{ "layer_index": int }}
+                | {{ "region": {{ "shape": "rect|ellipse", "x": int, "y": int, "width": int, "height": int }} }},
       "stop_on_error": true|false
     }}
   ]
 }}
 
+Targets (omit for the active layer):
+- "selection" limits the step to what the user has selected in GIMP.
+- {{ "layer": "name" }} only for layer names the user mentioned; "all_layers" runs the step on every layer.
+- {{ "region": ... }} for "the top half", "a circle in the middle" etc. when no selection exists.
+- crop_square, crop, resize_width, resize, pad, border, rotate, flip, undo, redo, export, save_xcf, new_image, open_image
+  and close_image always apply to the whole image: omit target.
+
 Parameter requirements:
 - blur params: {{ "radius": number }}
 - brightness_contrast params: {{ "brightness": number, "contrast": number }}
 - resize_width params: {{ "width": integer }}
+- resize params: {{ "mode": "width|height|percent|exact|fit|fill", "width": length, "height": length, "percent": number,
+  "interpolation": "none|linear|cubic|nohalo|lohalo", "no_upscale": bool }}
+  width: needs width; height: needs height; percent: needs percent; exact/fit/fill: need width and height.
+  "fit" keeps everything inside the box; "fill" covers the box and crops the overflow (e.g. a 1080×1080 post).
+  Use "no_upscale": true for "no bigger than" / "at most".
+- pad params: {{ "top": length, "right": length, "bottom": length, "left": length, "all": length, "color": string }}
+  or {{ "aspect": "1:1", "color": string }} to pad evenly out to a ratio ("pad to square for Instagram").
+  color defaults to white; "transparent" leaves the new area empty.
+- border params: {{ "width": length, "color": string }} — "add a white border", "5% black frame".
+- crop params: {{ "mode": "rect", "rect": {{ "x": length, "y": length, "width": length, "height": length }} }}
+  or {{ "mode": "aspect", "aspect": "16:9", "gravity": "center|top|bottom|left|right|face_safe" }}
+  or {{ "mode": "auto" }} (trim plain borders) or {{ "mode": "selection" }} (crop to what the user selected).
+  Use face_safe for portraits/people; "for Instagram stories" is 9:16, "widescreen" is 16:9.
 - crop_square params: {{ }}
-- draw_line params: {{ "x1": int, "y1": int, "x2": int, "y2": int }}
-  If no coordinates are given, use defaults: {{ "x1": 0, "y1": 0, "x2": 200, "y2": 200 }}
+- Positions and sizes are lengths: pixels (120), percent of the image ("50%"), units at the image DPI ("2cm", "1in")
+  or edge keywords ("left", "center", "bottom"). Points can also be anchors: "center", "top-left", "bottom-right"…
+  Prefer percentages and anchors: you don't know the image size.
+- draw_line params: {{ "from": point, "to": point, "color": string }} or {{ "x1": length, "y1": length, "x2": length, "y2": length }}
+  (color optional, default black). If no position is given, use {{ "from": "top-left", "to": "bottom-right" }}
   "add a line", "paint a line", "insert a line", "make a line", "black line" all map to draw_line.
-- undo/redo params: {{ "steps": int }} (default 1 if omitted)
+- rotate params: {{ "degrees": number, "interpolation": "none|linear|cubic|nohalo|lohalo" }}
+  degrees are clockwise; use -90 or 270 for counter-clockwise. 90/180/270 are lossless;
+  other angles (e.g. "straighten by 3 degrees") use interpolation (default "cubic").
+- flip params: {{ "orientation": "horizontal" | "vertical" }}
+  "mirror" means horizontal; "upside down" means vertical flip (or rotate 180).
+- desaturate params: {{ "mode": "luminance|luma|lightness|average|value" }} (default "luminance")
+  "black and white", "greyscale", "remove the color" all map to desaturate.
+- hue_saturation params: {{ "range": "all|red|yellow|green|cyan|blue|magenta", "hue": number, "lightness": number, "saturation": number }}
+  hue -180..180, lightness and saturation -100..100; omitted values are 0. "more saturated" → saturation 30.
+- invert params: {{ }}
+- threshold params: {{ "low": int, "high": int }} (0..255, defaults 127 and 255)
+- posterize params: {{ "levels": int }} (2..255)
+- draw_shape params: {{ "kind": "rectangle|ellipse|circle|triangle|heart", "center": point,
+  "size": length | {{ "width": length, "height": length }}, "color": string, "fill": bool, "stroke_width": number }}
+  or give "bbox": {{ "x": length, "y": length, "width": length, "height": length }} instead of center/size.
+  A size in % is of the shorter image side.
+  Omit center/size to draw in the middle of the image. "outline"/"hollow" means "fill": false.
+  Compose drawings from several draw_shape steps (e.g. a snowman is three circles).
+- add_text params: {{ "text": string, "font": string, "size": length, "color": string,
+  "align": "left|center|right|fill", "anchor": point }}
+  The text box sits at the anchor: "bottom" puts a caption centered just above the bottom edge,
+  "top-left" a label in the top-left corner. Size defaults to 6% of the shorter side; "24pt" also works.
+  Omit font unless the user names one. "add a caption", "put my name at the bottom", "write …" map to add_text.
+- Layer ops act on the step's target layer (omit target for the active layer, or {{ "layer": "name" }}):
+  new_layer {{ "name": string, "fill": "transparent|white|foreground|background", "opacity": 0-100, "blend_mode": mode }}
+  (added above the target), duplicate_layer {{ "name": string }}, delete_layer {{ }}, rename_layer {{ "name": string }},
+  move_layer {{ "to": "up|down|top|bottom" | {{ "index": int }} }} (0 is the top), set_layer_visibility {{ "visible": bool }},
+  merge_down {{ }}, set_layer_opacity {{ "opacity": 0-100 }}, set_layer_blend_mode {{ "mode": mode }}, set_active_layer {{ }}.
+  Modes: normal, multiply, screen, overlay, soft_light, hard_light, darken, lighten, difference, addition,
+  subtract, dodge, burn, hue, saturation, color, luminosity.
+  Prefer working on a new or duplicated layer when the user wants to keep the original.
+- Selection ops change GIMP's selection and keep it for later steps (target "selection" to use it):
+  select_all {{ }}, select_none {{ }}, select_invert {{ }},
+  select_shape {{ "shape": "rect|ellipse", "rect": {{ "x": length, "y": length, "width": length, "height": length }}, "mode": mode }}
+  or {{ "shape": "polygon", "points": [point, point, point, …], "mode": mode }},
+  modify_selection {{ "action": "grow|shrink|feather|border", "amount": length }},
+  select_color {{ "color": string, "threshold": 0-255, "mode": mode, "sample_merged": bool }} (e.g. "select the sky").
+  Modes: replace (default), add, subtract, intersect.
+  e.g. "darken everything except the center": select_shape ellipse, select_invert, then brightness_contrast with target "selection".
+- fill params: {{ "fill": "foreground|background|white|transparent|pattern", "color": string, "pattern": string }}
+  (color sets the foreground/background color; "transparent" erases). Fills the selection, or the whole layer.
+- gradient params: {{ "kind": "linear|radial|conical", "colors": [string, string, …], "from": point, "to": point }}
+  Omit from/to to span the selection top to bottom (radial: from its center). Use a region target or a
+  select_shape step first to limit it, e.g. "a sunset gradient on the top half": target
+  {{ "region": {{ "shape": "rect", "x": 0, "y": 0, "width": W, "height": H/2 }} }} or select the top half first,
+  colors ["coral", "orange", "gold"].
+- levels params: {{ "channel": "value|red|green|blue|alpha", "input_black": 0-255, "input_white": 0-255, "gamma": 0.1-10,
+  "output_black": 0-255, "output_white": 0-255 }} (defaults 0, 255, 1.0, 0, 255; one channel per step).
+  "lift the shadows" → output_black 20; "brighter midtones" → gamma 1.3.
+- curves params: {{ "channel": channel, "points": [[in, out], …] }} in 0..255, inputs increasing;
+  an S-curve for more contrast: [[0, 0], [64, 50], [192, 205], [255, 255]].
+- color_balance params: {{ "range": "shadows|midtones|highlights", "cyan_red": -100-100, "magenta_green": -100-100,
+  "yellow_blue": -100-100, "preserve_luminosity": bool }} (one range per step; negative is cyan/magenta/yellow).
+- color_temperature params: {{ "original": kelvin, "intended": kelvin }} (original defaults to 6500);
+  warmer → intended 7500-9000, cooler → 4500-5500.
+- stretch_contrast params: {{ }} — "auto contrast", "fix the washed-out photo".
+- gegl_filter params: {{ "operation": name, "properties": {{ … }} }} — only these filters and properties
+  (name (operation): what it does — property min..max (default)); omit properties to use the defaults:
+{filters}
+- Colors are CSS strings: a name ("crimson", "light blue"), hex like #1e90ff, rgb(r, g, b) or hsl(h, s%, l%).
+  For shades like "reddish-brown" pick the closest hex value.
+- undo/redo params: {{ "steps": int }} (default 1 if omitted). Undo steps back through edits earlier plans made;
+  redo re-applies what was undone, until a new edit is made.
+- export params: {{ "format": "png|jpeg|webp|tiff|gif", "file_name": string, "quality": 1-100, "compression": 0-9,
+  "strip_metadata": bool, "flatten": bool }}. file_name is a plain name ("card" or "card.jpg"), never a path;
+  files go to the user's output folder and are never overwritten. Use this only when the user asks to save/export.
+- save_xcf params: {{ "file_name": string }} — keep the layers in GIMP's own format.
+- new_image params: {{ "width": int, "height": int, "background": string, "transparent": bool }} (default 1920×1080 white).
+  Start with new_image when the user asks for a new/blank canvas; later steps then edit it.
+- open_image params: {{ "path": string }} — a file name from the output folder or a path the user gave.
+- close_image params: {{ "discard_changes": bool }} — only discard when the user says so.
+- "missing_image": "create" lets drawing requests work with no image open by starting from a blank white canvas;
+  omit it (the default "fail") when the request is about editing an existing photo.
 
 If the user is vague (e.g. "make it nicer"), return a plan with ZERO steps and a summary asking ONE clarification question.
 If the user requests any kind of line (regardless of color or phrasing), always use draw_line — never treat a line request as vague.
 
+{examples}
 User request:
 {user}
 "#,
+        op_list = op_names.iter().map(|n| format!("- {n}")).collect::<Vec<_>>().join("\n"),
+        op_choices = op_names.join("|"),
+        filters = crate::gegl_filters::prompt_lines(),
+        examples = examples_section(examples),
         user = user_text
     )
 }
 
-pub async fn make_plan_from_text(user_text: &str) -> Result<ActionPlan, String> {
-    let prompt = planner_prompt(user_text);
-
-    // Uses your existing Ollama client
-    let raw = crate::llm_client::chat(&prompt).await?;
-
+/// Parse the planner's reply and check every step validates.
+fn parse_plan(raw: &str) -> Result<ActionPlan, String> {
     // Strip anything before first '{' in case model misbehaves
-    let json_str = if let Some(idx) = raw.find('{') { &raw[idx..] } else { raw.as_str() };
+    let json_str = if let Some(idx) = raw.find('{') { &raw[idx..] } else { raw };
 
     let plan: ActionPlan = serde_json::from_str(json_str)
         .map_err(|e| format!("Failed to parse ActionPlan JSON: {e}\nLLM output was:\n{raw}"))?;
 
+    for (i, step) in plan.steps.iter().enumerate() {
+        crate::plan_validate::validate_step(step)
+            .map_err(|e| format!("Plan step {i} is invalid: {e}"))?;
+    }
+
     Ok(plan)
 }
+
+pub async fn make_plan_from_text(user_text: &str) -> Result<ActionPlan, String> {
+    // Past plans for similar requests make the planner much more accurate
+    let examples = crate::plan_memory::retrieve(user_text, FEW_SHOT_EXAMPLES).await;
+    let prompt = planner_prompt(user_text, &examples);
+
+    // Uses your existing Ollama client; a plan that fails to parse or validate
+    // on a small model is retried on the large one
+    let routed = crate::llm_client::chat_validated(Role::Planner, &prompt, parse_plan).await?;
+    let mut plan = routed.value;
+
+    // Record the model and sampling options so a bad plan can be reproduced
+    let mut generation = crate::llm_client::generation_record(Role::Planner);
+    generation.model = routed.model;
+    plan.generation = Some(generation);
+
+    Ok(plan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_llm::MockLlm;
+
+    #[tokio::test]
+    async fn make_plan_from_text_parses_planner_output() {
+        let mock = MockLlm::start();
+        mock.on(
+            r"planner for a GIMP assistant",
+            r#"Here you go: {"summary": "Blur", "steps": [{"op": "blur", "params": {"radius": 5}}]}"#,
+        );
+
+        let plan = make_plan_from_text("blur it a bit").await.unwrap();
+
+        assert_eq!(plan.steps.len(), 1);
+        assert!(matches!(plan.steps[0].op, Op::Blur));
+        assert!(mock.prompts()[0].contains("blur it a bit"));
+    }
+
+    #[tokio::test]
+    async fn deterministic_mode_pins_planner_options() {
+        let mock = MockLlm::start();
+        let mut settings = crate::llm_client::settings();
+        settings.deterministic = true;
+        crate::llm_client::set_settings(settings).unwrap();
+        mock.on(r"planner for a GIMP assistant", r#"{"steps": []}"#);
+
+        let plan = make_plan_from_text("make it nicer").await.unwrap();
+
+        let options = &mock.requests()[0]["options"];
+        assert_eq!(options["temperature"], 0.0);
+        assert_eq!(options["seed"], crate::llm_client::DETERMINISTIC_SEED);
+        let generation = plan.generation.unwrap();
+        assert!(generation.deterministic);
+        assert_eq!(generation.options.seed, Some(crate::llm_client::DETERMINISTIC_SEED));
+    }
+}
@@ -0,0 +1,251 @@
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::plan_schema::ActionPlan;
+
+/// Examples scoring below these are not worth showing to the planner. Prompt
+/// embeddings of unrelated image edits still tend to sit around 0.3-0.5 cosine
+/// similarity, while keyword overlap is 0 for them, so each scorer has its own bar.
+const MIN_COSINE_SCORE: f32 = 0.6;
+const MIN_KEYWORD_SCORE: f32 = 0.2;
+
+/// Cap on stored examples; the oldest are dropped first.
+const MAX_EXAMPLES: usize = 500;
+
+/// What the user thought of the result.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Feedback {
+    pub helpful: bool,
+    #[serde(default)]
+    pub comment: Option<String>,
+}
+
+/// A request that was planned and executed successfully.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlanExample {
+    pub id: u64,
+    pub prompt: String,
+    pub plan: ActionPlan,
+    #[serde(default)]
+    pub feedback: Option<Feedback>,
+    /// Prompt embedding; None if the embedding model wasn't available.
+    #[serde(default)]
+    pub embedding: Option<Vec<f32>>,
+    pub created_at: u64,
+}
+
+#[derive(Default)]
+struct Store {
+    examples: Vec<PlanExample>,
+    path: Option<PathBuf>,
+}
+
+static STORE: Lazy<Mutex<Store>> = Lazy::new(|| Mutex::new(Store::default()));
+
+impl Store {
+    fn save(&self) -> Result<(), String> {
+        let Some(path) = &self.path else { return Ok(()) };
+        let json = serde_json::to_string(&self.examples)
+            .map_err(|e| format!("Failed to serialize plan examples: {e}"))?;
+        std::fs::write(path, json)
+            .map_err(|e| format!("Failed to save plan examples to {}: {e}", path.display()))
+    }
+}
+
+/// Load examples saved by a previous run and persist future changes to `path`.
+pub fn init_store(path: PathBuf) -> Result<(), String> {
+    let mut store = STORE
+        .lock()
+        .map_err(|_| "Plan example store mutex poisoned".to_string())?;
+
+    if path.exists() {
+        let text = std::fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read plan examples from {}: {e}", path.display()))?;
+        store.examples = serde_json::from_str(&text)
+            .map_err(|e| format!("Invalid plan examples in {}: {e}", path.display()))?;
+    }
+    store.path = Some(path);
+    Ok(())
+}
+
+/// Remember a prompt and the plan that executed successfully for it.
+/// Returns the example id so the UI can attach feedback later.
+pub async fn record(prompt: &str, plan: &ActionPlan) -> Result<u64, String> {
+    let embedding = match crate::llm_client::embed(prompt).await {
+        Ok(v) => Some(v),
+        Err(e) => {
+            eprintln!("[MEMORY] No embedding for example, keyword matching only: {e}");
+            None
+        }
+    };
+
+    let mut plan = plan.clone();
+    plan.generation = None;
+
+    let mut store = STORE
+        .lock()
+        .map_err(|_| "Plan example store mutex poisoned".to_string())?;
+
+    let id = store.examples.iter().map(|e| e.id).max().unwrap_or(0) + 1;
+    store.examples.push(PlanExample {
+        id,
+        prompt: prompt.to_string(),
+        plan,
+        feedback: None,
+        embedding,
+        created_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+    });
+    if store.examples.len() > MAX_EXAMPLES {
+        let excess = store.examples.len() - MAX_EXAMPLES;
+        store.examples.drain(..excess);
+    }
+    store.save()?;
+    Ok(id)
+}
+
+/// Attach the user's feedback to a stored example.
+pub fn set_feedback(id: u64, feedback: Feedback) -> Result<(), String> {
+    let mut store = STORE
+        .lock()
+        .map_err(|_| "Plan example store mutex poisoned".to_string())?;
+    let example = store
+        .examples
+        .iter_mut()
+        .find(|e| e.id == id)
+        .ok_or_else(|| format!("No plan example with id {id}"))?;
+    example.feedback = Some(feedback);
+    store.save()
+}
+
+/// The `k` stored examples most similar to `prompt`. Uses embeddings when the
+/// embedding model is available, keyword overlap otherwise.
+pub async fn retrieve(prompt: &str, k: usize) -> Vec<PlanExample> {
+    let examples = match STORE.lock() {
+        Ok(store) if !store.examples.is_empty() => store.examples.clone(),
+        _ => return Vec::new(),
+    };
+
+    let query_embedding = crate::llm_client::embed(prompt).await.ok();
+    rank(&examples, prompt, query_embedding.as_deref(), k)
+}
+
+/// Score and pick the best `k` examples. Examples the user marked unhelpful are skipped.
+pub fn rank(
+    examples: &[PlanExample],
+    prompt: &str,
+    query_embedding: Option<&[f32]>,
+    k: usize,
+) -> Vec<PlanExample> {
+    let query_words = keywords(prompt);
+
+    let mut scored: Vec<(f32, &PlanExample)> = examples
+        .iter()
+        .filter(|e| e.feedback.as_ref().is_none_or(|f| f.helpful))
+        .filter_map(|e| {
+            let (score, min_score) = match (query_embedding, e.embedding.as_deref()) {
+                (Some(q), Some(v)) if q.len() == v.len() => (cosine(q, v), MIN_COSINE_SCORE),
+                _ => (jaccard(&query_words, &keywords(&e.prompt)), MIN_KEYWORD_SCORE),
+            };
+            (score >= min_score).then_some((score, e))
+        })
+        .map(|(score, e)| {
+            // Confirmed-good examples win ties
+            let bonus = if e.feedback.as_ref().is_some_and(|f| f.helpful) { 0.05 } else { 0.0 };
+            (score + bonus, e)
+        })
+        .collect();
+
+    scored.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+    scored.into_iter().take(k).map(|(_, e)| e.clone()).collect()
+}
+
+fn keywords(text: &str) -> HashSet<String> {
+    const STOP_WORDS: &[&str] = &["a", "an", "the", "it", "to", "of", "and", "my", "please", "make", "image", "picture", "photo"];
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty() && !STOP_WORDS.contains(w))
+        .map(|w| w.to_string())
+        .collect()
+}
+
+fn jaccard(a: &HashSet<String>, b: &HashSet<String>) -> f32 {
+    let union = a.union(b).count();
+    if union == 0 {
+        return 0.0;
+    }
+    a.intersection(b).count() as f32 / union as f32
+}
+
+fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let na: f32 = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let nb: f32 = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if na == 0.0 || nb == 0.0 {
+        0.0
+    } else {
+        dot / (na * nb)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example(id: u64, prompt: &str, feedback: Option<bool>) -> PlanExample {
+        PlanExample {
+            id,
+            prompt: prompt.to_string(),
+            plan: serde_json::from_str(r#"{"steps": []}"#).unwrap(),
+            feedback: feedback.map(|helpful| Feedback { helpful, comment: None }),
+            embedding: None,
+            created_at: 0,
+        }
+    }
+
+    #[test]
+    fn keyword_fallback_ranks_by_overlap_and_skips_unhelpful() {
+        let examples = vec![
+            example(1, "blur the background a lot", None),
+            example(2, "blur it slightly", Some(false)),
+            example(3, "crop to a square", None),
+        ];
+
+        let picked = rank(&examples, "blur slightly please", None, 3);
+
+        let ids: Vec<u64> = picked.iter().map(|e| e.id).collect();
+        assert_eq!(ids, vec![1]);
+    }
+
+    #[test]
+    fn embeddings_are_preferred_when_available() {
+        let mut near = example(1, "totally different words", None);
+        near.embedding = Some(vec![1.0, 0.0]);
+        let mut far = example(2, "blur the image", None);
+        far.embedding = Some(vec![0.0, 1.0]);
+
+        let picked = rank(&[near, far], "blur the image", Some(&[0.9, 0.1]), 1);
+
+        assert_eq!(picked[0].id, 1);
+    }
+
+    #[test]
+    fn each_scorer_has_its_own_threshold() {
+        // cos = 0.5: passes the keyword bar, but is too weak a match for embeddings
+        let mut loose = example(1, "sharpen the edges", None);
+        loose.embedding = Some(vec![0.5, 0.866]);
+        // No embedding: a Jaccard score of 0.25 is a real match
+        let keyword = example(2, "sharpen the text a bit", None);
+
+        let picked = rank(&[loose, keyword], "sharpen the edges", Some(&[1.0, 0.0]), 3);
+
+        let ids: Vec<u64> = picked.iter().map(|e| e.id).collect();
+        assert_eq!(ids, vec![2]);
+    }
+}
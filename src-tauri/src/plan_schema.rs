@@ -1,5 +1,9 @@
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{json, Value};
+
+use crate::llm_client::GenerationRecord;
+
+pub use crate::color::Color;
 
 /// A safe, structured plan produced by the LLM (planner only).
 /// Execution code will validate + route each step to deterministic macros.
@@ -10,6 +14,25 @@ pub struct ActionPlan {
 
     /// Ordered steps to execute.
     pub steps: Vec<ActionStep>,
+
+    /// Generation settings the planner ran with (filled in by us, not the LLM).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub generation: Option<GenerationRecord>,
+
+    /// What to do when a step needs an image and GIMP has none open.
+    #[serde(default)]
+    pub missing_image: MissingImage,
+}
+
+/// How the executor handles a step that needs an image when none is open.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MissingImage {
+    /// Fail the step with a `no_image_open` error.
+    #[default]
+    Fail,
+    /// Create a blank white canvas (see `new_image`'s defaults) and carry on.
+    Create,
 }
 
 /// One atomic action that maps to exactly one macro/tool.
@@ -20,7 +43,7 @@ pub struct ActionStep {
     /// Parameters for the operation (typed per op, validated later).
     pub params: Value,
 
-    /// Target context (kept simple for Phase 2.1).
+    /// What the step applies to; the active layer unless the plan says otherwise.
     #[serde(default)]
     pub target: Target,
 
@@ -29,15 +52,80 @@ pub struct ActionStep {
     pub stop_on_error: bool,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// What a step applies to. In plans: `"active_layer"`, `"selection"`, `"all_layers"`,
+/// `"image"`, `{"layer": "Name"}`, `{"layer_index": 0}` or `{"region": {...}}`.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum Target {
+    /// The layer selected in GIMP (the top layer if none is).
+    #[default]
     ActiveLayer,
+    /// The active layer, limited to GIMP's current selection.
+    Selection,
+    /// A layer by name.
+    Layer(String),
+    /// A layer by position, 0 = top of the stack.
+    LayerIndex(usize),
+    /// Every layer; the executor runs the step once per layer.
+    AllLayers,
+    /// The active layer, limited to a rectangle or ellipse (image pixels).
+    Region(Region),
+    /// The whole image flattened to one layer.
+    Image,
 }
 
-impl Default for Target {
-    fn default() -> Self {
-        Target::ActiveLayer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RegionShape {
+    #[default]
+    Rect,
+    Ellipse,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Region {
+    #[serde(default)]
+    pub shape: RegionShape,
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Target {
+    /// JSON Schema for a step's `target`, offered alongside op params.
+    pub fn schema() -> Value {
+        json!({
+            "description": "What to apply the op to; defaults to the active layer",
+            "oneOf": [
+                { "type": "string", "enum": ["active_layer", "selection", "all_layers", "image"] },
+                {
+                    "type": "object",
+                    "properties": { "layer": { "type": "string", "description": "Layer name" } },
+                    "required": ["layer"]
+                },
+                {
+                    "type": "object",
+                    "properties": { "layer_index": { "type": "integer", "minimum": 0, "description": "0 = top layer" } },
+                    "required": ["layer_index"]
+                },
+                {
+                    "type": "object",
+                    "properties": {
+                        "region": {
+                            "type": "object",
+                            "properties": {
+                                "shape": { "type": "string", "enum": ["rect", "ellipse"] },
+                                "x": { "type": "integer" }, "y": { "type": "integer" },
+                                "width": { "type": "integer" }, "height": { "type": "integer" }
+                            },
+                            "required": ["x", "y", "width", "height"]
+                        }
+                    },
+                    "required": ["region"]
+                }
+            ]
+        })
     }
 }
 
@@ -47,9 +135,1120 @@ impl Default for Target {
 pub enum Op {
     DrawLine,
     CropSquare,
+    Crop,
     ResizeWidth,
+    Resize,
+    Pad,
+    Border,
     BrightnessContrast,
     Blur,
+    Rotate,
+    Flip,
+    Desaturate,
+    HueSaturation,
+    Invert,
+    Threshold,
+    Posterize,
+    Levels,
+    Curves,
+    ColorBalance,
+    ColorTemperature,
+    StretchContrast,
+    GeglFilter,
+    DrawShape,
+    AddText,
+    NewLayer,
+    DuplicateLayer,
+    DeleteLayer,
+    RenameLayer,
+    MoveLayer,
+    SetLayerVisibility,
+    MergeDown,
+    SetLayerOpacity,
+    SetLayerBlendMode,
+    SetActiveLayer,
+    SelectAll,
+    SelectNone,
+    SelectInvert,
+    SelectShape,
+    ModifySelection,
+    SelectColor,
+    Fill,
+    Gradient,
     Undo,
     Redo,
+    Export,
+    SaveXcf,
+    NewImage,
+    OpenImage,
+    CloseImage,
+}
+
+/// A length in a geometry param: pixels (`120`, `"120px"`), a percentage of the
+/// image dimension (`"50%"`), a physical size at the image DPI (`"2cm"`, `"1in"`,
+/// `"12pt"`), or an edge keyword (`"left"`, `"center"`, `"bottom"`…).
+/// Resolved to pixels at execution time, against the live image.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Length {
+    Px(f64),
+    Percent(f64),
+    Inches(f64),
+}
+
+impl Length {
+    pub fn parse(text: &str) -> Result<Length, String> {
+        let s = text.trim().to_ascii_lowercase();
+        let edge = match s.as_str() {
+            "left" | "top" => Some(0.0),
+            "center" | "centre" | "middle" => Some(50.0),
+            "right" | "bottom" => Some(100.0),
+            _ => None,
+        };
+        if let Some(pct) = edge {
+            return Ok(Length::Percent(pct));
+        }
+
+        let split = s
+            .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-' || c == '+'))
+            .unwrap_or(s.len());
+        let (num, unit) = s.split_at(split);
+        let value: f64 = num
+            .parse()
+            .ok()
+            .filter(|v: &f64| v.is_finite())
+            .ok_or_else(|| format!("invalid length {text:?}"))?;
+        match unit.trim() {
+            "" | "px" => Ok(Length::Px(value)),
+            "%" => Ok(Length::Percent(value)),
+            "in" | "\"" => Ok(Length::Inches(value)),
+            "cm" => Ok(Length::Inches(value / 2.54)),
+            "mm" => Ok(Length::Inches(value / 25.4)),
+            "pt" => Ok(Length::Inches(value / 72.0)),
+            "pc" => Ok(Length::Inches(value / 6.0)),
+            other => Err(format!("unknown unit {other:?} in {text:?} (use px, %, in, cm, mm or pt)")),
+        }
+    }
+
+    /// Pixels, given the image extent along this axis and the image DPI.
+    pub fn to_px(self, extent: i32, dpi: f64) -> i32 {
+        let px = match self {
+            Length::Px(v) => v,
+            Length::Percent(p) => extent as f64 * p / 100.0,
+            Length::Inches(v) => v * dpi,
+        };
+        px.round() as i32
+    }
+}
+
+impl<'de> Deserialize<'de> for Length {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match Value::deserialize(deserializer)? {
+            Value::Number(n) => n
+                .as_f64()
+                .map(Length::Px)
+                .ok_or_else(|| serde::de::Error::custom("invalid length")),
+            Value::String(s) => Length::parse(&s).map_err(serde::de::Error::custom),
+            other => Err(serde::de::Error::custom(format!(
+                "expected a length like 120, \"50%\" or \"2cm\", got {other}"
+            ))),
+        }
+    }
+}
+
+/// A point: `{ "x": …, "y": … }`, `[x, y]` (each a `Length`) or an anchor
+/// keyword such as `"center"`, `"top-left"` or `"bottom-right"`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Point {
+    pub x: Length,
+    pub y: Length,
+}
+
+impl Point {
+    pub fn anchor(name: &str) -> Option<Point> {
+        let key = name.trim().to_ascii_lowercase().replace(['_', ' '], "-");
+        let (x, y) = match key.as_str() {
+            "center" | "centre" | "middle" => (50.0, 50.0),
+            "top-left" | "upper-left" => (0.0, 0.0),
+            "top" | "top-center" => (50.0, 0.0),
+            "top-right" | "upper-right" => (100.0, 0.0),
+            "left" | "center-left" => (0.0, 50.0),
+            "right" | "center-right" => (100.0, 50.0),
+            "bottom-left" | "lower-left" => (0.0, 100.0),
+            "bottom" | "bottom-center" => (50.0, 100.0),
+            "bottom-right" | "lower-right" => (100.0, 100.0),
+            _ => return None,
+        };
+        Some(Point { x: Length::Percent(x), y: Length::Percent(y) })
+    }
+}
+
+impl<'de> Deserialize<'de> for Point {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+        let length = |v: &Value| Length::deserialize(v.clone()).map_err(D::Error::custom);
+        match Value::deserialize(deserializer)? {
+            Value::String(s) => Point::anchor(&s).ok_or_else(|| {
+                D::Error::custom(format!("unknown anchor {s:?} (use center, top-left, bottom-right…)"))
+            }),
+            Value::Array(xy) if xy.len() == 2 => Ok(Point { x: length(&xy[0])?, y: length(&xy[1])? }),
+            Value::Object(map) => match (map.get("x"), map.get("y")) {
+                (Some(x), Some(y)) => Ok(Point { x: length(x)?, y: length(y)? }),
+                _ => Err(D::Error::custom("a point needs both x and y")),
+            },
+            other => Err(D::Error::custom(format!("expected a point or anchor, got {other}"))),
+        }
+    }
+}
+
+/// A rectangle in `Length`s; `x`/`y` is the top-left corner.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct LengthRect {
+    pub x: Length,
+    pub y: Length,
+    pub width: Length,
+    pub height: Length,
+}
+
+/// Which part of the image an aspect-ratio crop keeps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Gravity {
+    #[default]
+    Center,
+    Top,
+    Bottom,
+    Left,
+    Right,
+    /// Keep the image's upper third where faces usually are.
+    FaceSafe,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShapeKind {
+    Rectangle,
+    Ellipse,
+    Circle,
+    Triangle,
+    Heart,
+}
+
+/// Resampling method for transforms (GIMP's `Gimp.InterpolationType`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Interpolation {
+    None,
+    Linear,
+    #[default]
+    Cubic,
+    Nohalo,
+    Lohalo,
+}
+
+impl Interpolation {
+    /// Python expression for the GIMP 3 enum value.
+    pub fn gimp_enum(&self) -> &'static str {
+        match self {
+            Interpolation::None => "Gimp.InterpolationType.NONE",
+            Interpolation::Linear => "Gimp.InterpolationType.LINEAR",
+            Interpolation::Cubic => "Gimp.InterpolationType.CUBIC",
+            Interpolation::Nohalo => "Gimp.InterpolationType.NOHALO",
+            Interpolation::Lohalo => "Gimp.InterpolationType.LOHALO",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FlipOrientation {
+    Horizontal,
+    Vertical,
+}
+
+impl FlipOrientation {
+    pub fn gimp_enum(&self) -> &'static str {
+        match self {
+            FlipOrientation::Horizontal => "Gimp.OrientationType.HORIZONTAL",
+            FlipOrientation::Vertical => "Gimp.OrientationType.VERTICAL",
+        }
+    }
+}
+
+/// How `desaturate` computes grey (GIMP's `Gimp.DesaturateMode`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DesaturateMode {
+    #[default]
+    Luminance,
+    Luma,
+    Lightness,
+    Average,
+    Value,
+}
+
+impl DesaturateMode {
+    pub fn gimp_enum(&self) -> &'static str {
+        match self {
+            DesaturateMode::Luminance => "Gimp.DesaturateMode.LUMINANCE",
+            DesaturateMode::Luma => "Gimp.DesaturateMode.LUMA",
+            DesaturateMode::Lightness => "Gimp.DesaturateMode.LIGHTNESS",
+            DesaturateMode::Average => "Gimp.DesaturateMode.AVERAGE",
+            DesaturateMode::Value => "Gimp.DesaturateMode.VALUE",
+        }
+    }
+}
+
+/// Which hues `hue_saturation` affects (GIMP's `Gimp.HueRange`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HueRange {
+    #[default]
+    All,
+    Red,
+    Yellow,
+    Green,
+    Cyan,
+    Blue,
+    Magenta,
+}
+
+impl HueRange {
+    pub fn gimp_enum(&self) -> &'static str {
+        match self {
+            HueRange::All => "Gimp.HueRange.ALL",
+            HueRange::Red => "Gimp.HueRange.RED",
+            HueRange::Yellow => "Gimp.HueRange.YELLOW",
+            HueRange::Green => "Gimp.HueRange.GREEN",
+            HueRange::Cyan => "Gimp.HueRange.CYAN",
+            HueRange::Blue => "Gimp.HueRange.BLUE",
+            HueRange::Magenta => "Gimp.HueRange.MAGENTA",
+        }
+    }
+}
+
+/// How lines of a text layer line up (GIMP's `Gimp.TextJustification`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TextAlign {
+    Left,
+    #[default]
+    Center,
+    Right,
+    Fill,
+}
+
+impl TextAlign {
+    pub fn gimp_enum(&self) -> &'static str {
+        match self {
+            TextAlign::Left => "Gimp.TextJustification.LEFT",
+            TextAlign::Center => "Gimp.TextJustification.CENTER",
+            TextAlign::Right => "Gimp.TextJustification.RIGHT",
+            TextAlign::Fill => "Gimp.TextJustification.FILL",
+        }
+    }
+}
+
+/// Layer blend mode (GIMP's `Gimp.LayerMode`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlendMode {
+    #[default]
+    Normal,
+    Multiply,
+    Screen,
+    Overlay,
+    SoftLight,
+    HardLight,
+    Darken,
+    Lighten,
+    Difference,
+    Addition,
+    Subtract,
+    Dodge,
+    Burn,
+    Hue,
+    Saturation,
+    Color,
+    Luminosity,
+}
+
+impl BlendMode {
+    pub const ALL: &'static [BlendMode] = &[
+        BlendMode::Normal,
+        BlendMode::Multiply,
+        BlendMode::Screen,
+        BlendMode::Overlay,
+        BlendMode::SoftLight,
+        BlendMode::HardLight,
+        BlendMode::Darken,
+        BlendMode::Lighten,
+        BlendMode::Difference,
+        BlendMode::Addition,
+        BlendMode::Subtract,
+        BlendMode::Dodge,
+        BlendMode::Burn,
+        BlendMode::Hue,
+        BlendMode::Saturation,
+        BlendMode::Color,
+        BlendMode::Luminosity,
+    ];
+
+    pub fn gimp_enum(&self) -> &'static str {
+        match self {
+            BlendMode::Normal => "Gimp.LayerMode.NORMAL",
+            BlendMode::Multiply => "Gimp.LayerMode.MULTIPLY",
+            BlendMode::Screen => "Gimp.LayerMode.SCREEN",
+            BlendMode::Overlay => "Gimp.LayerMode.OVERLAY",
+            BlendMode::SoftLight => "Gimp.LayerMode.SOFTLIGHT",
+            BlendMode::HardLight => "Gimp.LayerMode.HARDLIGHT",
+            BlendMode::Darken => "Gimp.LayerMode.DARKEN_ONLY",
+            BlendMode::Lighten => "Gimp.LayerMode.LIGHTEN_ONLY",
+            BlendMode::Difference => "Gimp.LayerMode.DIFFERENCE",
+            BlendMode::Addition => "Gimp.LayerMode.ADDITION",
+            BlendMode::Subtract => "Gimp.LayerMode.SUBTRACT",
+            BlendMode::Dodge => "Gimp.LayerMode.DODGE",
+            BlendMode::Burn => "Gimp.LayerMode.BURN",
+            BlendMode::Hue => "Gimp.LayerMode.HSV_HUE",
+            BlendMode::Saturation => "Gimp.LayerMode.HSV_SATURATION",
+            BlendMode::Color => "Gimp.LayerMode.HSL_COLOR",
+            BlendMode::Luminosity => "Gimp.LayerMode.LUMINANCE",
+        }
+    }
+}
+
+/// What a new layer starts out filled with (GIMP's `Gimp.FillType`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LayerFill {
+    #[default]
+    Transparent,
+    White,
+    Foreground,
+    Background,
+}
+
+impl LayerFill {
+    pub fn gimp_enum(&self) -> &'static str {
+        match self {
+            LayerFill::Transparent => "Gimp.FillType.TRANSPARENT",
+            LayerFill::White => "Gimp.FillType.WHITE",
+            LayerFill::Foreground => "Gimp.FillType.FOREGROUND",
+            LayerFill::Background => "Gimp.FillType.BACKGROUND",
+        }
+    }
+}
+
+/// Where `move_layer` puts a layer: one step up/down, the top/bottom of the
+/// stack, or `{ "index": n }` counting from the top.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LayerPosition {
+    Up,
+    Down,
+    Top,
+    Bottom,
+    Index(usize),
+}
+
+/// How a new selection combines with the existing one (GIMP's `Gimp.ChannelOps`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SelectionMode {
+    #[default]
+    Replace,
+    Add,
+    Subtract,
+    Intersect,
+}
+
+impl SelectionMode {
+    pub fn gimp_enum(&self) -> &'static str {
+        match self {
+            SelectionMode::Replace => "Gimp.ChannelOps.REPLACE",
+            SelectionMode::Add => "Gimp.ChannelOps.ADD",
+            SelectionMode::Subtract => "Gimp.ChannelOps.SUBTRACT",
+            SelectionMode::Intersect => "Gimp.ChannelOps.INTERSECT",
+        }
+    }
+}
+
+/// What `fill` fills with (GIMP's `Gimp.FillType`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FillKind {
+    #[default]
+    Foreground,
+    Background,
+    White,
+    Transparent,
+    Pattern,
+}
+
+impl FillKind {
+    pub fn gimp_enum(&self) -> &'static str {
+        match self {
+            FillKind::Foreground => "Gimp.FillType.FOREGROUND",
+            FillKind::Background => "Gimp.FillType.BACKGROUND",
+            FillKind::White => "Gimp.FillType.WHITE",
+            FillKind::Transparent => "Gimp.FillType.TRANSPARENT",
+            FillKind::Pattern => "Gimp.FillType.PATTERN",
+        }
+    }
+}
+
+/// Gradient shape (GIMP's `Gimp.GradientType`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GradientKind {
+    #[default]
+    Linear,
+    Radial,
+    Conical,
+}
+
+impl GradientKind {
+    pub fn gimp_enum(&self) -> &'static str {
+        match self {
+            GradientKind::Linear => "Gimp.GradientType.LINEAR",
+            GradientKind::Radial => "Gimp.GradientType.RADIAL",
+            GradientKind::Conical => "Gimp.GradientType.CONICAL_ASYMMETRIC",
+        }
+    }
+}
+
+/// Channel a tonal correction works on (GIMP's `Gimp.HistogramChannel`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Channel {
+    #[default]
+    Value,
+    Red,
+    Green,
+    Blue,
+    Alpha,
+}
+
+impl Channel {
+    pub fn gimp_enum(&self) -> &'static str {
+        match self {
+            Channel::Value => "Gimp.HistogramChannel.VALUE",
+            Channel::Red => "Gimp.HistogramChannel.RED",
+            Channel::Green => "Gimp.HistogramChannel.GREEN",
+            Channel::Blue => "Gimp.HistogramChannel.BLUE",
+            Channel::Alpha => "Gimp.HistogramChannel.ALPHA",
+        }
+    }
+}
+
+/// Tonal range `color_balance` shifts (GIMP's `Gimp.TransferMode`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToneRange {
+    Shadows,
+    #[default]
+    Midtones,
+    Highlights,
+}
+
+impl ToneRange {
+    pub fn gimp_enum(&self) -> &'static str {
+        match self {
+            ToneRange::Shadows => "Gimp.TransferMode.SHADOWS",
+            ToneRange::Midtones => "Gimp.TransferMode.MIDTONES",
+            ToneRange::Highlights => "Gimp.TransferMode.HIGHLIGHTS",
+        }
+    }
+}
+
+/// File format `export` writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    #[default]
+    Png,
+    #[serde(alias = "jpg")]
+    Jpeg,
+    Webp,
+    #[serde(alias = "tif")]
+    Tiff,
+    Gif,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Png => "png",
+            ExportFormat::Jpeg => "jpg",
+            ExportFormat::Webp => "webp",
+            ExportFormat::Tiff => "tif",
+            ExportFormat::Gif => "gif",
+        }
+    }
+
+    /// The format a file extension stands for.
+    pub fn from_extension(ext: &str) -> Option<ExportFormat> {
+        match ext.to_ascii_lowercase().as_str() {
+            "png" => Some(ExportFormat::Png),
+            "jpg" | "jpeg" => Some(ExportFormat::Jpeg),
+            "webp" => Some(ExportFormat::Webp),
+            "tif" | "tiff" => Some(ExportFormat::Tiff),
+            "gif" => Some(ExportFormat::Gif),
+            _ => None,
+        }
+    }
+
+    /// GIMP 3 export procedure.
+    pub fn procedure(&self) -> &'static str {
+        match self {
+            ExportFormat::Png => "file-png-export",
+            ExportFormat::Jpeg => "file-jpeg-export",
+            ExportFormat::Webp => "file-webp-export",
+            ExportFormat::Tiff => "file-tiff-export",
+            ExportFormat::Gif => "file-gif-export",
+        }
+    }
+}
+
+/// JSON Schema for a `Channel` param.
+fn channel_schema() -> Value {
+    json!({ "type": "string", "enum": ["value", "red", "green", "blue", "alpha"] })
+}
+
+/// JSON Schema for a `SelectionMode` param.
+fn selection_mode_schema() -> Value {
+    json!({ "type": "string", "enum": ["replace", "add", "subtract", "intersect"] })
+}
+
+/// JSON Schema for a `BlendMode` param.
+fn blend_mode_schema() -> Value {
+    let names: Vec<Value> = BlendMode::ALL
+        .iter()
+        .map(|m| serde_json::to_value(m).unwrap_or_default())
+        .collect();
+    json!({ "type": "string", "enum": names })
+}
+
+/// JSON Schema for a `Length` param.
+fn length_schema() -> Value {
+    json!({
+        "type": ["number", "string"],
+        "description": "Pixels (120), percent of the image (\"50%\"), units at the image DPI (\"2cm\", \"1in\", \"12pt\") or left/center/right/top/bottom"
+    })
+}
+
+/// JSON Schema for a `Point` param.
+fn point_schema() -> Value {
+    json!({
+        "description": "{ x, y } lengths, or an anchor: center, top, bottom, left, right, top-left, top-right, bottom-left, bottom-right",
+        "oneOf": [
+            { "type": "object", "properties": { "x": length_schema(), "y": length_schema() }, "required": ["x", "y"] },
+            { "type": "string" }
+        ]
+    })
+}
+
+impl Op {
+    /// Every op, in the order they are offered to the LLM.
+    pub const ALL: &'static [Op] = &[
+        Op::DrawLine,
+        Op::CropSquare,
+        Op::Crop,
+        Op::ResizeWidth,
+        Op::Resize,
+        Op::Pad,
+        Op::Border,
+        Op::BrightnessContrast,
+        Op::Blur,
+        Op::Rotate,
+        Op::Flip,
+        Op::Desaturate,
+        Op::HueSaturation,
+        Op::Invert,
+        Op::Threshold,
+        Op::Posterize,
+        Op::Levels,
+        Op::Curves,
+        Op::ColorBalance,
+        Op::ColorTemperature,
+        Op::StretchContrast,
+        Op::GeglFilter,
+        Op::DrawShape,
+        Op::AddText,
+        Op::NewLayer,
+        Op::DuplicateLayer,
+        Op::DeleteLayer,
+        Op::RenameLayer,
+        Op::MoveLayer,
+        Op::SetLayerVisibility,
+        Op::MergeDown,
+        Op::SetLayerOpacity,
+        Op::SetLayerBlendMode,
+        Op::SetActiveLayer,
+        Op::SelectAll,
+        Op::SelectNone,
+        Op::SelectInvert,
+        Op::SelectShape,
+        Op::ModifySelection,
+        Op::SelectColor,
+        Op::Fill,
+        Op::Gradient,
+        Op::Undo,
+        Op::Redo,
+        Op::Export,
+        Op::SaveXcf,
+        Op::NewImage,
+        Op::OpenImage,
+        Op::CloseImage,
+    ];
+
+    /// The snake_case name used in plans and tool calls.
+    pub fn name(&self) -> String {
+        serde_json::to_value(self)
+            .ok()
+            .and_then(|v| v.as_str().map(|s| s.to_string()))
+            .unwrap_or_default()
+    }
+
+    pub fn from_name(name: &str) -> Option<Op> {
+        serde_json::from_value(Value::String(name.to_string())).ok()
+    }
+
+    /// Whether the op works on an open image; only the ops that open one don't.
+    pub fn needs_image(&self) -> bool {
+        !matches!(self, Op::NewImage | Op::OpenImage)
+    }
+
+    /// One-line description for tool definitions.
+    pub fn description(&self) -> &'static str {
+        match self {
+            Op::DrawLine => "Draw a line between two points, black unless a color is given.",
+            Op::CropSquare => "Crop the image to a centred square.",
+            Op::Crop => "Crop to a rectangle, an aspect ratio (16:9, 4:3, 9:16…) with gravity, the current selection, or auto-crop uniform borders.",
+            Op::ResizeWidth => "Scale the image to a new width, keeping the aspect ratio.",
+            Op::Resize => "Scale the image by width, height, percent, exact size, to fit within a box, or to fill a box and crop the overflow.",
+            Op::Pad => "Grow the canvas by per-side margins, or to an aspect ratio, filling the new area with a color.",
+            Op::Border => "Add an even border of a color around the image, growing the canvas.",
+            Op::BrightnessContrast => "Adjust brightness and contrast (-100..100 each).",
+            Op::Blur => "Apply a gaussian blur with the given radius.",
+            Op::Rotate => "Rotate the whole image clockwise by 90, 180, 270 or any angle in degrees.",
+            Op::Flip => "Mirror the whole image horizontally or vertically.",
+            Op::Desaturate => "Make the image black and white (greyscale colors, still an RGB image).",
+            Op::HueSaturation => "Shift hue (-180..180) and change saturation/lightness (-100..100), optionally for one color range.",
+            Op::Invert => "Invert the colors (negative).",
+            Op::Threshold => "Make pixels pure black or white: values between low and high (0..255) become white.",
+            Op::Posterize => "Reduce the number of color levels per channel (2..255).",
+            Op::Levels => "Levels: set the input black/white points, gamma and output range of a channel.",
+            Op::Curves => "Curves: remap a channel through control points.",
+            Op::ColorBalance => "Shift the colors of the shadows, midtones or highlights toward red/cyan, green/magenta or blue/yellow.",
+            Op::ColorTemperature => "Warm up or cool down the image by changing its color temperature.",
+            Op::StretchContrast => "Automatically stretch the contrast so the darkest pixel is black and the brightest white.",
+            Op::GeglFilter => "Run an allowed GEGL filter (sharpen, denoise, pixelize, vignette, emboss, drop shadow, red-eye removal…).",
+            Op::DrawShape => "Draw a filled or outlined rectangle, ellipse, circle, triangle or heart at a position and size.",
+            Op::AddText => "Add text (a caption, title, name…) as a new editable text layer.",
+            Op::NewLayer => "Create a new layer above the target layer.",
+            Op::DuplicateLayer => "Duplicate the target layer.",
+            Op::DeleteLayer => "Delete the target layer.",
+            Op::RenameLayer => "Rename the target layer.",
+            Op::MoveLayer => "Move the target layer up, down, to the top or bottom, or to a position in the stack.",
+            Op::SetLayerVisibility => "Show or hide the target layer.",
+            Op::MergeDown => "Merge the target layer into the layer below it.",
+            Op::SetLayerOpacity => "Set the target layer's opacity (0-100).",
+            Op::SetLayerBlendMode => "Set the target layer's blend mode (multiply, screen, overlay…).",
+            Op::SetActiveLayer => "Make the target layer the active layer.",
+            Op::SelectAll => "Select the whole image.",
+            Op::SelectNone => "Clear the selection.",
+            Op::SelectInvert => "Invert the selection.",
+            Op::SelectShape => "Select a rectangle, ellipse or polygon, replacing, adding to, subtracting from or intersecting the selection.",
+            Op::ModifySelection => "Grow, shrink, feather or border the current selection.",
+            Op::SelectColor => "Select pixels close to a color on the target layer.",
+            Op::Fill => "Fill the selection (or the whole layer) with a color, white, transparency or a pattern.",
+            Op::Gradient => "Fill the selection (or the whole layer) with a linear, radial or conical gradient between two or more colors.",
+            Op::Undo => "Undo the last change(s).",
+            Op::Redo => "Redo previously undone change(s).",
+            Op::Export => "Export the image to a PNG, JPEG, WebP, TIFF or GIF file in the output folder.",
+            Op::SaveXcf => "Save the image with its layers as a GIMP .xcf file in the output folder.",
+            Op::NewImage => "Create a new blank image and make it the one later steps edit.",
+            Op::OpenImage => "Open an image file from the output folder or an allowed input folder.",
+            Op::CloseImage => "Close the image; refuses if it has unsaved changes unless discard_changes is true.",
+        }
+    }
+
+    /// JSON Schema of the op's params, matching what `validate_step` accepts.
+    pub fn params_schema(&self) -> Value {
+        match self {
+            Op::DrawLine => json!({
+                "type": "object",
+                "description": "Give x1/y1/x2/y2, or from/to points",
+                "properties": {
+                    "x1": length_schema(), "y1": length_schema(),
+                    "x2": length_schema(), "y2": length_schema(),
+                    "from": point_schema(), "to": point_schema(),
+                    "color": { "type": "string", "description": "CSS color: name, #hex, rgb() or hsl()" }
+                }
+            }),
+            Op::CropSquare => json!({ "type": "object", "properties": {} }),
+            Op::Crop => json!({
+                "type": "object",
+                "properties": {
+                    "mode": { "type": "string", "enum": ["rect", "aspect", "auto", "selection"] },
+                    "rect": {
+                        "type": "object",
+                        "description": "mode=rect: the area to keep",
+                        "properties": {
+                            "x": length_schema(), "y": length_schema(),
+                            "width": length_schema(), "height": length_schema()
+                        },
+                        "required": ["x", "y", "width", "height"]
+                    },
+                    "aspect": { "type": "string", "description": "mode=aspect: \"16:9\", \"4:3\", \"1:1\", \"9:16\"…" },
+                    "gravity": {
+                        "type": "string",
+                        "enum": ["center", "top", "bottom", "left", "right", "face_safe"],
+                        "description": "mode=aspect: which part to keep; face_safe keeps the top third (portraits)"
+                    }
+                },
+                "required": ["mode"]
+            }),
+            Op::ResizeWidth => json!({
+                "type": "object",
+                "properties": { "width": { "type": "integer", "minimum": 16, "maximum": 8192 } },
+                "required": ["width"]
+            }),
+            Op::Resize => json!({
+                "type": "object",
+                "properties": {
+                    "mode": { "type": "string", "enum": ["width", "height", "percent", "exact", "fit", "fill"] },
+                    "width": length_schema(),
+                    "height": length_schema(),
+                    "percent": { "type": "number", "minimum": 1, "maximum": 1000 },
+                    "interpolation": { "type": "string", "enum": ["none", "linear", "cubic", "nohalo", "lohalo"] },
+                    "no_upscale": { "type": "boolean", "description": "Never make the image bigger" }
+                },
+                "required": ["mode"]
+            }),
+            Op::Pad => json!({
+                "type": "object",
+                "properties": {
+                    "top": length_schema(),
+                    "right": length_schema(),
+                    "bottom": length_schema(),
+                    "left": length_schema(),
+                    "all": { "description": "Margin on every side (a % is of the shorter side)", "oneOf": [length_schema()] },
+                    "aspect": { "type": "string", "description": "Pad evenly out to this aspect ratio, e.g. \"1:1\" or \"9:16\"" },
+                    "color": { "type": "string", "description": "CSS color for the new area, or \"transparent\" (default white)" }
+                }
+            }),
+            Op::Border => json!({
+                "type": "object",
+                "properties": {
+                    "width": { "description": "Border width (a % is of the shorter side)", "oneOf": [length_schema()] },
+                    "color": { "type": "string", "description": "CSS color (default white)" }
+                },
+                "required": ["width"]
+            }),
+            Op::BrightnessContrast => json!({
+                "type": "object",
+                "properties": {
+                    "brightness": { "type": "number", "minimum": -100, "maximum": 100 },
+                    "contrast": { "type": "number", "minimum": -100, "maximum": 100 }
+                },
+                "required": ["brightness", "contrast"]
+            }),
+            Op::Blur => json!({
+                "type": "object",
+                "properties": { "radius": { "type": "number", "minimum": 0, "maximum": 200 } },
+                "required": ["radius"]
+            }),
+            Op::Rotate => json!({
+                "type": "object",
+                "properties": {
+                    "degrees": { "type": "number", "description": "Clockwise; negative for counter-clockwise" },
+                    "interpolation": {
+                        "type": "string",
+                        "enum": ["none", "linear", "cubic", "nohalo", "lohalo"],
+                        "description": "Only used for angles that aren't multiples of 90"
+                    }
+                },
+                "required": ["degrees"]
+            }),
+            Op::Flip => json!({
+                "type": "object",
+                "properties": { "orientation": { "type": "string", "enum": ["horizontal", "vertical"] } },
+                "required": ["orientation"]
+            }),
+            Op::Desaturate => json!({
+                "type": "object",
+                "properties": {
+                    "mode": { "type": "string", "enum": ["luminance", "luma", "lightness", "average", "value"] }
+                }
+            }),
+            Op::HueSaturation => json!({
+                "type": "object",
+                "properties": {
+                    "range": { "type": "string", "enum": ["all", "red", "yellow", "green", "cyan", "blue", "magenta"] },
+                    "hue": { "type": "number", "minimum": -180, "maximum": 180 },
+                    "lightness": { "type": "number", "minimum": -100, "maximum": 100 },
+                    "saturation": { "type": "number", "minimum": -100, "maximum": 100 }
+                }
+            }),
+            Op::Invert => json!({ "type": "object", "properties": {} }),
+            Op::Threshold => json!({
+                "type": "object",
+                "properties": {
+                    "low": { "type": "integer", "minimum": 0, "maximum": 255 },
+                    "high": { "type": "integer", "minimum": 0, "maximum": 255 }
+                }
+            }),
+            Op::Posterize => json!({
+                "type": "object",
+                "properties": { "levels": { "type": "integer", "minimum": 2, "maximum": 255 } },
+                "required": ["levels"]
+            }),
+            Op::Levels => json!({
+                "type": "object",
+                "properties": {
+                    "channel": channel_schema(),
+                    "input_black": { "type": "number", "minimum": 0, "maximum": 255 },
+                    "input_white": { "type": "number", "minimum": 0, "maximum": 255 },
+                    "gamma": { "type": "number", "minimum": 0.1, "maximum": 10 },
+                    "output_black": { "type": "number", "minimum": 0, "maximum": 255 },
+                    "output_white": { "type": "number", "minimum": 0, "maximum": 255 }
+                }
+            }),
+            Op::Curves => json!({
+                "type": "object",
+                "properties": {
+                    "channel": channel_schema(),
+                    "points": {
+                        "type": "array",
+                        "description": "[input, output] pairs in 0..255, inputs increasing",
+                        "items": { "type": "array", "items": { "type": "number" }, "minItems": 2, "maxItems": 2 },
+                        "minItems": 2
+                    }
+                },
+                "required": ["points"]
+            }),
+            Op::ColorBalance => json!({
+                "type": "object",
+                "properties": {
+                    "range": { "type": "string", "enum": ["shadows", "midtones", "highlights"] },
+                    "cyan_red": { "type": "number", "minimum": -100, "maximum": 100 },
+                    "magenta_green": { "type": "number", "minimum": -100, "maximum": 100 },
+                    "yellow_blue": { "type": "number", "minimum": -100, "maximum": 100 },
+                    "preserve_luminosity": { "type": "boolean" }
+                }
+            }),
+            Op::ColorTemperature => json!({
+                "type": "object",
+                "properties": {
+                    "original": { "type": "number", "minimum": 1000, "maximum": 12000, "description": "Kelvin the image was shot at (default 6500)" },
+                    "intended": { "type": "number", "minimum": 1000, "maximum": 12000, "description": "Kelvin to correct to; above original warms, below cools" }
+                },
+                "required": ["intended"]
+            }),
+            Op::StretchContrast => json!({ "type": "object", "properties": {} }),
+            Op::GeglFilter => {
+                let operations: Vec<&str> = crate::gegl_filters::FILTERS.iter().map(|f| f.operation).collect();
+                json!({
+                    "type": "object",
+                    "properties": {
+                        "operation": { "type": "string", "enum": operations },
+                        "properties": { "type": "object", "description": "Filter properties; see list_gegl_filters for names and ranges" }
+                    },
+                    "required": ["operation"]
+                })
+            }
+            Op::DrawShape => json!({
+                "type": "object",
+                "properties": {
+                    "kind": { "type": "string", "enum": ["rectangle", "ellipse", "circle", "triangle", "heart"] },
+                    "center": point_schema(),
+                    "size": {
+                        "description": "Diameter/side (a % is of the shorter image side), or { width, height }",
+                        "oneOf": [
+                            length_schema(),
+                            {
+                                "type": "object",
+                                "properties": { "width": length_schema(), "height": length_schema() }
+                            }
+                        ]
+                    },
+                    "bbox": {
+                        "type": "object",
+                        "description": "Bounding box; use instead of center/size",
+                        "properties": {
+                            "x": length_schema(), "y": length_schema(),
+                            "width": length_schema(), "height": length_schema()
+                        }
+                    },
+                    "color": { "type": "string", "description": "CSS color: name, #hex, rgb() or hsl()" },
+                    "fill": { "type": "boolean", "description": "false draws only the outline" },
+                    "stroke_width": { "type": "number", "minimum": 1, "maximum": 200 }
+                },
+                "required": ["kind"]
+            }),
+            Op::AddText => json!({
+                "type": "object",
+                "properties": {
+                    "text": { "type": "string", "minLength": 1 },
+                    "font": { "type": "string", "description": "Installed font family, e.g. \"Sans-serif\" or \"DejaVu Serif Bold\"" },
+                    "size": length_schema(),
+                    "color": { "type": "string", "description": "CSS color: name, #hex, rgb() or hsl()" },
+                    "align": { "type": "string", "enum": ["left", "center", "right", "fill"] },
+                    "anchor": point_schema()
+                },
+                "required": ["text"]
+            }),
+            Op::NewLayer => json!({
+                "type": "object",
+                "properties": {
+                    "name": { "type": "string" },
+                    "fill": { "type": "string", "enum": ["transparent", "white", "foreground", "background"] },
+                    "opacity": { "type": "number", "minimum": 0, "maximum": 100 },
+                    "blend_mode": blend_mode_schema()
+                }
+            }),
+            Op::DuplicateLayer => json!({
+                "type": "object",
+                "properties": { "name": { "type": "string", "description": "Name for the copy" } }
+            }),
+            Op::DeleteLayer
+            | Op::MergeDown
+            | Op::SetActiveLayer
+            | Op::SelectAll
+            | Op::SelectNone
+            | Op::SelectInvert => json!({ "type": "object", "properties": {} }),
+            Op::SelectShape => json!({
+                "type": "object",
+                "properties": {
+                    "shape": { "type": "string", "enum": ["rect", "ellipse", "polygon"] },
+                    "rect": {
+                        "type": "object",
+                        "description": "Bounds for rect and ellipse",
+                        "properties": {
+                            "x": length_schema(), "y": length_schema(),
+                            "width": length_schema(), "height": length_schema()
+                        },
+                        "required": ["x", "y", "width", "height"]
+                    },
+                    "points": { "type": "array", "items": point_schema(), "minItems": 3, "description": "Corners for polygon" },
+                    "mode": selection_mode_schema()
+                },
+                "required": ["shape"]
+            }),
+            Op::ModifySelection => json!({
+                "type": "object",
+                "properties": {
+                    "action": { "type": "string", "enum": ["grow", "shrink", "feather", "border"] },
+                    "amount": length_schema()
+                },
+                "required": ["action", "amount"]
+            }),
+            Op::SelectColor => json!({
+                "type": "object",
+                "properties": {
+                    "color": { "type": "string", "description": "CSS color: name, #hex, rgb() or hsl()" },
+                    "threshold": { "type": "integer", "minimum": 0, "maximum": 255, "description": "How different a pixel may be (default 15)" },
+                    "mode": selection_mode_schema(),
+                    "sample_merged": { "type": "boolean", "description": "Compare against all visible layers" }
+                },
+                "required": ["color"]
+            }),
+            Op::RenameLayer => json!({
+                "type": "object",
+                "properties": { "name": { "type": "string", "minLength": 1 } },
+                "required": ["name"]
+            }),
+            Op::MoveLayer => json!({
+                "type": "object",
+                "properties": {
+                    "to": {
+                        "description": "\"up\", \"down\", \"top\", \"bottom\" or { \"index\": n } (0 is the top)",
+                        "oneOf": [
+                            { "type": "string", "enum": ["up", "down", "top", "bottom"] },
+                            { "type": "object", "properties": { "index": { "type": "integer", "minimum": 0 } }, "required": ["index"] }
+                        ]
+                    }
+                },
+                "required": ["to"]
+            }),
+            Op::SetLayerVisibility => json!({
+                "type": "object",
+                "properties": { "visible": { "type": "boolean" } },
+                "required": ["visible"]
+            }),
+            Op::SetLayerOpacity => json!({
+                "type": "object",
+                "properties": { "opacity": { "type": "number", "minimum": 0, "maximum": 100 } },
+                "required": ["opacity"]
+            }),
+            Op::SetLayerBlendMode => json!({
+                "type": "object",
+                "properties": { "mode": blend_mode_schema() },
+                "required": ["mode"]
+            }),
+            Op::Fill => json!({
+                "type": "object",
+                "properties": {
+                    "fill": { "type": "string", "enum": ["foreground", "background", "white", "transparent", "pattern"] },
+                    "color": { "type": "string", "description": "CSS color for a foreground or background fill" },
+                    "pattern": { "type": "string", "description": "GIMP pattern name for a pattern fill" }
+                }
+            }),
+            Op::Gradient => json!({
+                "type": "object",
+                "properties": {
+                    "kind": { "type": "string", "enum": ["linear", "radial", "conical"] },
+                    "colors": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "minItems": 2,
+                        "description": "CSS colors, first at the start point"
+                    },
+                    "from": point_schema(),
+                    "to": point_schema()
+                },
+                "required": ["colors"]
+            }),
+            Op::Undo | Op::Redo => json!({
+                "type": "object",
+                "properties": { "steps": { "type": "integer", "minimum": 1, "maximum": 50 } }
+            }),
+            Op::Export => json!({
+                "type": "object",
+                "properties": {
+                    "format": { "type": "string", "enum": ["png", "jpeg", "webp", "tiff", "gif"] },
+                    "file_name": { "type": "string", "description": "Plain file name; an extension picks the format" },
+                    "quality": { "type": "integer", "minimum": 1, "maximum": 100, "description": "JPEG and WebP quality (default 90)" },
+                    "compression": { "type": "integer", "minimum": 0, "maximum": 9, "description": "PNG compression level; TIFF uses LZW when above 0 (default 6)" },
+                    "strip_metadata": { "type": "boolean", "description": "Leave out EXIF, XMP, IPTC and comments" },
+                    "flatten": { "type": "boolean", "description": "Flatten a copy before exporting; the open image keeps its layers" }
+                }
+            }),
+            Op::SaveXcf => json!({
+                "type": "object",
+                "properties": { "file_name": { "type": "string", "description": "Plain file name" } }
+            }),
+            Op::NewImage => json!({
+                "type": "object",
+                "properties": {
+                    "width": { "type": "integer", "minimum": 1, "maximum": 32768, "description": "Pixels (default 1920)" },
+                    "height": { "type": "integer", "minimum": 1, "maximum": 32768, "description": "Pixels (default 1080)" },
+                    "background": { "type": "string", "description": "CSS color (default white)" },
+                    "transparent": { "type": "boolean", "description": "Start with a transparent background instead" }
+                }
+            }),
+            Op::OpenImage => json!({
+                "type": "object",
+                "properties": {
+                    "path": { "type": "string", "description": "File name in the output folder, or a path inside an allowed input folder" }
+                },
+                "required": ["path"]
+            }),
+            Op::CloseImage => json!({
+                "type": "object",
+                "properties": { "discard_changes": { "type": "boolean", "description": "Close even if the image has unsaved changes" } }
+            }),
+        }
+    }
 }
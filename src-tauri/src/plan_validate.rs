@@ -1,6 +1,64 @@
 use serde::{Deserialize, Serialize};
 
-use crate::plan_schema::{ActionStep, Op, Target};
+use serde_json::{Map, Value};
+use std::cell::Cell;
+use std::collections::BTreeMap;
+
+use crate::gegl_filters::FilterValue;
+use crate::plan_schema::{
+    ActionStep, BlendMode, Channel, Color, DesaturateMode, ExportFormat, FillKind, FlipOrientation, GradientKind, Gravity,
+    HueRange, Interpolation, LayerFill, LayerPosition, Length, LengthRect, Op, Point, SelectionMode, ShapeKind,
+    Target, TextAlign, ToneRange,
+};
+
+/// Ops that change the whole canvas; they can't be limited to a layer or region.
+fn is_whole_image_op(op: &Op) -> bool {
+    matches!(
+        op,
+        Op::CropSquare
+            | Op::Crop
+            | Op::ResizeWidth
+            | Op::Resize
+            | Op::Pad
+            | Op::Border
+            | Op::Rotate
+            | Op::Flip
+            | Op::Undo
+            | Op::Redo
+            | Op::Export
+            | Op::SaveXcf
+            | Op::NewImage
+            | Op::OpenImage
+            | Op::CloseImage
+    )
+}
+
+/// Ops that change the layer stack or a layer's properties; they act on one
+/// layer picked by the target.
+fn is_layer_op(op: &Op) -> bool {
+    matches!(
+        op,
+        Op::NewLayer
+            | Op::DuplicateLayer
+            | Op::DeleteLayer
+            | Op::RenameLayer
+            | Op::MoveLayer
+            | Op::SetLayerVisibility
+            | Op::MergeDown
+            | Op::SetLayerOpacity
+            | Op::SetLayerBlendMode
+            | Op::SetActiveLayer
+    )
+}
+
+/// Ops that change the image's selection. It persists, so later steps can use
+/// it through the `selection` target.
+fn is_selection_op(op: &Op) -> bool {
+    matches!(
+        op,
+        Op::SelectAll | Op::SelectNone | Op::SelectInvert | Op::SelectShape | Op::ModifySelection | Op::SelectColor
+    )
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ValidatedStep {
@@ -8,18 +66,265 @@ pub struct ValidatedStep {
     pub target: Target,
     pub stop_on_error: bool,
     pub params: ValidatedParams,
+    /// Things worth telling the user that don't stop the step.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+    /// Validated without the image, and some pixels in `params` came from
+    /// image-relative lengths or defaults, so they're placeholders.
+    #[serde(skip)]
+    pub placeholder_pixels: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum ValidatedParams {
-    DrawLine { x1: i32, y1: i32, x2: i32, y2: i32 },
+    DrawLine { x1: i32, y1: i32, x2: i32, y2: i32, color: Color },
     CropSquare {},
+    /// Keep `width`×`height` pixels starting at (`x`, `y`).
+    Crop { x: i32, y: i32, width: i32, height: i32 },
+    /// Trim borders of uniform color.
+    AutoCrop {},
+    CropToSelection {},
     ResizeWidth { width: i32 },
+    /// Scale to `scale_width`×`scale_height`, then centre-crop to `width`×`height`
+    /// if those are smaller (fill mode). `width`×`height` is the final size.
+    Resize {
+        scale_width: i32,
+        scale_height: i32,
+        width: i32,
+        height: i32,
+        interpolation: Interpolation,
+    },
+    /// Grow the canvas by these margins to `width`×`height` (0×0 when validated
+    /// without the image). Existing layers are only moved; a `color` layer is
+    /// added underneath unless the color is fully transparent.
+    Pad { left: i32, top: i32, right: i32, bottom: i32, color: Color, width: i32, height: i32 },
     BrightnessContrast { brightness: f64, contrast: f64 },
     Blur { radius: f64 },
+    /// `degrees` is clockwise in (0, 360).
+    Rotate { degrees: f64, interpolation: Interpolation },
+    Flip { orientation: FlipOrientation },
+    Desaturate { mode: DesaturateMode },
+    HueSaturation { range: HueRange, hue: f64, lightness: f64, saturation: f64 },
+    Invert {},
+    /// `low..=high` on a 0..255 scale.
+    Threshold { low: i32, high: i32 },
+    Posterize { levels: i32 },
+    /// All levels in 0–255.
+    Levels {
+        channel: Channel,
+        input_black: f64,
+        input_white: f64,
+        gamma: f64,
+        output_black: f64,
+        output_white: f64,
+    },
+    /// `(input, output)` pairs in 0–255, inputs strictly increasing.
+    Curves { channel: Channel, points: Vec<(f64, f64)> },
+    /// Shifts in -100..100.
+    ColorBalance {
+        range: ToneRange,
+        cyan_red: f64,
+        magenta_green: f64,
+        yellow_blue: f64,
+        preserve_luminosity: bool,
+    },
+    /// Kelvin.
+    ColorTemperature { original: f64, intended: f64 },
+    StretchContrast {},
+    /// `operation` is from the filter registry; unset properties keep GEGL's defaults.
+    GeglFilter { operation: String, properties: BTreeMap<String, FilterValue> },
+    /// Bounding box in image pixels; `stroke_width` only matters when `fill` is false.
+    DrawShape {
+        kind: ShapeKind,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+        color: Color,
+        fill: bool,
+        stroke_width: f64,
+    },
+    /// `x`/`y` is the anchor in image pixels; the text box's point at the same
+    /// relative position (`anchor_x`/`anchor_y`, 0–1) sits there, `margin` in
+    /// from the nearest edges.
+    AddText {
+        text: String,
+        font: String,
+        size: i32,
+        color: Color,
+        align: TextAlign,
+        x: i32,
+        y: i32,
+        anchor_x: f64,
+        anchor_y: f64,
+        margin: i32,
+    },
+    NewLayer { name: String, fill: LayerFill, opacity: f64, blend_mode: BlendMode },
+    DuplicateLayer { name: Option<String> },
+    DeleteLayer {},
+    RenameLayer { name: String },
+    MoveLayer { to: LayerPosition },
+    SetLayerVisibility { visible: bool },
+    MergeDown {},
+    SetLayerOpacity { opacity: f64 },
+    SetLayerBlendMode { mode: BlendMode },
+    SetActiveLayer {},
+    SelectAll {},
+    SelectNone {},
+    SelectInvert {},
+    SelectRect { x: i32, y: i32, width: i32, height: i32, mode: SelectionMode },
+    SelectEllipse { x: i32, y: i32, width: i32, height: i32, mode: SelectionMode },
+    /// Corners in image pixels.
+    SelectPolygon { points: Vec<(i32, i32)>, mode: SelectionMode },
+    GrowSelection { pixels: i32 },
+    ShrinkSelection { pixels: i32 },
+    FeatherSelection { radius: f64 },
+    BorderSelection { pixels: i32 },
+    /// `threshold` is 0–255, like GIMP's fuzzy-select threshold.
+    SelectColor { color: Color, threshold: i32, mode: SelectionMode, sample_merged: bool },
+    /// `color` sets the foreground/background color first; `pattern` picks the pattern.
+    Fill { fill: FillKind, color: Option<Color>, pattern: Option<String> },
+    /// `from`/`to` in image pixels; unset means across the selection bounds
+    /// (top to bottom, or centre outwards for radial and conical).
+    Gradient { kind: GradientKind, colors: Vec<Color>, from: Option<(i32, i32)>, to: Option<(i32, i32)> },
     Undo { steps: i32 },
     Redo { steps: i32 },
+    /// `file_name` is a sanitized stem; `path` is filled in by the executor when
+    /// it reserves the file in the output directory.
+    Export {
+        format: ExportFormat,
+        file_name: String,
+        quality: i32,
+        compression: i32,
+        strip_metadata: bool,
+        flatten: bool,
+        path: Option<String>,
+    },
+    SaveXcf { file_name: String, path: Option<String> },
+    /// One layer filled with `background`, or left transparent.
+    NewImage { width: i32, height: i32, background: Color, transparent: bool },
+    /// `path` as the plan gave it until the executor resolves it inside the
+    /// allowed directories.
+    OpenImage { path: String },
+    CloseImage { discard_changes: bool },
+}
+
+/// The open image, as far as validation needs to know it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ImageInfo {
+    pub width: i32,
+    pub height: i32,
+    /// Layer names, top to bottom.
+    pub layers: Vec<String>,
+    /// Horizontal resolution, for lengths given in physical units.
+    pub dpi: f64,
+}
+
+/// GIMP's default resolution, used when the metadata doesn't say.
+const DEFAULT_DPI: f64 = 72.0;
+
+impl ImageInfo {
+    /// Read the parsed JSON text of a `get_image_metadata` result.
+    pub fn from_metadata(meta: &Value) -> Result<ImageInfo, String> {
+        let basic = meta.get("basic").unwrap_or(&Value::Null);
+        let width = basic.get("width").and_then(|v| v.as_i64()).unwrap_or(0) as i32;
+        let height = basic.get("height").and_then(|v| v.as_i64()).unwrap_or(0) as i32;
+        if width <= 0 || height <= 0 {
+            return Err("Image metadata has no width/height".to_string());
+        }
+
+        // Layers may come as a plain array or as { "count": n, "layers": [...] }
+        let layers = meta.get("layers").or_else(|| meta.get("structure").and_then(|s| s.get("layers")));
+        let layers = layers
+            .and_then(|l| l.as_array().or_else(|| l.get("layers").and_then(|v| v.as_array())))
+            .map(|l| {
+                l.iter()
+                    .filter_map(|layer| layer.get("name").and_then(|n| n.as_str()))
+                    .map(|n| n.to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        // Resolution may be { "x": …, "y": … } or flat x_resolution/xres keys
+        let dpi = basic
+            .get("resolution")
+            .and_then(|r| r.get("x").or_else(|| r.get(0)))
+            .or_else(|| basic.get("x_resolution"))
+            .or_else(|| basic.get("xres"))
+            .and_then(|v| v.as_f64())
+            .filter(|v| *v > 0.0)
+            .unwrap_or(DEFAULT_DPI);
+
+        Ok(ImageInfo { width, height, layers, dpi })
+    }
+
+    /// Pixels for a horizontal length (x, width).
+    pub fn x_px(&self, length: Length) -> i32 {
+        length.to_px(self.width, self.dpi)
+    }
+
+    /// Pixels for a vertical length (y, height).
+    pub fn y_px(&self, length: Length) -> i32 {
+        length.to_px(self.height, self.dpi)
+    }
+
+    /// Pixels for a length that isn't tied to an axis (a circle's size):
+    /// percentages are of the shorter side.
+    pub fn min_side_px(&self, length: Length) -> i32 {
+        length.to_px(self.width.min(self.height), self.dpi)
+    }
+}
+
+/// The image lengths are resolved against while validating. Without the image
+/// its size and DPI are 0, and `guessed` notes that a pixel value used them.
+struct Dims {
+    info: ImageInfo,
+    known: bool,
+    guessed: Cell<bool>,
+}
+
+impl Dims {
+    fn new(image: Option<&ImageInfo>) -> Dims {
+        Dims { info: image.cloned().unwrap_or_default(), known: image.is_some(), guessed: Cell::new(false) }
+    }
+
+    fn x_px(&self, length: Length) -> i32 {
+        self.relative(length);
+        self.info.x_px(length)
+    }
+
+    fn y_px(&self, length: Length) -> i32 {
+        self.relative(length);
+        self.info.y_px(length)
+    }
+
+    fn min_side_px(&self, length: Length) -> i32 {
+        self.relative(length);
+        self.info.min_side_px(length)
+    }
+
+    fn width(&self) -> i32 {
+        self.guess();
+        self.info.width
+    }
+
+    fn height(&self) -> i32 {
+        self.guess();
+        self.info.height
+    }
+
+    fn relative(&self, length: Length) {
+        if !matches!(length, Length::Px(_)) {
+            self.guess();
+        }
+    }
+
+    fn guess(&self) {
+        if !self.known {
+            self.guessed.set(true);
+        }
+    }
 }
 
 fn clamp_i32(x: i32, lo: i32, hi: i32) -> i32 {
@@ -30,14 +335,320 @@ fn clamp_f64(x: f64, lo: f64, hi: f64) -> f64 {
     if x < lo { lo } else if x > hi { hi } else { x }
 }
 
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ShapeSize {
+    Side(Length),
+    Box { width: Length, height: Length },
+}
+
+/// Resolve a shape's bbox in pixels from either `bbox` or `center` + `size`.
+/// Missing centre/size default to the image centre and the size the keyword
+/// shortcuts use.
+fn shape_bbox(
+    kind: ShapeKind,
+    center: Option<Point>,
+    size: Option<ShapeSize>,
+    bbox: Option<LengthRect>,
+    dims: &Dims,
+) -> Result<(i32, i32, i32, i32), String> {
+    if let Some(b) = bbox {
+        if center.is_some() || size.is_some() {
+            return Err("give either bbox or center/size, not both".to_string());
+        }
+        return Ok((dims.x_px(b.x), dims.y_px(b.y), dims.x_px(b.width), dims.y_px(b.height)));
+    }
+
+    let (iw, ih) = (dims.width(), dims.height());
+    let (mut w, mut h) = match size {
+        Some(ShapeSize::Side(s)) => (dims.min_side_px(s), dims.min_side_px(s)),
+        Some(ShapeSize::Box { width, height }) => (dims.x_px(width), dims.y_px(height)),
+        None => match kind {
+            ShapeKind::Rectangle => (iw / 2, ih / 2),
+            ShapeKind::Ellipse => (iw * 2 / 3, ih / 3),
+            ShapeKind::Circle | ShapeKind::Triangle | ShapeKind::Heart => {
+                (iw.min(ih) * 2 / 3, iw.min(ih) * 2 / 3)
+            }
+        },
+    };
+    if kind == ShapeKind::Circle {
+        w = w.min(h);
+        h = w;
+    }
+    let (cx, cy) = center.map(|c| (dims.x_px(c.x), dims.y_px(c.y))).unwrap_or((iw / 2, ih / 2));
+    Ok((cx.saturating_sub(w / 2), cy.saturating_sub(h / 2), w, h))
+}
+
+/// Largest side a resize may produce; GIMP itself allows more, but nothing we
+/// do needs it.
+const MAX_RESIZE_SIDE: i32 = 32768;
+
+/// Output size above which growing the image gets a warning (about 50 megapixels).
+const PIXEL_BUDGET: i64 = 50_000_000;
+
+/// Warning for an output size over `PIXEL_BUDGET`; `action` is e.g. "Resizing".
+fn pixel_budget_warning(action: &str, width: i32, height: i32) -> Option<String> {
+    let pixels = width as i64 * height as i64;
+    (pixels > PIXEL_BUDGET).then(|| {
+        format!(
+            "{action} to {width}×{height} ({:.0} megapixels) will be slow and use a lot of memory",
+            pixels as f64 / 1_000_000.0
+        )
+    })
+}
+
+/// Font `add_text` uses when the plan doesn't name one; fontconfig always resolves it.
+const DEFAULT_FONT: &str = "Sans-serif";
+
+const MAX_TEXT_CHARS: usize = 1000;
+
+/// File types `open_image` accepts, by extension.
+const OPENABLE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "webp", "tif", "tiff", "gif", "bmp", "xcf"];
+
+/// Match a requested font against the fonts GIMP has installed: exact name
+/// first (ignoring case), then the shortest installed name starting with it,
+/// so "DejaVu Sans" finds "DejaVu Sans" before "DejaVu Sans Bold".
+pub fn match_font(requested: &str, available: &[String]) -> Result<String, String> {
+    let wanted = requested.trim().to_lowercase();
+    if let Some(font) = available.iter().find(|f| f.to_lowercase() == wanted) {
+        return Ok(font.clone());
+    }
+    if let Some(font) = available
+        .iter()
+        .filter(|f| f.to_lowercase().starts_with(&wanted))
+        .min_by_key(|f| f.len())
+    {
+        return Ok(font.clone());
+    }
+
+    let first_word = wanted.split_whitespace().next().unwrap_or("");
+    let similar: Vec<&str> = available
+        .iter()
+        .filter(|f| !first_word.is_empty() && f.to_lowercase().contains(first_word))
+        .take(5)
+        .map(String::as_str)
+        .collect();
+    if similar.is_empty() {
+        Err(format!("add_text params invalid: font {requested:?} is not installed"))
+    } else {
+        Err(format!(
+            "add_text params invalid: font {requested:?} is not installed (similar: {})",
+            similar.join(", ")
+        ))
+    }
+}
+
+const MAX_POLYGON_POINTS: usize = 200;
+
+const MAX_GRADIENT_COLORS: usize = 16;
+
+const MAX_CURVE_POINTS: usize = 17;
+
+/// Sanitized stem of an output file name (default "image"), plus the export
+/// format its extension names, if any.
+fn output_file_name(name: Option<&str>) -> Result<(String, Option<ExportFormat>), String> {
+    let name = name.map(str::trim).filter(|n| !n.is_empty()).unwrap_or("image");
+    let (stem, format) = match name.rsplit_once('.') {
+        Some((stem, ext)) => match ExportFormat::from_extension(ext) {
+            Some(format) => (stem, Some(format)),
+            None => (name, None),
+        },
+        None => (name, None),
+    };
+    Ok((crate::file_access::sanitize_file_stem(stem)?, format))
+}
+
+/// A trimmed, non-empty layer name.
+fn layer_name(name: Option<&str>) -> Option<String> {
+    name.map(str::trim).filter(|n| !n.is_empty()).map(str::to_string)
+}
+
+/// GIMP renames a clashing layer ("Shadow #1"), which later `{ "layer": … }`
+/// targets wouldn't expect.
+fn warn_duplicate_name(name: &str, image: Option<&ImageInfo>, warnings: &mut Vec<String>) {
+    if image.is_some_and(|img| img.layers.iter().any(|l| l == name)) {
+        warnings.push(format!("A layer named {name:?} already exists; GIMP will number the new name"));
+    }
+}
+
+/// Position of the target layer in the image's layer list, when the target names
+/// one and the list is known. The active layer's position isn't.
+fn target_layer_index(target: &Target, image: Option<&ImageInfo>) -> Option<usize> {
+    let layers = &image?.layers;
+    match target {
+        Target::Layer(name) => layers.iter().position(|l| l == name),
+        Target::LayerIndex(index) => Some(*index).filter(|i| *i < layers.len()),
+        _ => None,
+    }
+}
+
+/// "16:9", "16/9" or "1.78" as width / height.
+fn parse_aspect(text: &str) -> Result<f64, String> {
+    let text = text.trim();
+    let ratio = match text.split_once([':', '/', 'x']) {
+        Some((w, h)) => {
+            let w: f64 = w.trim().parse().map_err(|_| format!("invalid aspect ratio {text:?}"))?;
+            let h: f64 = h.trim().parse().map_err(|_| format!("invalid aspect ratio {text:?}"))?;
+            w / h
+        }
+        None => text.parse().map_err(|_| format!("invalid aspect ratio {text:?}"))?,
+    };
+    if !ratio.is_finite() || ratio <= 0.0 {
+        return Err(format!("invalid aspect ratio {text:?}"));
+    }
+    Ok(ratio)
+}
+
+/// The largest `ratio` (w / h) rectangle that fits the image, placed by `gravity`.
+fn aspect_crop_rect(ratio: f64, gravity: Gravity, width: i32, height: i32) -> (i32, i32, i32, i32) {
+    let (cw, ch) = if width as f64 / height as f64 > ratio {
+        (((height as f64 * ratio).round() as i32).min(width), height)
+    } else {
+        (width, ((width as f64 / ratio).round() as i32).min(height))
+    };
+    let (free_x, free_y) = (width - cw, height - ch);
+    let x = match gravity {
+        Gravity::Left => 0,
+        Gravity::Right => free_x,
+        _ => free_x / 2,
+    };
+    let y = match gravity {
+        Gravity::Top => 0,
+        Gravity::Bottom => free_y,
+        // Line the image's upper third up with the crop's upper third
+        Gravity::FaceSafe => clamp_i32((height - ch) / 3, 0, free_y),
+        _ => free_y / 2,
+    };
+    (x, y, cw, ch)
+}
+
+/// Check the step's target makes sense for the op and, given the image, exists.
+fn validate_target(op: &Op, target: &Target, image: Option<&ImageInfo>) -> Result<(), String> {
+    let name = op.name();
+    if is_whole_image_op(op) && !matches!(target, Target::ActiveLayer | Target::Image) {
+        let target = serde_json::to_value(target).unwrap_or_default();
+        return Err(format!("{name} always applies to the whole image; it can't target {target}"));
+    }
+    if matches!(op, Op::DrawShape) && matches!(target, Target::Selection | Target::Region(_)) {
+        return Err("draw_shape makes its own selection; target a layer instead".to_string());
+    }
+    if is_layer_op(op) {
+        // Property changes can fan out over every layer; structural ones would
+        // shift the indices the other runs use
+        let per_layer = matches!(op, Op::SetLayerVisibility | Op::SetLayerOpacity | Op::SetLayerBlendMode);
+        let ok = match target {
+            Target::ActiveLayer | Target::Layer(_) | Target::LayerIndex(_) => true,
+            Target::AllLayers => per_layer,
+            _ => false,
+        };
+        if !ok {
+            let target = serde_json::to_value(target).unwrap_or_default();
+            return Err(format!("{name} acts on a layer; it can't target {target}"));
+        }
+    }
+    if is_selection_op(op) {
+        // The selection belongs to the image; only select_color reads a layer
+        let ok = match target {
+            Target::ActiveLayer | Target::Image => true,
+            Target::Layer(_) | Target::LayerIndex(_) => matches!(op, Op::SelectColor),
+            _ => false,
+        };
+        if !ok {
+            let target = serde_json::to_value(target).unwrap_or_default();
+            return Err(format!("{name} changes the image's selection; it can't target {target}"));
+        }
+    }
+    if matches!(op, Op::AddText) && matches!(target, Target::Selection | Target::Region(_) | Target::AllLayers) {
+        return Err("add_text creates one new layer above its target; target a single layer instead".to_string());
+    }
+
+    match target {
+        Target::Region(region) => {
+            if region.width < 1 || region.height < 1 {
+                return Err(format!("target region {}×{} is empty", region.width, region.height));
+            }
+            if let Some(img) = image {
+                let outside = region.x >= img.width
+                    || region.y >= img.height
+                    || region.x.saturating_add(region.width) <= 0
+                    || region.y.saturating_add(region.height) <= 0;
+                if outside {
+                    return Err(format!(
+                        "target region at ({}, {}) is outside the {}×{} image",
+                        region.x, region.y, img.width, img.height
+                    ));
+                }
+            }
+        }
+        // An empty layer list means the metadata didn't include layers, not that there are none
+        Target::Layer(layer) => {
+            if let Some(img) = image.filter(|i| !i.layers.is_empty()) {
+                if !img.layers.iter().any(|l| l == layer) {
+                    return Err(format!(
+                        "target layer {layer:?} does not exist (layers: {})",
+                        img.layers.join(", ")
+                    ));
+                }
+            }
+        }
+        Target::LayerIndex(index) => {
+            if let Some(img) = image.filter(|i| !i.layers.is_empty()) {
+                if *index >= img.layers.len() {
+                    return Err(format!(
+                        "target layer_index {index} is out of range (the image has {} layers)",
+                        img.layers.len()
+                    ));
+                }
+            }
+        }
+        Target::ActiveLayer | Target::Selection | Target::AllLayers | Target::Image => {}
+    }
+    Ok(())
+}
+
+/// Validate a step's params without knowing the image (structure only).
 pub fn validate_step(step: &ActionStep) -> Result<ValidatedStep, String> {
+    validate_step_for_image(step, None)
+}
+
+/// Validate a step's params; with `image`, geometry is also checked against
+/// the image and percentages, units, anchors and defaults are resolved to pixels.
+pub fn validate_step_for_image(step: &ActionStep, image: Option<&ImageInfo>) -> Result<ValidatedStep, String> {
+    validate_target(&step.op, &step.target, image)?;
+    let mut warnings = Vec::new();
+
+    // Without the image, relative lengths resolve to placeholder 0s; only the
+    // params' shape is being checked then
+    let dims = Dims::new(image);
+
     let validated = match step.op {
         Op::DrawLine => {
             #[derive(Deserialize)]
-            struct P { x1: i32, y1: i32, x2: i32, y2: i32 }
+            struct P {
+                x1: Option<Length>,
+                y1: Option<Length>,
+                x2: Option<Length>,
+                y2: Option<Length>,
+                from: Option<Point>,
+                to: Option<Point>,
+                color: Option<Color>,
+            }
             let p: P = serde_json::from_value(step.params.clone())
                 .map_err(|e| format!("draw_line params invalid: {e}"))?;
-            ValidatedParams::DrawLine { x1: p.x1, y1: p.y1, x2: p.x2, y2: p.y2 }
+            let (from, to) = match (p.from, p.to, p.x1, p.y1, p.x2, p.y2) {
+                (Some(from), Some(to), None, None, None, None) => (from, to),
+                (None, None, Some(x1), Some(y1), Some(x2), Some(y2)) => {
+                    (Point { x: x1, y: y1 }, Point { x: x2, y: y2 })
+                }
+                _ => return Err("draw_line params invalid: give x1, y1, x2 and y2, or from and to".to_string()),
+            };
+            ValidatedParams::DrawLine {
+                x1: dims.x_px(from.x),
+                y1: dims.y_px(from.y),
+                x2: dims.x_px(to.x),
+                y2: dims.y_px(to.y),
+                color: p.color.unwrap_or(Color::DEFAULT_INK),
+            }
         }
 
         Op::CropSquare => {
@@ -45,6 +656,58 @@ pub fn validate_step(step: &ActionStep) -> Result<ValidatedStep, String> {
             ValidatedParams::CropSquare {}
         }
 
+        Op::Crop => {
+            #[derive(Deserialize)]
+            #[serde(rename_all = "snake_case")]
+            enum Mode { Rect, Aspect, Auto, Selection }
+            #[derive(Deserialize)]
+            struct P {
+                mode: Mode,
+                rect: Option<LengthRect>,
+                aspect: Option<String>,
+                #[serde(default)] gravity: Gravity,
+            }
+            let p: P = serde_json::from_value(step.params.clone())
+                .map_err(|e| format!("crop params invalid: {e}"))?;
+
+            match p.mode {
+                Mode::Auto => ValidatedParams::AutoCrop {},
+                Mode::Selection => ValidatedParams::CropToSelection {},
+                Mode::Rect => {
+                    let rect = p.rect.ok_or("crop params invalid: mode \"rect\" needs a rect")?;
+                    let (x, y) = (dims.x_px(rect.x), dims.y_px(rect.y));
+                    let (width, height) = (dims.x_px(rect.width), dims.y_px(rect.height));
+                    if let Some(img) = image {
+                        let inside = x >= 0
+                            && y >= 0
+                            && x.saturating_add(width) <= img.width
+                            && y.saturating_add(height) <= img.height;
+                        if width < 1 || height < 1 || !inside {
+                            return Err(format!(
+                                "crop params invalid: {width}×{height} at ({x}, {y}) is not inside the {}×{} image",
+                                img.width, img.height
+                            ));
+                        }
+                    }
+                    ValidatedParams::Crop { x, y, width, height }
+                }
+                Mode::Aspect => {
+                    let aspect = p.aspect.ok_or("crop params invalid: mode \"aspect\" needs an aspect")?;
+                    let ratio = parse_aspect(&aspect).map_err(|e| format!("crop params invalid: {e}"))?;
+                    // Without the image size this is a placeholder the executor won't run
+                    let (x, y, width, height) = aspect_crop_rect(ratio, p.gravity, dims.width(), dims.height());
+                    if image.is_some() && (width < 1 || height < 1) {
+                        return Err(format!(
+                            "crop params invalid: aspect {aspect:?} leaves a {width}×{height} crop of the {}×{} image",
+                            dims.width(),
+                            dims.height()
+                        ));
+                    }
+                    ValidatedParams::Crop { x, y, width, height }
+                }
+            }
+        }
+
         Op::ResizeWidth => {
             #[derive(Deserialize)]
             struct P { width: i32 }
@@ -54,6 +717,151 @@ pub fn validate_step(step: &ActionStep) -> Result<ValidatedStep, String> {
             ValidatedParams::ResizeWidth { width }
         }
 
+        Op::Resize => {
+            #[derive(Deserialize)]
+            #[serde(rename_all = "snake_case")]
+            enum Mode { Width, Height, Percent, Exact, Fit, Fill }
+            #[derive(Deserialize)]
+            struct P {
+                mode: Mode,
+                width: Option<Length>,
+                height: Option<Length>,
+                percent: Option<f64>,
+                #[serde(default)] interpolation: Interpolation,
+                #[serde(default)] no_upscale: bool,
+            }
+            let p: P = serde_json::from_value(step.params.clone())
+                .map_err(|e| format!("resize params invalid: {e}"))?;
+
+            let need = |v: Option<Length>, name: &str| {
+                v.ok_or_else(|| format!("resize params invalid: this mode needs {name}"))
+            };
+            let (iw, ih) = (dims.width() as f64, dims.height() as f64);
+            // Scale factors per axis, plus the box to crop to for fill
+            let (sx, sy, crop_box) = match p.mode {
+                Mode::Width => {
+                    let s = dims.x_px(need(p.width, "width")?) as f64 / iw;
+                    (s, s, None)
+                }
+                Mode::Height => {
+                    let s = dims.y_px(need(p.height, "height")?) as f64 / ih;
+                    (s, s, None)
+                }
+                Mode::Percent => {
+                    let pct = p.percent.ok_or("resize params invalid: this mode needs percent")?;
+                    let s = clamp_f64(pct, 1.0, 1000.0) / 100.0;
+                    (s, s, None)
+                }
+                Mode::Exact => (
+                    dims.x_px(need(p.width, "width")?) as f64 / iw,
+                    dims.y_px(need(p.height, "height")?) as f64 / ih,
+                    None,
+                ),
+                Mode::Fit | Mode::Fill => {
+                    let bw = dims.x_px(need(p.width, "width")?);
+                    let bh = dims.y_px(need(p.height, "height")?);
+                    let (fx, fy) = (bw as f64 / iw, bh as f64 / ih);
+                    if matches!(p.mode, Mode::Fit) {
+                        (fx.min(fy), fx.min(fy), None)
+                    } else {
+                        (fx.max(fy), fx.max(fy), Some((bw, bh)))
+                    }
+                }
+            };
+            let (sx, sy) = if p.no_upscale { (sx.min(1.0), sy.min(1.0)) } else { (sx, sy) };
+
+            // Without the image every size is a placeholder 0
+            let scale_width = (iw * sx).round() as i32;
+            let scale_height = (ih * sy).round() as i32;
+            let (width, height) = match crop_box {
+                Some((bw, bh)) => (bw.min(scale_width), bh.min(scale_height)),
+                None => (scale_width, scale_height),
+            };
+
+            if let Some(img) = image {
+                if scale_width < 1 || scale_height < 1 || scale_width > MAX_RESIZE_SIDE || scale_height > MAX_RESIZE_SIDE {
+                    return Err(format!(
+                        "resize params invalid: {}×{} → {scale_width}×{scale_height} is outside 1..{MAX_RESIZE_SIDE} pixels per side",
+                        img.width, img.height
+                    ));
+                }
+                warnings.extend(pixel_budget_warning("Resizing", scale_width, scale_height));
+            }
+
+            ValidatedParams::Resize { scale_width, scale_height, width, height, interpolation: p.interpolation }
+        }
+
+        Op::Pad | Op::Border => {
+            #[derive(Deserialize)]
+            struct P {
+                top: Option<Length>,
+                right: Option<Length>,
+                bottom: Option<Length>,
+                left: Option<Length>,
+                all: Option<Length>,
+                width: Option<Length>,
+                aspect: Option<String>,
+                color: Option<Color>,
+            }
+            let name = step.op.name();
+            let p: P = serde_json::from_value(step.params.clone())
+                .map_err(|e| format!("{name} params invalid: {e}"))?;
+            let color = p.color.unwrap_or(Color::WHITE);
+
+            let (left, top, right, bottom) = if let Op::Border = step.op {
+                let width = p.width.ok_or_else(|| format!("{name} params invalid: width is required"))?;
+                let px = dims.min_side_px(width);
+                (px, px, px, px)
+            } else if let Some(aspect) = &p.aspect {
+                let sides = [p.top, p.right, p.bottom, p.left, p.all];
+                if sides.iter().any(Option::is_some) {
+                    return Err(format!("{name} params invalid: give an aspect or margins, not both"));
+                }
+                let ratio = parse_aspect(aspect).map_err(|e| format!("{name} params invalid: {e}"))?;
+                let (w, h) = (dims.width(), dims.height());
+                // Grow whichever side is short of the ratio, splitting the margin evenly
+                let (extra_w, extra_h) = if (w as f64) < h as f64 * ratio {
+                    ((h as f64 * ratio).round() as i32 - w, 0)
+                } else {
+                    (0, (w as f64 / ratio).round() as i32 - h)
+                };
+                if image.is_some() && extra_w == 0 && extra_h == 0 {
+                    warnings.push(format!("The image already has a {aspect} aspect ratio; nothing to pad"));
+                }
+                (extra_w / 2, extra_h / 2, extra_w - extra_w / 2, extra_h - extra_h / 2)
+            } else {
+                let all = p.all.map(|l| dims.min_side_px(l));
+                let side = |v: Option<Length>, horizontal: bool| match v {
+                    Some(l) if horizontal => dims.x_px(l),
+                    Some(l) => dims.y_px(l),
+                    None => all.unwrap_or(0),
+                };
+                let sides = (side(p.left, true), side(p.top, false), side(p.right, true), side(p.bottom, false));
+                if [p.top, p.right, p.bottom, p.left, p.all].iter().all(Option::is_none) {
+                    return Err(format!("{name} params invalid: give margins (top, right, bottom, left or all) or an aspect"));
+                }
+                sides
+            };
+
+            if [left, top, right, bottom].iter().any(|m| *m < 0) {
+                return Err(format!("{name} params invalid: margins can't be negative (use crop to shrink the canvas)"));
+            }
+            // Saturate so huge margins fail the size check instead of overflowing
+            let width = dims.width().saturating_add(left).saturating_add(right);
+            let height = dims.height().saturating_add(top).saturating_add(bottom);
+            if let Some(img) = image {
+                if width > MAX_RESIZE_SIDE || height > MAX_RESIZE_SIDE {
+                    return Err(format!(
+                        "{name} params invalid: {}×{} → {width}×{height} is over {MAX_RESIZE_SIDE} pixels per side",
+                        img.width, img.height
+                    ));
+                }
+                warnings.extend(pixel_budget_warning("Padding", width, height));
+            }
+
+            ValidatedParams::Pad { left, top, right, bottom, color, width, height }
+        }
+
         Op::BrightnessContrast => {
             #[derive(Deserialize)]
             struct P { brightness: f64, contrast: f64 }
@@ -73,6 +881,505 @@ pub fn validate_step(step: &ActionStep) -> Result<ValidatedStep, String> {
             ValidatedParams::Blur { radius }
         }
 
+        Op::Rotate => {
+            #[derive(Deserialize)]
+            struct P { degrees: f64, #[serde(default)] interpolation: Interpolation }
+            let p: P = serde_json::from_value(step.params.clone())
+                .map_err(|e| format!("rotate params invalid: {e}"))?;
+            if !p.degrees.is_finite() {
+                return Err("rotate params invalid: degrees must be a number".to_string());
+            }
+            // Normalise to clockwise (0, 360): -90 becomes 270
+            let degrees = p.degrees.rem_euclid(360.0);
+            if !(0.01..=359.99).contains(&degrees) {
+                return Err("rotate params invalid: rotating by 0 or 360 degrees does nothing".to_string());
+            }
+            ValidatedParams::Rotate { degrees, interpolation: p.interpolation }
+        }
+
+        Op::Flip => {
+            #[derive(Deserialize)]
+            struct P { orientation: FlipOrientation }
+            let p: P = serde_json::from_value(step.params.clone())
+                .map_err(|e| format!("flip params invalid: {e}"))?;
+            ValidatedParams::Flip { orientation: p.orientation }
+        }
+
+        Op::Desaturate => {
+            #[derive(Deserialize)]
+            struct P { #[serde(default)] mode: DesaturateMode }
+            let p: P = serde_json::from_value(step.params.clone())
+                .map_err(|e| format!("desaturate params invalid: {e}"))?;
+            ValidatedParams::Desaturate { mode: p.mode }
+        }
+
+        Op::HueSaturation => {
+            #[derive(Deserialize)]
+            struct P {
+                #[serde(default)] range: HueRange,
+                #[serde(default)] hue: f64,
+                #[serde(default)] lightness: f64,
+                #[serde(default)] saturation: f64,
+            }
+            let p: P = serde_json::from_value(step.params.clone())
+                .map_err(|e| format!("hue_saturation params invalid: {e}"))?;
+            let hue = clamp_f64(p.hue, -180.0, 180.0);
+            let lightness = clamp_f64(p.lightness, -100.0, 100.0);
+            let saturation = clamp_f64(p.saturation, -100.0, 100.0);
+            if hue == 0.0 && lightness == 0.0 && saturation == 0.0 {
+                return Err("hue_saturation params invalid: hue, lightness and saturation are all 0".to_string());
+            }
+            ValidatedParams::HueSaturation { range: p.range, hue, lightness, saturation }
+        }
+
+        Op::Invert => ValidatedParams::Invert {},
+
+        Op::Threshold => {
+            #[derive(Deserialize)]
+            struct P { low: Option<i32>, high: Option<i32> }
+            let p: P = serde_json::from_value(step.params.clone())
+                .map_err(|e| format!("threshold params invalid: {e}"))?;
+            let low = clamp_i32(p.low.unwrap_or(127), 0, 255);
+            let high = clamp_i32(p.high.unwrap_or(255), 0, 255);
+            if low > high {
+                return Err(format!("threshold params invalid: low ({low}) is above high ({high})"));
+            }
+            ValidatedParams::Threshold { low, high }
+        }
+
+        Op::Posterize => {
+            #[derive(Deserialize)]
+            struct P { levels: i32 }
+            let p: P = serde_json::from_value(step.params.clone())
+                .map_err(|e| format!("posterize params invalid: {e}"))?;
+            ValidatedParams::Posterize { levels: clamp_i32(p.levels, 2, 255) }
+        }
+
+        Op::Levels => {
+            #[derive(Deserialize)]
+            struct P {
+                #[serde(default)] channel: Channel,
+                input_black: Option<f64>,
+                input_white: Option<f64>,
+                gamma: Option<f64>,
+                output_black: Option<f64>,
+                output_white: Option<f64>,
+            }
+            let p: P = serde_json::from_value(step.params.clone())
+                .map_err(|e| format!("levels params invalid: {e}"))?;
+            let level = |v: Option<f64>, default: f64| clamp_f64(v.unwrap_or(default), 0.0, 255.0);
+            let (input_black, input_white) = (level(p.input_black, 0.0), level(p.input_white, 255.0));
+            if input_black >= input_white {
+                return Err(format!(
+                    "levels params invalid: input_black {input_black} must be below input_white {input_white}"
+                ));
+            }
+            // Output black above output white is allowed: it inverts the channel
+            ValidatedParams::Levels {
+                channel: p.channel,
+                input_black,
+                input_white,
+                gamma: clamp_f64(p.gamma.unwrap_or(1.0), 0.1, 10.0),
+                output_black: level(p.output_black, 0.0),
+                output_white: level(p.output_white, 255.0),
+            }
+        }
+
+        Op::Curves => {
+            #[derive(Deserialize)]
+            struct P {
+                #[serde(default)] channel: Channel,
+                points: Vec<(f64, f64)>,
+            }
+            let p: P = serde_json::from_value(step.params.clone())
+                .map_err(|e| format!("curves params invalid: {e}"))?;
+            if !(2..=MAX_CURVE_POINTS).contains(&p.points.len()) {
+                return Err(format!(
+                    "curves params invalid: a curve needs 2 to {MAX_CURVE_POINTS} points, got {}",
+                    p.points.len()
+                ));
+            }
+            if p.points.iter().any(|&(x, y)| !(0.0..=255.0).contains(&x) || !(0.0..=255.0).contains(&y)) {
+                return Err("curves params invalid: points must be in 0..255".to_string());
+            }
+            if p.points.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+                return Err("curves params invalid: point inputs must increase".to_string());
+            }
+            ValidatedParams::Curves { channel: p.channel, points: p.points }
+        }
+
+        Op::ColorBalance => {
+            #[derive(Deserialize)]
+            struct P {
+                #[serde(default)] range: ToneRange,
+                cyan_red: Option<f64>,
+                magenta_green: Option<f64>,
+                yellow_blue: Option<f64>,
+                preserve_luminosity: Option<bool>,
+            }
+            let p: P = serde_json::from_value(step.params.clone())
+                .map_err(|e| format!("color_balance params invalid: {e}"))?;
+            let shift = |v: Option<f64>| clamp_f64(v.unwrap_or(0.0), -100.0, 100.0);
+            ValidatedParams::ColorBalance {
+                range: p.range,
+                cyan_red: shift(p.cyan_red),
+                magenta_green: shift(p.magenta_green),
+                yellow_blue: shift(p.yellow_blue),
+                preserve_luminosity: p.preserve_luminosity.unwrap_or(true),
+            }
+        }
+
+        Op::ColorTemperature => {
+            #[derive(Deserialize)]
+            struct P { original: Option<f64>, intended: f64 }
+            let p: P = serde_json::from_value(step.params.clone())
+                .map_err(|e| format!("color_temperature params invalid: {e}"))?;
+            let kelvin = |k: f64| clamp_f64(k, 1000.0, 12000.0);
+            ValidatedParams::ColorTemperature {
+                original: kelvin(p.original.unwrap_or(6500.0)),
+                intended: kelvin(p.intended),
+            }
+        }
+
+        Op::StretchContrast => ValidatedParams::StretchContrast {},
+
+        Op::GeglFilter => {
+            #[derive(Deserialize)]
+            struct P {
+                operation: String,
+                #[serde(default)] properties: Map<String, Value>,
+            }
+            let p: P = serde_json::from_value(step.params.clone())
+                .map_err(|e| format!("gegl_filter params invalid: {e}"))?;
+            let spec = crate::gegl_filters::find(&p.operation).ok_or_else(|| {
+                let allowed: Vec<&str> = crate::gegl_filters::FILTERS.iter().map(|f| f.name).collect();
+                format!(
+                    "gegl_filter params invalid: {:?} is not an allowed filter (allowed: {})",
+                    p.operation,
+                    allowed.join(", ")
+                )
+            })?;
+            let properties = spec.check(&p.properties).map_err(|e| format!("gegl_filter params invalid: {e}"))?;
+            ValidatedParams::GeglFilter { operation: spec.operation.to_string(), properties }
+        }
+
+        Op::DrawShape => {
+            #[derive(Deserialize)]
+            struct P {
+                kind: ShapeKind,
+                center: Option<Point>,
+                size: Option<ShapeSize>,
+                bbox: Option<LengthRect>,
+                color: Option<Color>,
+                fill: Option<bool>,
+                stroke_width: Option<f64>,
+            }
+            let p: P = serde_json::from_value(step.params.clone())
+                .map_err(|e| format!("draw_shape params invalid: {e}"))?;
+            let (x, y, width, height) = shape_bbox(p.kind, p.center, p.size, p.bbox, &dims)
+                .map_err(|e| format!("draw_shape params invalid: {e}"))?;
+
+            if let Some(img) = image {
+                if width < 1 || height < 1 {
+                    return Err(format!("draw_shape params invalid: size {width}×{height} is empty"));
+                }
+                if x >= img.width || y >= img.height || x.saturating_add(width) <= 0 || y.saturating_add(height) <= 0 {
+                    return Err(format!(
+                        "draw_shape params invalid: shape at ({x}, {y}) size {width}×{height} is outside the {}×{} image",
+                        img.width, img.height
+                    ));
+                }
+            }
+
+            ValidatedParams::DrawShape {
+                kind: p.kind,
+                x,
+                y,
+                width,
+                height,
+                color: p.color.unwrap_or(Color::DEFAULT_INK),
+                fill: p.fill.unwrap_or(true),
+                stroke_width: clamp_f64(p.stroke_width.unwrap_or(4.0), 1.0, 200.0),
+            }
+        }
+
+        Op::AddText => {
+            #[derive(Deserialize)]
+            struct P {
+                text: String,
+                font: Option<String>,
+                size: Option<Length>,
+                color: Option<Color>,
+                #[serde(default)] align: TextAlign,
+                anchor: Option<Point>,
+            }
+            let p: P = serde_json::from_value(step.params.clone())
+                .map_err(|e| format!("add_text params invalid: {e}"))?;
+            if p.text.trim().is_empty() {
+                return Err("add_text params invalid: text is empty".to_string());
+            }
+            if p.text.chars().count() > MAX_TEXT_CHARS {
+                return Err(format!("add_text params invalid: text is longer than {MAX_TEXT_CHARS} characters"));
+            }
+
+            // A % size is of the shorter image side, like draw_shape; "12pt" uses the image DPI
+            let size = dims.min_side_px(p.size.unwrap_or(Length::Percent(6.0)));
+            let anchor = p.anchor.unwrap_or(Point { x: Length::Percent(50.0), y: Length::Percent(50.0) });
+            let (x, y) = (dims.x_px(anchor.x), dims.y_px(anchor.y));
+            let fraction = |v: i32, extent: i32| if extent > 0 { (v as f64 / extent as f64).clamp(0.0, 1.0) } else { 0.5 };
+
+            ValidatedParams::AddText {
+                text: p.text,
+                font: p.font.filter(|f| !f.trim().is_empty()).unwrap_or_else(|| DEFAULT_FONT.to_string()),
+                size: if image.is_some() { clamp_i32(size, 4, 2000) } else { size },
+                color: p.color.unwrap_or(Color::DEFAULT_INK),
+                align: p.align,
+                x,
+                y,
+                anchor_x: fraction(x, dims.width()),
+                anchor_y: fraction(y, dims.height()),
+                margin: dims.min_side_px(Length::Percent(3.0)),
+            }
+        }
+
+        Op::NewLayer => {
+            #[derive(Deserialize)]
+            struct P {
+                name: Option<String>,
+                #[serde(default)] fill: LayerFill,
+                opacity: Option<f64>,
+                #[serde(default)] blend_mode: BlendMode,
+            }
+            let p: P = serde_json::from_value(step.params.clone())
+                .map_err(|e| format!("new_layer params invalid: {e}"))?;
+            let name = layer_name(p.name.as_deref()).unwrap_or_else(|| "Layer".to_string());
+            warn_duplicate_name(&name, image, &mut warnings);
+            ValidatedParams::NewLayer {
+                name,
+                fill: p.fill,
+                opacity: clamp_f64(p.opacity.unwrap_or(100.0), 0.0, 100.0),
+                blend_mode: p.blend_mode,
+            }
+        }
+
+        Op::DuplicateLayer => {
+            #[derive(Deserialize)]
+            struct P { name: Option<String> }
+            let p: P = serde_json::from_value(step.params.clone())
+                .map_err(|e| format!("duplicate_layer params invalid: {e}"))?;
+            let name = layer_name(p.name.as_deref());
+            if let Some(name) = &name {
+                warn_duplicate_name(name, image, &mut warnings);
+            }
+            ValidatedParams::DuplicateLayer { name }
+        }
+
+        Op::DeleteLayer => {
+            if let Some(img) = image.filter(|i| i.layers.len() == 1) {
+                return Err(format!("delete_layer: {:?} is the image's only layer", img.layers[0]));
+            }
+            ValidatedParams::DeleteLayer {}
+        }
+
+        Op::RenameLayer => {
+            #[derive(Deserialize)]
+            struct P { name: String }
+            let p: P = serde_json::from_value(step.params.clone())
+                .map_err(|e| format!("rename_layer params invalid: {e}"))?;
+            let name = layer_name(Some(&p.name))
+                .ok_or_else(|| "rename_layer params invalid: name is empty".to_string())?;
+            warn_duplicate_name(&name, image, &mut warnings);
+            ValidatedParams::RenameLayer { name }
+        }
+
+        Op::MoveLayer => {
+            #[derive(Deserialize)]
+            struct P { to: LayerPosition }
+            let p: P = serde_json::from_value(step.params.clone())
+                .map_err(|e| format!("move_layer params invalid: {e}"))?;
+            if let (LayerPosition::Index(index), Some(img)) = (p.to, image.filter(|i| !i.layers.is_empty())) {
+                if index >= img.layers.len() {
+                    return Err(format!(
+                        "move_layer params invalid: index {index} is out of range (the image has {} layers)",
+                        img.layers.len()
+                    ));
+                }
+            }
+            ValidatedParams::MoveLayer { to: p.to }
+        }
+
+        Op::SetLayerVisibility => {
+            #[derive(Deserialize)]
+            struct P { visible: bool }
+            let p: P = serde_json::from_value(step.params.clone())
+                .map_err(|e| format!("set_layer_visibility params invalid: {e}"))?;
+            ValidatedParams::SetLayerVisibility { visible: p.visible }
+        }
+
+        Op::MergeDown => {
+            if let (Some(index), Some(img)) = (target_layer_index(&step.target, image), image) {
+                if index + 1 >= img.layers.len() {
+                    return Err(format!("merge_down: {:?} is the bottom layer; there is nothing below it", img.layers[index]));
+                }
+            }
+            ValidatedParams::MergeDown {}
+        }
+
+        Op::SetLayerOpacity => {
+            #[derive(Deserialize)]
+            struct P { opacity: f64 }
+            let p: P = serde_json::from_value(step.params.clone())
+                .map_err(|e| format!("set_layer_opacity params invalid: {e}"))?;
+            ValidatedParams::SetLayerOpacity { opacity: clamp_f64(p.opacity, 0.0, 100.0) }
+        }
+
+        Op::SetLayerBlendMode => {
+            #[derive(Deserialize)]
+            struct P { mode: BlendMode }
+            let p: P = serde_json::from_value(step.params.clone())
+                .map_err(|e| format!("set_layer_blend_mode params invalid: {e}"))?;
+            ValidatedParams::SetLayerBlendMode { mode: p.mode }
+        }
+
+        Op::SetActiveLayer => ValidatedParams::SetActiveLayer {},
+
+        Op::SelectAll => ValidatedParams::SelectAll {},
+        Op::SelectNone => ValidatedParams::SelectNone {},
+        Op::SelectInvert => ValidatedParams::SelectInvert {},
+
+        Op::SelectShape => {
+            #[derive(Deserialize)]
+            #[serde(rename_all = "snake_case")]
+            enum Shape { Rect, Ellipse, Polygon }
+            #[derive(Deserialize)]
+            struct P {
+                shape: Shape,
+                rect: Option<LengthRect>,
+                points: Option<Vec<Point>>,
+                #[serde(default)] mode: SelectionMode,
+            }
+            let p: P = serde_json::from_value(step.params.clone())
+                .map_err(|e| format!("select_shape params invalid: {e}"))?;
+
+            if let Shape::Polygon = p.shape {
+                let points = p.points.ok_or("select_shape params invalid: a polygon needs points")?;
+                if !(3..=MAX_POLYGON_POINTS).contains(&points.len()) {
+                    return Err(format!(
+                        "select_shape params invalid: a polygon needs 3 to {MAX_POLYGON_POINTS} points, got {}",
+                        points.len()
+                    ));
+                }
+                let points = points.iter().map(|pt| (dims.x_px(pt.x), dims.y_px(pt.y))).collect();
+                ValidatedParams::SelectPolygon { points, mode: p.mode }
+            } else {
+                let rect = p.rect.ok_or("select_shape params invalid: rect and ellipse need a rect")?;
+                let (x, y) = (dims.x_px(rect.x), dims.y_px(rect.y));
+                let (width, height) = (dims.x_px(rect.width), dims.y_px(rect.height));
+                if let Some(img) = image {
+                    if width < 1 || height < 1 {
+                        return Err(format!("select_shape params invalid: size {width}×{height} is empty"));
+                    }
+                    if x >= img.width || y >= img.height || x.saturating_add(width) <= 0 || y.saturating_add(height) <= 0 {
+                        return Err(format!(
+                            "select_shape params invalid: {width}×{height} at ({x}, {y}) is outside the {}×{} image",
+                            img.width, img.height
+                        ));
+                    }
+                }
+                match p.shape {
+                    Shape::Ellipse => ValidatedParams::SelectEllipse { x, y, width, height, mode: p.mode },
+                    _ => ValidatedParams::SelectRect { x, y, width, height, mode: p.mode },
+                }
+            }
+        }
+
+        Op::ModifySelection => {
+            #[derive(Deserialize)]
+            #[serde(rename_all = "snake_case")]
+            enum Action { Grow, Shrink, Feather, Border }
+            #[derive(Deserialize)]
+            struct P { action: Action, amount: Length }
+            let p: P = serde_json::from_value(step.params.clone())
+                .map_err(|e| format!("modify_selection params invalid: {e}"))?;
+            // A % amount is of the shorter image side
+            let amount = dims.min_side_px(p.amount);
+            let pixels = if image.is_some() { clamp_i32(amount, 1, 1000) } else { amount };
+            match p.action {
+                Action::Grow => ValidatedParams::GrowSelection { pixels },
+                Action::Shrink => ValidatedParams::ShrinkSelection { pixels },
+                Action::Feather => ValidatedParams::FeatherSelection { radius: pixels as f64 },
+                Action::Border => ValidatedParams::BorderSelection { pixels },
+            }
+        }
+
+        Op::SelectColor => {
+            #[derive(Deserialize)]
+            struct P {
+                color: Color,
+                threshold: Option<i32>,
+                #[serde(default)] mode: SelectionMode,
+                #[serde(default)] sample_merged: bool,
+            }
+            let p: P = serde_json::from_value(step.params.clone())
+                .map_err(|e| format!("select_color params invalid: {e}"))?;
+            ValidatedParams::SelectColor {
+                color: p.color,
+                threshold: clamp_i32(p.threshold.unwrap_or(15), 0, 255),
+                mode: p.mode,
+                sample_merged: p.sample_merged,
+            }
+        }
+
+        Op::Fill => {
+            #[derive(Deserialize)]
+            struct P {
+                fill: Option<FillKind>,
+                color: Option<Color>,
+                pattern: Option<String>,
+            }
+            let p: P = serde_json::from_value(step.params.clone())
+                .map_err(|e| format!("fill params invalid: {e}"))?;
+            let fill = p.fill.unwrap_or_default();
+            if p.color.is_some() && !matches!(fill, FillKind::Foreground | FillKind::Background) {
+                return Err(format!(
+                    "fill params invalid: color only applies to foreground or background fills, not {}",
+                    serde_json::to_value(fill).unwrap_or_default()
+                ));
+            }
+            let pattern = p.pattern.map(|n| n.trim().to_string()).filter(|n| !n.is_empty());
+            if pattern.is_some() && fill != FillKind::Pattern {
+                return Err("fill params invalid: pattern needs \"fill\": \"pattern\"".to_string());
+            }
+            ValidatedParams::Fill { fill, color: p.color, pattern }
+        }
+
+        Op::Gradient => {
+            #[derive(Deserialize)]
+            struct P {
+                #[serde(default)] kind: GradientKind,
+                colors: Vec<Color>,
+                from: Option<Point>,
+                to: Option<Point>,
+            }
+            let p: P = serde_json::from_value(step.params.clone())
+                .map_err(|e| format!("gradient params invalid: {e}"))?;
+            if !(2..=MAX_GRADIENT_COLORS).contains(&p.colors.len()) {
+                return Err(format!(
+                    "gradient params invalid: a gradient needs 2 to {MAX_GRADIENT_COLORS} colors, got {}",
+                    p.colors.len()
+                ));
+            }
+            if p.from.is_some() != p.to.is_some() {
+                return Err("gradient params invalid: give both from and to, or neither".to_string());
+            }
+            let from = p.from.map(|pt| (dims.x_px(pt.x), dims.y_px(pt.y)));
+            let to = p.to.map(|pt| (dims.x_px(pt.x), dims.y_px(pt.y)));
+            if image.is_some() && from.is_some() && from == to {
+                return Err("gradient params invalid: from and to are the same point".to_string());
+            }
+            ValidatedParams::Gradient { kind: p.kind, colors: p.colors, from, to }
+        }
+
         Op::Undo => {
             #[derive(Deserialize)]
             struct P { steps: Option<i32> }
@@ -90,6 +1397,106 @@ pub fn validate_step(step: &ActionStep) -> Result<ValidatedStep, String> {
             let steps = clamp_i32(p.steps.unwrap_or(1), 1, 50);
             ValidatedParams::Redo { steps }
         }
+
+        Op::Export => {
+            #[derive(Deserialize)]
+            struct P {
+                format: Option<ExportFormat>,
+                file_name: Option<String>,
+                quality: Option<i32>,
+                compression: Option<i32>,
+                #[serde(default)] strip_metadata: bool,
+                #[serde(default)] flatten: bool,
+            }
+            let p: P = serde_json::from_value(step.params.clone())
+                .map_err(|e| format!("export params invalid: {e}"))?;
+            let (file_name, ext_format) = output_file_name(p.file_name.as_deref())
+                .map_err(|e| format!("export params invalid: {e}"))?;
+            let format = match (p.format, ext_format) {
+                (Some(format), Some(ext)) if format != ext => {
+                    return Err(format!(
+                        "export params invalid: format {} doesn't match the file name's extension",
+                        format.extension()
+                    ));
+                }
+                (format, ext) => format.or(ext).unwrap_or_default(),
+            };
+            ValidatedParams::Export {
+                format,
+                file_name,
+                quality: clamp_i32(p.quality.unwrap_or(90), 1, 100),
+                compression: clamp_i32(p.compression.unwrap_or(6), 0, 9),
+                strip_metadata: p.strip_metadata,
+                flatten: p.flatten,
+                path: None,
+            }
+        }
+
+        Op::SaveXcf => {
+            #[derive(Deserialize)]
+            struct P { file_name: Option<String> }
+            let p: P = serde_json::from_value(step.params.clone())
+                .map_err(|e| format!("save_xcf params invalid: {e}"))?;
+            let name = p.file_name.as_deref().map(|n| n.trim().trim_end_matches(".xcf"));
+            let (file_name, _) = output_file_name(name).map_err(|e| format!("save_xcf params invalid: {e}"))?;
+            ValidatedParams::SaveXcf { file_name, path: None }
+        }
+
+        Op::NewImage => {
+            #[derive(Deserialize)]
+            struct P {
+                width: Option<i32>,
+                height: Option<i32>,
+                background: Option<Color>,
+                #[serde(default)] transparent: bool,
+            }
+            let p: P = serde_json::from_value(step.params.clone())
+                .map_err(|e| format!("new_image params invalid: {e}"))?;
+            let (width, height) = (p.width.unwrap_or(1920), p.height.unwrap_or(1080));
+            if !(1..=MAX_RESIZE_SIDE).contains(&width) || !(1..=MAX_RESIZE_SIDE).contains(&height) {
+                return Err(format!(
+                    "new_image params invalid: {width}×{height} is outside 1..{MAX_RESIZE_SIDE} pixels per side"
+                ));
+            }
+            warnings.extend(pixel_budget_warning("Creating an image", width, height));
+            if p.transparent && p.background.is_some() {
+                warnings.push("new_image: background is ignored for a transparent image".to_string());
+            }
+            ValidatedParams::NewImage {
+                width,
+                height,
+                background: p.background.unwrap_or(Color::WHITE),
+                transparent: p.transparent,
+            }
+        }
+
+        Op::OpenImage => {
+            #[derive(Deserialize)]
+            struct P { path: String }
+            let p: P = serde_json::from_value(step.params.clone())
+                .map_err(|e| format!("open_image params invalid: {e}"))?;
+            let path = p.path.trim().to_string();
+            let ext = std::path::Path::new(&path)
+                .extension()
+                .and_then(|e| e.to_str())
+                .map(|e| e.to_ascii_lowercase())
+                .unwrap_or_default();
+            if !OPENABLE_EXTENSIONS.contains(&ext.as_str()) {
+                return Err(format!(
+                    "open_image params invalid: {path:?} is not one of {}",
+                    OPENABLE_EXTENSIONS.join(", ")
+                ));
+            }
+            ValidatedParams::OpenImage { path }
+        }
+
+        Op::CloseImage => {
+            #[derive(Deserialize)]
+            struct P { #[serde(default)] discard_changes: bool }
+            let p: P = serde_json::from_value(step.params.clone())
+                .map_err(|e| format!("close_image params invalid: {e}"))?;
+            ValidatedParams::CloseImage { discard_changes: p.discard_changes }
+        }
     };
 
     Ok(ValidatedStep {
@@ -97,5 +1504,391 @@ pub fn validate_step(step: &ActionStep) -> Result<ValidatedStep, String> {
         target: step.target.clone(),
         stop_on_error: step.stop_on_error,
         params: validated,
+        warnings,
+        placeholder_pixels: dims.guessed.get(),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn step(op: Op, params: Value) -> ActionStep {
+        ActionStep { op, params, target: Target::default(), stop_on_error: true }
+    }
+
+    #[test]
+    fn rotate_normalises_to_clockwise_and_rejects_no_op() {
+        let v = validate_step(&step(Op::Rotate, json!({ "degrees": -90 }))).unwrap();
+        assert!(matches!(v.params, ValidatedParams::Rotate { degrees, .. } if degrees == 270.0));
+
+        assert!(validate_step(&step(Op::Rotate, json!({ "degrees": 360 }))).is_err());
+        assert!(validate_step(&step(Op::Flip, json!({ "orientation": "diagonal" }))).is_err());
+    }
+
+    #[test]
+    fn shapes_default_to_image_centre_and_must_overlap_it() {
+        let image = ImageInfo { width: 800, height: 600, layers: vec![], dpi: 72.0 };
+
+        let v = validate_step_for_image(&step(Op::DrawShape, json!({ "kind": "circle" })), Some(&image)).unwrap();
+        assert!(matches!(
+            v.params,
+            ValidatedParams::DrawShape { x: 200, y: 100, width: 400, height: 400, fill: true, .. }
+        ));
+
+        let off_canvas = json!({ "kind": "rectangle", "bbox": { "x": 900, "y": 0, "width": 50, "height": 50 } });
+        assert!(validate_step_for_image(&step(Op::DrawShape, off_canvas.clone()), Some(&image)).is_err());
+        // Without the image only the params' shape is checked
+        assert!(validate_step(&step(Op::DrawShape, off_canvas)).is_ok());
+        let far_left = json!({ "kind": "circle", "center": { "x": -3e9, "y": 10 }, "size": 3e9 });
+        let e = validate_step_for_image(&step(Op::DrawShape, far_left), Some(&image)).unwrap_err();
+        assert!(e.contains("outside"), "{e}");
+        assert!(validate_step(&step(Op::DrawShape, json!({ "kind": "circle", "color": "red'); x('" }))).is_err());
+    }
+
+    #[test]
+    fn targets_are_checked_against_the_op_and_image() {
+        let image = ImageInfo { width: 800, height: 600, layers: vec!["Text".into(), "Background".into()], dpi: 72.0 };
+        let mut blur = step(Op::Blur, json!({ "radius": 4 }));
+
+        blur.target = serde_json::from_value(json!({ "layer": "Background" })).unwrap();
+        assert!(validate_step_for_image(&blur, Some(&image)).is_ok());
+        blur.target = serde_json::from_value(json!({ "layer": "Sky" })).unwrap();
+        assert!(validate_step_for_image(&blur, Some(&image)).is_err());
+        blur.target = serde_json::from_value(json!({ "layer_index": 2 })).unwrap();
+        assert!(validate_step_for_image(&blur, Some(&image)).is_err());
+
+        let mut crop = step(Op::CropSquare, json!({}));
+        crop.target = Target::Selection;
+        assert!(validate_step(&crop).is_err());
+
+        // Huge regions reach past the image without overflowing
+        blur.target = serde_json::from_value(json!({ "region": { "x": 100, "y": 100, "width": i32::MAX, "height": i32::MAX } })).unwrap();
+        assert!(validate_step_for_image(&blur, Some(&image)).is_ok());
+        blur.target = serde_json::from_value(json!({ "region": { "x": i32::MIN, "y": 0, "width": 10, "height": 10 } })).unwrap();
+        assert!(validate_step_for_image(&blur, Some(&image)).is_err());
+    }
+
+    #[test]
+    fn lengths_and_anchors_resolve_to_pixels() {
+        let image = ImageInfo { width: 4000, height: 3000, layers: vec![], dpi: 300.0 };
+
+        let line = json!({ "from": "top-left", "x1": 5 });
+        assert!(validate_step_for_image(&step(Op::DrawLine, line), Some(&image)).is_err());
+
+        let line = json!({ "x1": "10%", "y1": "1in", "x2": "right", "y2": "2.54cm", "color": "red" });
+        let v = validate_step_for_image(&step(Op::DrawLine, line), Some(&image)).unwrap();
+        assert!(matches!(v.params, ValidatedParams::DrawLine { x1: 400, y1: 300, x2: 4000, y2: 300, .. }));
+
+        let shape = json!({ "kind": "circle", "center": "bottom-right", "size": "10%" });
+        let v = validate_step_for_image(&step(Op::DrawShape, shape), Some(&image)).unwrap();
+        assert!(matches!(
+            v.params,
+            ValidatedParams::DrawShape { x: 3850, y: 2850, width: 300, height: 300, .. }
+        ));
+    }
+
+    #[test]
+    fn aspect_crops_fit_the_image_and_follow_gravity() {
+        let image = ImageInfo { width: 4000, height: 3000, layers: vec![], dpi: 72.0 };
+        let crop = |params: Value| validate_step_for_image(&step(Op::Crop, params), Some(&image));
+
+        let v = crop(json!({ "mode": "aspect", "aspect": "16:9" })).unwrap();
+        assert!(matches!(v.params, ValidatedParams::Crop { x: 0, y: 375, width: 4000, height: 2250 }));
+
+        let v = crop(json!({ "mode": "aspect", "aspect": "9:16", "gravity": "right" })).unwrap();
+        assert!(matches!(v.params, ValidatedParams::Crop { x: 2312, y: 0, width: 1688, height: 3000 }));
+
+        let v = crop(json!({ "mode": "aspect", "aspect": "16:9", "gravity": "face_safe" })).unwrap();
+        assert!(matches!(v.params, ValidatedParams::Crop { y: 250, .. }));
+
+        assert!(crop(json!({ "mode": "rect", "rect": { "x": "50%", "y": 0, "width": "60%", "height": 100 } })).is_err());
+        assert!(crop(json!({ "mode": "aspect" })).is_err());
+        assert!(crop(json!({ "mode": "aspect", "aspect": "1000000:1" })).is_err());
+        assert!(crop(json!({ "mode": "rect", "rect": { "x": 10, "y": 0, "width": 3e9, "height": 100 } })).is_err());
+
+        // An aspect crop can't be worked out without the image size
+        let v = validate_step(&step(Op::Crop, json!({ "mode": "aspect", "aspect": "16:9" }))).unwrap();
+        assert!(v.placeholder_pixels);
+    }
+
+    #[test]
+    fn resize_modes_compute_the_output_size() {
+        let image = ImageInfo { width: 4000, height: 3000, layers: vec![], dpi: 72.0 };
+        let resize = |params: Value| validate_step_for_image(&step(Op::Resize, params), Some(&image)).unwrap();
+
+        let v = resize(json!({ "mode": "fit", "width": 1080, "height": 1080 }));
+        assert!(matches!(v.params, ValidatedParams::Resize { width: 1080, height: 810, .. }));
+
+        let v = resize(json!({ "mode": "fill", "width": 1080, "height": 1080 }));
+        assert!(matches!(
+            v.params,
+            ValidatedParams::Resize { scale_width: 1440, scale_height: 1080, width: 1080, height: 1080, .. }
+        ));
+
+        let v = resize(json!({ "mode": "height", "height": 6000, "no_upscale": true }));
+        assert!(matches!(v.params, ValidatedParams::Resize { width: 4000, height: 3000, .. }));
+
+        let v = resize(json!({ "mode": "percent", "percent": 300 }));
+        assert_eq!(v.warnings.len(), 1);
+    }
+
+    #[test]
+    fn add_text_anchors_and_fonts() {
+        let image = ImageInfo { width: 1000, height: 500, layers: vec![], dpi: 72.0 };
+        let params = json!({ "text": "Hello", "anchor": "bottom", "size": "24pt", "color": "white" });
+        let v = validate_step_for_image(&step(Op::AddText, params), Some(&image)).unwrap();
+        match v.params {
+            ValidatedParams::AddText { size, x, y, anchor_x, anchor_y, ref font, .. } => {
+                assert_eq!((size, x, y), (24, 500, 500));
+                assert_eq!((anchor_x, anchor_y), (0.5, 1.0));
+                assert_eq!(font, DEFAULT_FONT);
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+
+        let fonts: Vec<String> = ["DejaVu Sans Bold", "DejaVu Sans", "Serif"].map(String::from).to_vec();
+        assert_eq!(match_font("dejavu sans", &fonts).unwrap(), "DejaVu Sans");
+        assert_eq!(match_font("DejaVu Sans B", &fonts).unwrap(), "DejaVu Sans Bold");
+        assert!(match_font("DejaVu Mono", &fonts).unwrap_err().contains("similar: DejaVu Sans Bold"));
+        assert!(validate_step(&ActionStep { target: Target::AllLayers, ..step(Op::AddText, json!({ "text": "x" })) }).is_err());
+    }
+
+    #[test]
+    fn layer_ops_check_the_layer_list() {
+        let image = ImageInfo {
+            width: 100,
+            height: 100,
+            layers: vec!["Text".to_string(), "Background".to_string()],
+            dpi: 72.0,
+        };
+        let on = |op: Op, target: Target, params: Value| {
+            validate_step_for_image(&ActionStep { target, ..step(op, params) }, Some(&image))
+        };
+
+        let merge = on(Op::MergeDown, Target::Layer("Background".to_string()), json!({}));
+        assert!(merge.unwrap_err().contains("bottom layer"));
+        assert!(on(Op::MergeDown, Target::LayerIndex(0), json!({})).is_ok());
+        assert!(on(Op::DeleteLayer, Target::Image, json!({})).is_err());
+        assert!(on(Op::MoveLayer, Target::ActiveLayer, json!({ "to": { "index": 2 } })).is_err());
+        assert!(on(Op::MoveLayer, Target::ActiveLayer, json!({ "to": "top" })).is_ok());
+
+        let rename = on(Op::RenameLayer, Target::LayerIndex(0), json!({ "name": "Background" })).unwrap();
+        assert_eq!(rename.warnings.len(), 1);
+        let opacity = on(Op::SetLayerOpacity, Target::AllLayers, json!({ "opacity": 150 })).unwrap();
+        assert!(matches!(opacity.params, ValidatedParams::SetLayerOpacity { opacity } if opacity == 100.0));
+        assert!(on(Op::DuplicateLayer, Target::AllLayers, json!({})).is_err());
+    }
+
+    #[test]
+    fn selection_ops_resolve_geometry() {
+        let image = ImageInfo { width: 400, height: 200, layers: vec![], dpi: 72.0 };
+        let select = |op: Op, params: Value| validate_step_for_image(&step(op, params), Some(&image));
+
+        let params = json!({ "shape": "ellipse", "rect": { "x": "25%", "y": 0, "width": "50%", "height": "100%" }, "mode": "add" });
+        assert!(matches!(
+            select(Op::SelectShape, params).unwrap().params,
+            ValidatedParams::SelectEllipse { x: 100, y: 0, width: 200, height: 200, mode: SelectionMode::Add }
+        ));
+
+        let params = json!({ "shape": "polygon", "points": ["top-left", "top-right", { "x": "50%", "y": "bottom" }] });
+        match select(Op::SelectShape, params).unwrap().params {
+            ValidatedParams::SelectPolygon { points, mode } => {
+                assert_eq!(points, vec![(0, 0), (400, 0), (200, 200)]);
+                assert_eq!(mode, SelectionMode::Replace);
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+        assert!(select(Op::SelectShape, json!({ "shape": "polygon", "points": ["top", "bottom"] })).is_err());
+        assert!(select(Op::SelectShape, json!({ "shape": "rect" })).is_err());
+
+        let grow = select(Op::ModifySelection, json!({ "action": "grow", "amount": "5%" })).unwrap();
+        assert!(matches!(grow.params, ValidatedParams::GrowSelection { pixels: 10 }));
+        let color = select(Op::SelectColor, json!({ "color": "sky blue", "threshold": 400 })).unwrap();
+        assert!(matches!(color.params, ValidatedParams::SelectColor { threshold: 255, .. }));
+        assert!(validate_step(&ActionStep { target: Target::Selection, ..step(Op::SelectAll, json!({})) }).is_err());
+    }
+
+    #[test]
+    fn fill_and_gradient_params() {
+        let image = ImageInfo { width: 300, height: 100, layers: vec![], dpi: 72.0 };
+        let run = |op: Op, params: Value| validate_step_for_image(&step(op, params), Some(&image));
+
+        let fill = run(Op::Fill, json!({ "color": "gold" })).unwrap();
+        assert!(matches!(fill.params, ValidatedParams::Fill { fill: FillKind::Foreground, color: Some(_), .. }));
+        assert!(run(Op::Fill, json!({ "fill": "white", "color": "gold" })).is_err());
+        assert!(run(Op::Fill, json!({ "fill": "pattern", "pattern": "Pine" })).is_ok());
+
+        let params = json!({ "kind": "radial", "colors": ["orange", "#ff1493", "rgb(75, 0, 130)"], "from": "center", "to": "right" });
+        match run(Op::Gradient, params).unwrap().params {
+            ValidatedParams::Gradient { kind, colors, from, to } => {
+                assert_eq!(kind, GradientKind::Radial);
+                assert_eq!(colors.len(), 3);
+                assert_eq!((from, to), (Some((150, 50)), Some((300, 50))));
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+        assert!(run(Op::Gradient, json!({ "colors": ["red"] })).is_err());
+        assert!(run(Op::Gradient, json!({ "colors": ["red", "blue"], "from": "top" })).is_err());
+    }
+
+    #[test]
+    fn gegl_filter_only_runs_registered_operations() {
+        let params = json!({ "operation": "gegl:pixelize", "properties": { "size-x": 12, "size-y": 12 } });
+        match validate_step(&step(Op::GeglFilter, params)).unwrap().params {
+            ValidatedParams::GeglFilter { operation, properties } => {
+                assert_eq!(operation, "gegl:pixelize");
+                assert_eq!(properties["size-y"], FilterValue::Int(12));
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+        let unknown = validate_step(&step(Op::GeglFilter, json!({ "operation": "gegl:load", "properties": { "path": "/etc/passwd" } })));
+        assert!(unknown.unwrap_err().contains("not an allowed filter"));
+        let out_of_range = json!({ "operation": "sharpen", "properties": { "scale": -1 } });
+        assert!(validate_step(&step(Op::GeglFilter, out_of_range)).is_err());
+    }
+
+    #[test]
+    fn tonal_corrections() {
+        let v = validate_step(&step(Op::Levels, json!({ "channel": "red", "input_black": 20, "gamma": 40 }))).unwrap();
+        assert!(matches!(
+            v.params,
+            ValidatedParams::Levels { channel: Channel::Red, input_black: 20.0, input_white: 255.0, gamma: 10.0, .. }
+        ));
+        assert!(validate_step(&step(Op::Levels, json!({ "input_black": 200, "input_white": 100 }))).is_err());
+
+        let s_curve = json!({ "points": [[0, 0], [64, 50], [192, 205], [255, 255]] });
+        assert!(validate_step(&step(Op::Curves, s_curve)).is_ok());
+        assert!(validate_step(&step(Op::Curves, json!({ "points": [[0, 0], [0, 255]] }))).is_err());
+        assert!(validate_step(&step(Op::Curves, json!({ "points": [[0, 0], [300, 255]] }))).is_err());
+
+        let v = validate_step(&step(Op::ColorBalance, json!({ "range": "highlights", "yellow_blue": -150 }))).unwrap();
+        assert!(matches!(
+            v.params,
+            ValidatedParams::ColorBalance { range: ToneRange::Highlights, yellow_blue: -100.0, preserve_luminosity: true, .. }
+        ));
+        let v = validate_step(&step(Op::ColorTemperature, json!({ "intended": 8000 }))).unwrap();
+        assert!(matches!(v.params, ValidatedParams::ColorTemperature { original: 6500.0, intended: 8000.0 }));
+    }
+
+    #[test]
+    fn pad_and_border_grow_the_canvas() {
+        let image = ImageInfo { width: 1200, height: 800, layers: vec![], dpi: 72.0 };
+        let run = |op: Op, params: Value| validate_step_for_image(&step(op, params), Some(&image));
+
+        let square = run(Op::Pad, json!({ "aspect": "1:1" })).unwrap();
+        assert!(matches!(
+            square.params,
+            ValidatedParams::Pad { left: 0, top: 200, right: 0, bottom: 200, width: 1200, height: 1200, .. }
+        ));
+
+        let margins = run(Op::Pad, json!({ "all": 20, "bottom": "10%", "color": "transparent" })).unwrap();
+        match margins.params {
+            ValidatedParams::Pad { left, top, right, bottom, color, .. } => {
+                assert_eq!((left, top, right, bottom), (20, 20, 20, 80));
+                assert_eq!(color.a, 0);
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+
+        let border = run(Op::Border, json!({ "width": "5%", "color": "black" })).unwrap();
+        assert!(matches!(border.params, ValidatedParams::Pad { left: 40, width: 1280, height: 880, .. }));
+        assert!(run(Op::Pad, json!({})).is_err());
+        assert!(run(Op::Pad, json!({ "left": -10 })).is_err());
+        assert!(run(Op::Pad, json!({ "aspect": "4:5", "top": 10 })).is_err());
+        assert!(run(Op::Pad, json!({ "all": 1e10 })).is_err());
+        assert_eq!(run(Op::Pad, json!({ "aspect": "3:2" })).unwrap().warnings.len(), 1);
+    }
+
+    #[test]
+    fn export_params_pick_format_and_file_name() {
+        let v = validate_step(&step(Op::Export, json!({ "file_name": "Holiday Card.JPG", "quality": 120 }))).unwrap();
+        assert!(matches!(
+            v.params,
+            ValidatedParams::Export { format: ExportFormat::Jpeg, quality: 100, ref file_name, path: None, .. }
+                if file_name == "Holiday Card"
+        ));
+        let v = validate_step(&step(Op::Export, json!({}))).unwrap();
+        assert!(matches!(v.params, ValidatedParams::Export { format: ExportFormat::Png, compression: 6, .. }));
+
+        assert!(validate_step(&step(Op::Export, json!({ "file_name": "a.png", "format": "webp" }))).is_err());
+        assert!(validate_step(&step(Op::Export, json!({ "file_name": "../../.ssh/key" }))).is_err());
+        assert!(validate_step(&step(Op::SaveXcf, json!({ "file_name": "/tmp/x.xcf" }))).is_err());
+    }
+
+    #[test]
+    fn image_relative_pixels_without_the_image_are_placeholders() {
+        let line = |params: Value| validate_step(&step(Op::DrawLine, params)).unwrap().placeholder_pixels;
+        assert!(!line(json!({ "x1": 0, "y1": 0, "x2": "120px", "y2": 40 })));
+        assert!(line(json!({ "x1": 0, "y1": 0, "x2": "50%", "y2": 40 })));
+        assert!(line(json!({ "x1": 0, "y1": 0, "x2": "2cm", "y2": 40 })));
+        assert!(line(json!({ "from": "top-left", "to": [10, 10] })));
+        // Defaults taken from the image size count too
+        assert!(validate_step(&step(Op::AddText, json!({ "text": "Hi" }))).unwrap().placeholder_pixels);
+        assert!(!validate_step(&step(Op::Invert, json!({}))).unwrap().placeholder_pixels);
+
+        let image = ImageInfo { width: 800, height: 600, layers: vec![], dpi: 72.0 };
+        let v = validate_step_for_image(&step(Op::DrawLine, json!({ "from": "center", "to": "2cm" })), Some(&image));
+        assert!(v.is_err(), "\"2cm\" is not a point");
+        let v = validate_step_for_image(&step(Op::DrawLine, json!({ "from": "center", "to": ["2cm", 0] })), Some(&image));
+        assert!(!v.unwrap().placeholder_pixels);
+    }
+
+    #[test]
+    fn image_lifecycle_params_are_checked() {
+        let v = validate_step(&step(Op::NewImage, json!({}))).unwrap();
+        assert!(matches!(
+            v.params,
+            ValidatedParams::NewImage { width: 1920, height: 1080, transparent: false, background }
+                if background == Color::WHITE
+        ));
+        let v = validate_step(&step(Op::NewImage, json!({ "width": 800, "height": 800, "background": "navy", "transparent": true }))).unwrap();
+        assert_eq!(v.warnings.len(), 1);
+        assert!(validate_step(&step(Op::NewImage, json!({ "width": 0 }))).is_err());
+        assert!(validate_step(&step(Op::NewImage, json!({ "width": 40000 }))).is_err());
+
+        assert!(validate_step(&step(Op::OpenImage, json!({ "path": "shots/Cat.JPG" }))).is_ok());
+        assert!(validate_step(&step(Op::OpenImage, json!({ "path": "notes.txt" }))).is_err());
+        assert!(validate_step(&step(Op::OpenImage, json!({}))).is_err());
+        let v = validate_step(&step(Op::CloseImage, json!({}))).unwrap();
+        assert!(matches!(v.params, ValidatedParams::CloseImage { discard_changes: false }));
+        let layer = ActionStep { target: Target::Layer("Text".to_string()), ..step(Op::CloseImage, json!({})) };
+        assert!(validate_step(&layer).is_err());
+    }
+
+    #[test]
+    fn color_adjustments_are_clamped_and_checked() {
+        let v = validate_step(&step(Op::HueSaturation, json!({ "range": "red", "saturation": 250 }))).unwrap();
+        assert!(matches!(
+            v.params,
+            ValidatedParams::HueSaturation { range: HueRange::Red, saturation, hue, .. } if saturation == 100.0 && hue == 0.0
+        ));
+
+        assert!(validate_step(&step(Op::Threshold, json!({ "low": 200, "high": 100 }))).is_err());
+        let v = validate_step(&step(Op::Posterize, json!({ "levels": 1 }))).unwrap();
+        assert!(matches!(v.params, ValidatedParams::Posterize { levels: 2 }));
+    }
+
+    #[test]
+    fn huge_lengths_are_checked_without_overflowing() {
+        let image = ImageInfo { width: 800, height: 600, layers: vec![], dpi: 72.0 };
+        let run = |op: Op, params: Value| validate_step_for_image(&step(op, params), Some(&image));
+        let max = i32::MAX;
+
+        let rect = json!({ "x": 10, "y": 10, "width": max, "height": max });
+        assert!(run(Op::Crop, json!({ "mode": "rect", "rect": rect })).is_err());
+        assert!(run(Op::Pad, json!({ "all": max })).is_err());
+        assert!(run(Op::Pad, json!({ "left": max, "right": max })).is_err());
+
+        // Shapes and selections only have to overlap the image, which these do
+        let v = run(Op::DrawShape, json!({ "kind": "rectangle", "bbox": rect })).unwrap();
+        assert!(matches!(v.params, ValidatedParams::DrawShape { x: 10, width: i32::MAX, .. }));
+        assert!(run(Op::DrawShape, json!({ "kind": "rectangle", "center": [max, max], "size": max })).is_err());
+        for shape in ["rect", "ellipse"] {
+            assert!(run(Op::SelectShape, json!({ "shape": shape, "rect": rect })).is_ok());
+            let off = json!({ "x": -max, "y": 0, "width": 10, "height": 10 });
+            assert!(run(Op::SelectShape, json!({ "shape": shape, "rect": off })).is_err());
+        }
+    }
+}
@@ -0,0 +1,249 @@
+use serde_json::{json, Value};
+
+use crate::llm_client::{self, ChatMessage, Role, ToolCall, ToolDefinition};
+use crate::plan_schema::{ActionPlan, ActionStep, MissingImage, Op, Target};
+
+/// Upper bound on model ↔ tool round trips for one request.
+const MAX_TURNS: usize = 6;
+
+/// Tool results longer than this are cut before being fed back to the model.
+const MAX_RESULT_CHARS: usize = 4000;
+
+const SYSTEM_PROMPT: &str = "You are a GIMP assistant. Use the provided tools to inspect or edit the \
+image the user has open in GIMP. Call get_image_metadata first if you need the image size. \
+When you are done, reply to the user in one or two plain sentences. If no tool is needed, just answer.";
+
+/// Added to the system prompt when `call_api` is offered.
+const CALL_API_HINT: &str = " Prefer the typed editing tools (draw_line, blur, brightness_contrast, …); \
+only use call_api when no typed tool fits.";
+
+/// MCP tools offered next to the plan ops. They only read, so they need no
+/// validation; `call_api` is added when the `allow_call_api` setting is on.
+const READ_ONLY_MCP_TOOLS: &[&str] = &["get_gimp_info", "get_image_metadata"];
+
+/// Outcome of a tool-calling conversation, shaped for `assistant_request`.
+pub struct ToolRun {
+    pub reply: String,
+    /// Every call the model made, in order.
+    pub tool_calls: Vec<ToolCall>,
+    /// `{ tool, arguments, result }` per call, same shape as the legacy STEP 3.
+    pub tool_results: Vec<Value>,
+    /// True if at least one editing call succeeded.
+    pub edited: bool,
+}
+
+/// Whether the model may call this MCP tool.
+fn mcp_tool_allowed(name: &str) -> bool {
+    READ_ONLY_MCP_TOOLS.contains(&name) || (name == "call_api" && llm_client::settings().allow_call_api)
+}
+
+/// Tool definitions offered to the model: the typed plan ops plus the allowed
+/// tools from the MCP catalog.
+pub fn tool_definitions() -> Vec<ToolDefinition> {
+    let mut defs: Vec<ToolDefinition> = Op::ALL
+        .iter()
+        .map(|op| {
+            let mut parameters = op.params_schema();
+            parameters["properties"]["target"] = Target::schema();
+            ToolDefinition {
+                name: op.name(),
+                description: op.description().to_string(),
+                parameters,
+            }
+        })
+        .collect();
+
+    match crate::mcp::list_tools() {
+        Ok(list) => {
+            let tools = list.get("tools").and_then(|t| t.as_array()).cloned().unwrap_or_default();
+            for tool in tools {
+                let Some(name) = tool.get("name").and_then(|n| n.as_str()) else { continue };
+                if !mcp_tool_allowed(name) || defs.iter().any(|d| d.name == name) {
+                    continue;
+                }
+                defs.push(ToolDefinition {
+                    name: name.to_string(),
+                    description: tool
+                        .get("description")
+                        .and_then(|d| d.as_str())
+                        .unwrap_or("")
+                        .to_string(),
+                    parameters: tool
+                        .get("inputSchema")
+                        .cloned()
+                        .unwrap_or_else(|| json!({ "type": "object", "properties": {} })),
+                });
+            }
+        }
+        Err(e) => eprintln!("[TOOLS] MCP tool catalog unavailable: {e}"),
+    }
+
+    defs
+}
+
+/// Run the request as a multi-turn tool-calling conversation until the model
+/// stops calling tools (or `MAX_TURNS` is reached). Fails only if the first
+/// turn does, before anything ran; a later failure ends the run with what was done.
+pub async fn run(prompt: &str) -> Result<ToolRun, String> {
+    let defs = tool_definitions();
+    let mut system = SYSTEM_PROMPT.to_string();
+    if defs.iter().any(|d| d.name == "call_api") {
+        system.push_str(CALL_API_HINT);
+    }
+
+    let mut messages = vec![
+        ChatMessage::new("system", &system),
+        ChatMessage::new("user", prompt),
+    ];
+    let mut run = ToolRun {
+        reply: String::new(),
+        tool_calls: Vec::new(),
+        tool_results: Vec::new(),
+        edited: false,
+    };
+
+    for _ in 0..MAX_TURNS {
+        let resp = match llm_client::chat_with_tools(Role::Planner, messages.clone(), &defs).await {
+            Ok(resp) => resp,
+            Err(e) if run.tool_calls.is_empty() => return Err(e),
+            Err(e) => {
+                run.reply = format!("I stopped partway because the model failed: {e}");
+                return Ok(run);
+            }
+        };
+
+        if resp.tool_calls.is_empty() {
+            run.reply = resp.content.trim().to_string();
+            return Ok(run);
+        }
+
+        messages.push(resp.to_message());
+
+        for call in &resp.tool_calls {
+            let (result, edited) = execute_tool_call(call);
+            run.edited |= edited;
+
+            messages.push(ChatMessage::tool_result(call, result_for_model(&result)));
+            run.tool_results.push(json!({
+                "tool": call.name,
+                "arguments": call.arguments,
+                "result": result
+            }));
+            run.tool_calls.push(call.clone());
+        }
+    }
+
+    run.reply = format!("I stopped after {MAX_TURNS} rounds of tool calls without finishing.");
+    Ok(run)
+}
+
+/// Execute one tool call. Plan ops go through the validated plan pipeline,
+/// anything else is forwarded to MCP. Returns the result and whether it edited the image.
+fn execute_tool_call(call: &ToolCall) -> (Value, bool) {
+    let mut arguments = if call.arguments.is_null() { json!({}) } else { call.arguments.clone() };
+
+    if let Some(op) = Op::from_name(&call.name) {
+        // Plan ops take `target` next to their params in tool calls
+        let target = match arguments.as_object_mut().and_then(|args| args.remove("target")) {
+            Some(t) => match serde_json::from_value(t) {
+                Ok(target) => target,
+                Err(e) => return (json!({ "ok": false, "error": format!("invalid target: {e}") }), false),
+            },
+            None => Target::default(),
+        };
+        let plan = ActionPlan {
+            summary: None,
+            steps: vec![ActionStep {
+                op,
+                params: arguments,
+                target,
+                stop_on_error: true,
+            }],
+            generation: None,
+            missing_image: MissingImage::default(),
+        };
+        return match crate::plan_execute::execute_plan(plan) {
+            Ok(results) => {
+                let ok = results.iter().all(|r| r.ok);
+                (json!({ "ok": ok, "steps": results }), ok)
+            }
+            Err(e) => (json!({ "ok": false, "error": e }), false),
+        };
+    }
+
+    if !mcp_tool_allowed(&call.name) {
+        return (json!({ "ok": false, "error": format!("unknown tool {:?}", call.name) }), false);
+    }
+    match crate::plan_execute::call_tool_recorded(&call.name, arguments) {
+        Ok(result) => {
+            let is_error = result.get("isError").and_then(|e| e.as_bool()).unwrap_or(false);
+            let edited = call.name == "call_api" && !is_error;
+            (result, edited)
+        }
+        Err(err) => (
+            json!({
+                "isError": true,
+                "content": [
+                    { "text": format!("MCP transport error: {err}"), "type": "text" }
+                ]
+            }),
+            false,
+        ),
+    }
+}
+
+/// Compact text form of a tool result for the next model turn.
+fn result_for_model(result: &Value) -> String {
+    let text = result
+        .get("content")
+        .and_then(|c| c.as_array())
+        .and_then(|arr| arr.first())
+        .and_then(|first| first.get("text"))
+        .and_then(|t| t.as_str())
+        .map(|t| t.to_string())
+        .unwrap_or_else(|| result.to_string());
+
+    if text.chars().count() > MAX_RESULT_CHARS {
+        let cut: String = text.chars().take(MAX_RESULT_CHARS).collect();
+        format!("{cut}… (truncated)")
+    } else {
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_llm::MockLlm;
+    use crate::mock_mcp::MockMcp;
+
+    fn offered() -> Vec<String> {
+        tool_definitions().into_iter().map(|d| d.name).collect()
+    }
+
+    #[test]
+    fn only_read_only_mcp_tools_are_offered_unless_call_api_is_allowed() {
+        // The LLM mock holds the settings lock and restores them afterwards
+        let _llm = MockLlm::start();
+        let mcp = MockMcp::start();
+        mcp.with_tools(&["get_gimp_info", "get_image_metadata", "get_image_bitmap", "call_api"]);
+
+        let names = offered();
+        assert!(names.contains(&"blur".to_string()));
+        assert!(names.contains(&"get_image_metadata".to_string()));
+        assert!(!names.contains(&"get_image_bitmap".to_string()));
+        assert!(!names.contains(&"call_api".to_string()));
+
+        // A call to a tool that wasn't offered never reaches GIMP
+        let call = ToolCall { id: None, name: "call_api".to_string(), arguments: json!({ "api_path": "exec" }) };
+        let (result, edited) = execute_tool_call(&call);
+        assert_eq!(result["ok"], false);
+        assert!(!edited);
+        assert!(mcp.calls().is_empty());
+
+        let mut settings = llm_client::settings();
+        settings.allow_call_api = true;
+        llm_client::set_settings(settings).unwrap();
+        assert!(offered().contains(&"call_api".to_string()));
+    }
+}
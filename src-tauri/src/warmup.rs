@@ -0,0 +1,189 @@
+use serde::Serialize;
+use std::process::{Child, Command};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Manager};
+
+use crate::llm_client;
+
+/// Event the UI listens to for model loading progress.
+pub const WARMUP_EVENT: &str = "llm-warmup";
+
+/// How often the idle watcher checks whether to re-warm.
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long to wait for a freshly spawned `ollama serve` to answer.
+const SERVE_STARTUP_TIMEOUT: Duration = Duration::from_secs(20);
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WarmupStatus {
+    Checking,
+    StartingServer,
+    Loading,
+    Ready,
+    Error,
+}
+
+/// The `ollama serve` the app started, kept in app state so it can be
+/// stopped when the app exits.
+#[derive(Default)]
+pub struct OllamaServer(Mutex<Option<Child>>);
+
+impl OllamaServer {
+    fn set(&self, child: Child) {
+        if let Ok(mut server) = self.0.lock() {
+            if let Some(old) = server.replace(child) {
+                stop_child(old);
+            }
+        }
+    }
+
+    /// The server's exit status if it has stopped; it is forgotten then.
+    fn exited(&self) -> Option<std::process::ExitStatus> {
+        let mut server = self.0.lock().ok()?;
+        let status = server.as_mut()?.try_wait().ok()??;
+        *server = None;
+        Some(status)
+    }
+
+    /// Kill the server if the app started it.
+    pub fn stop(&self) {
+        if let Some(child) = self.0.lock().ok().and_then(|mut server| server.take()) {
+            stop_child(child);
+        }
+    }
+}
+
+fn stop_child(mut child: Child) {
+    if let Err(e) = child.kill() {
+        eprintln!("[WARMUP] Failed to stop `ollama serve`: {e}");
+    }
+    // Reap it so it doesn't linger as a zombie
+    let _ = child.wait();
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WarmupEvent {
+    pub status: WarmupStatus,
+    pub model: String,
+    pub message: String,
+    pub elapsed_ms: u128,
+}
+
+fn emit(app: &AppHandle, status: WarmupStatus, message: impl Into<String>, started: Instant) {
+    let event = WarmupEvent {
+        status,
+        model: llm_client::planner_model(),
+        message: message.into(),
+        elapsed_ms: started.elapsed().as_millis(),
+    };
+    if let Err(e) = app.emit(WARMUP_EVENT, event) {
+        eprintln!("[WARMUP] Failed to emit event: {e}");
+    }
+}
+
+/// Warm up the planner model at startup, then keep it warm while the app runs.
+pub async fn run(app: AppHandle) {
+    let mut warmed = false;
+    // Model activity as of the last failed warm-up. Retrying every round would
+    // keep flagging an error (and spawning `ollama serve`), so wait until a
+    // chat gets through instead.
+    let mut failed_at = None;
+    if llm_client::settings().warmup.enabled {
+        warmed = warm_once(&app).await;
+        if !warmed {
+            failed_at = Some(llm_client::last_activity());
+        }
+    }
+
+    loop {
+        tokio::time::sleep(IDLE_CHECK_INTERVAL).await;
+        // Re-read settings each round so changes from the UI apply without a restart
+        let settings = llm_client::settings().warmup;
+        if !settings.enabled {
+            continue;
+        }
+        if let Some(activity) = failed_at {
+            if llm_client::last_activity() == activity {
+                continue;
+            }
+            // A chat reached the model since, so it's up and loaded
+            failed_at = None;
+            warmed = true;
+        }
+        if !warmed {
+            eprintln!("[WARMUP] Warm-up was turned on, warming");
+        } else if settings.rewarm_after_idle_secs > 0 && llm_client::idle_secs() >= settings.rewarm_after_idle_secs {
+            eprintln!("[WARMUP] Model idle for {}s, re-warming", llm_client::idle_secs());
+        } else {
+            continue;
+        }
+        if warm_once(&app).await {
+            warmed = true;
+        } else {
+            failed_at = Some(llm_client::last_activity());
+        }
+    }
+}
+
+/// One warm-up pass: make sure the server is up (spawning it if allowed), then
+/// load the model. Returns whether the model is loaded.
+pub async fn warm_once(app: &AppHandle) -> bool {
+    let started = Instant::now();
+    let settings = llm_client::settings().warmup;
+
+    emit(app, WarmupStatus::Checking, "Checking for the local LLM server…", started);
+
+    if !llm_client::server_reachable().await {
+        if !settings.spawn_ollama {
+            emit(app, WarmupStatus::Error, "Ollama isn't running. Start it with `ollama serve`.", started);
+            return false;
+        }
+
+        emit(app, WarmupStatus::StartingServer, "Starting `ollama serve`…", started);
+        if let Err(e) = start_ollama_serve(app).await {
+            emit(app, WarmupStatus::Error, e, started);
+            return false;
+        }
+    }
+
+    emit(app, WarmupStatus::Loading, "Loading the model into memory…", started);
+    match llm_client::warm_up(settings.keep_alive).await {
+        Ok(()) => {
+            emit(app, WarmupStatus::Ready, "Model loaded", started);
+            true
+        }
+        Err(e) => {
+            emit(app, WarmupStatus::Error, format!("Model warm-up failed: {e}"), started);
+            false
+        }
+    }
+}
+
+/// Spawn `ollama serve`, keep it in the app's `OllamaServer` state and wait
+/// until it answers.
+async fn start_ollama_serve(app: &AppHandle) -> Result<(), String> {
+    let child = Command::new("ollama")
+        .arg("serve")
+        .spawn()
+        .map_err(|e| format!("Failed to start `ollama serve`: {e}"))?;
+    let server = app.state::<OllamaServer>();
+    server.set(child);
+
+    let started = Instant::now();
+    while started.elapsed() < SERVE_STARTUP_TIMEOUT {
+        if llm_client::server_reachable().await {
+            return Ok(());
+        }
+        // e.g. the port is taken; reap it rather than wait out the timeout
+        if let Some(status) = server.exited() {
+            return Err(format!("`ollama serve` exited: {status}"));
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+    Err(format!(
+        "`ollama serve` did not answer within {}s",
+        SERVE_STARTUP_TIMEOUT.as_secs()
+    ))
+}